@@ -0,0 +1,308 @@
+//! Our daily loop is "build PRG, launch, watch", so autostart does the rest of
+//! the work after launching. First the machine is booted, either by letting the
+//! KERNAL run until it waits for input at the READY prompt, or by a fast path
+//! that skips the boot altogether and only sets up the bare minimum.
+//!
+//! Then the PRG is loaded the way `LOAD "",8,1` would, and started by either
+//! typing `RUN` into the keyboard buffer, or by jumping to a start address.
+//!
+//! When booted, the jump is made by typing `SYS` instead, so the program can
+//! return to BASIC with a RTS. With the fast path there is nothing to return to,
+//! and BASIC isn't set up to be able to RUN anything either.
+
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+use super::basic::BASIC_START;
+use super::prg::LoadError;
+use super::{Block, C64};
+
+// Where the KERNAL waits for a key to be pressed, once READY. is printed
+const READY_LOOP: RangeInclusive<u16> = 0xe5cd..=0xe5d4;
+
+// A real C64 is ready after about 2.5 million cycles
+const MAX_BOOT_CYCLES: u64 = 10_000_000;
+
+const KEYBOARD_BUFFER: u16 = 0x0277;
+const KEYBOARD_BUFFER_SIZE: usize = 10;
+const KEYBOARD_COUNT: u16 = 0x00c6;
+
+// The pointer to the start of the BASIC program
+const TXTTAB: u16 = 0x002b;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boot {
+    /// Reset the machine and let the KERNAL run until READY
+    Kernal,
+    /// Skip the boot, with neither the KERNAL nor BASIC initialised
+    Fast,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Start {
+    /// Type RUN, just like a user would
+    Run,
+    /// Start at the address of the SYS line at the start of the program
+    Sys,
+    /// Start at the given address
+    Address(u16),
+}
+
+#[derive(Debug)]
+pub enum AutostartError {
+    Load(LoadError),
+    /// Booting requires the KERNAL ROM
+    MissingKernal,
+    /// Booting requires the BASIC ROM, as the KERNAL starts BASIC once done
+    MissingBasic,
+    /// The KERNAL never reached the READY prompt
+    NotReady,
+    /// The program doesn't start with a SYS line
+    NoSysLine,
+    /// RUN requires BASIC, which the fast path doesn't initialise
+    RunWithoutBoot,
+}
+
+impl Display for AutostartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AutostartError::Load(err) => write!(f, "{err}"),
+            AutostartError::MissingKernal => write!(f, "booting requires the KERNAL ROM"),
+            AutostartError::MissingBasic => write!(f, "booting requires the BASIC ROM"),
+            AutostartError::NotReady => {
+                write!(
+                    f,
+                    "the KERNAL didn't reach READY within {MAX_BOOT_CYCLES} cycles"
+                )
+            }
+            AutostartError::NoSysLine => write!(f, "the program doesn't start with a SYS line"),
+            AutostartError::RunWithoutBoot => write!(f, "RUN requires booting the KERNAL"),
+        }
+    }
+}
+
+impl std::error::Error for AutostartError {}
+
+impl From<LoadError> for AutostartError {
+    fn from(err: LoadError) -> Self {
+        AutostartError::Load(err)
+    }
+}
+
+impl C64 {
+    pub fn autostart(
+        &mut self,
+        prg: &[u8],
+        boot: Boot,
+        start: Start,
+    ) -> Result<(), AutostartError> {
+        // Everything that can be known up front is checked before the machine
+        // is touched, so an error leaves it as it was
+        if boot == Boot::Kernal {
+            self.check_roms()?;
+        }
+        let block = Block::from_prg(prg)?;
+        block.check_outside_io()?;
+        let address = match start {
+            Start::Run if boot == Boot::Fast => return Err(AutostartError::RunWithoutBoot),
+            Start::Run => None,
+            Start::Sys => Some(block.sys_address().ok_or(AutostartError::NoSysLine)?),
+            Start::Address(address) => Some(address),
+        };
+
+        match boot {
+            Boot::Kernal => self.boot()?,
+            Boot::Fast => self.fast_boot(),
+        }
+        self.load_prg(prg, true)?;
+
+        match (boot, address) {
+            (_, None) => self.type_text("RUN\r"),
+            (Boot::Kernal, Some(address)) => self.type_text(&format!("SYS{address}\r")),
+            (Boot::Fast, Some(address)) => self.cpu.jump(address),
+        }
+        Ok(())
+    }
+
+    /// Resets the machine and runs until the KERNAL waits for input at READY.
+    pub fn boot(&mut self) -> Result<(), AutostartError> {
+        self.check_roms()?;
+
+        self.reset();
+        let start = self.cpu.cycles();
+
+        // Take the reset sequence first, as the PC starts at the reset vector
+        self.step();
+        while !READY_LOOP.contains(&self.cpu.PC) {
+            if self.cpu.cycles() - start > MAX_BOOT_CYCLES {
+                return Err(AutostartError::NotReady);
+            }
+            self.step();
+        }
+        Ok(())
+    }
+
+    // Booting needs the KERNAL, and BASIC for the KERNAL to start once done
+    fn check_roms(&self) -> Result<(), AutostartError> {
+        let roms = self.cpu.bus().roms();
+        if roms.kernal.is_none() {
+            return Err(AutostartError::MissingKernal);
+        }
+        if roms.basic.is_none() {
+            return Err(AutostartError::MissingBasic);
+        }
+        Ok(())
+    }
+
+    // Sets up the memory configuration and stack the way the KERNAL leaves
+    // them, without running any of it.
+    fn fast_boot(&mut self) {
+        self.poke(0x0000, 0x2f);
+        self.poke(0x0001, 0x37);
+        let [lo, hi] = BASIC_START.to_le_bytes();
+        self.poke(TXTTAB, lo);
+        self.poke(TXTTAB + 1, hi);
+
+        self.cpu.SP = 0xff;
+        self.cpu.clear_flag(super::cpu::StatusFlags::I);
+    }
+
+    /// Puts the text into the keyboard buffer, as if it was typed. The buffer
+    /// only holds 10 characters, so anything after that is dropped.
+    pub fn type_text(&mut self, text: &str) {
+        let bytes: Vec<u8> = text.bytes().take(KEYBOARD_BUFFER_SIZE).collect();
+
+        for (offset, byte) in bytes.iter().enumerate() {
+            self.poke(KEYBOARD_BUFFER + offset as u16, *byte);
+        }
+        self.poke(KEYBOARD_COUNT, bytes.len() as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c64::roms::Roms;
+
+    // 10 SYS 2061, followed by: INC $D020 / JMP $080D
+    #[rustfmt::skip]
+    const PRG_WITH_SYS_LINE: [u8; 20] = [
+        0x01, 0x08,
+        0x0b, 0x08, 0x0a, 0x00, 0x9e, 0x32, 0x30, 0x36, 0x31, 0x00,
+        0x00, 0x00,
+        0xee, 0x20, 0xd0, 0x4c, 0x0d, 0x08,
+    ];
+
+    // Resets into the same loop as the KERNAL, waiting for the keyboard buffer,
+    // with an empty BASIC that is never started
+    fn fake_kernal() -> Roms {
+        let mut kernal = vec![0xea; 0x2000];
+        let wait = Block::assemble(
+            r"
+            *= $e5cd
+            LDA $C6
+            STA $CC
+            STA $0292
+            BEQ $E5CD
+            JMP $E5CD
+        ",
+        );
+        let offset = (wait.start - 0xe000) as usize;
+        kernal[offset..offset + wait.instructions.len()].copy_from_slice(&wait.instructions);
+
+        // The reset vector
+        kernal[0x1ffc] = 0xcd;
+        kernal[0x1ffd] = 0xe5;
+
+        Roms {
+            basic: Some(vec![0x00; 0x2000]),
+            kernal: Some(kernal),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_start_sys_line_with_fast_path() {
+        let mut c64 = C64::new();
+        c64.autostart(&PRG_WITH_SYS_LINE, Boot::Fast, Start::Sys)
+            .unwrap();
+
+        assert_eq!(c64.cpu.PC, 2061);
+        c64.step();
+        c64.step();
+        c64.step();
+        // Only the lower nibble of the border colour is used
+        assert_eq!(c64.peek(0xd020), 0xf2);
+    }
+
+    #[test]
+    fn should_jump_to_given_address() {
+        let mut c64 = C64::new();
+        c64.autostart(&PRG_WITH_SYS_LINE, Boot::Fast, Start::Address(0x0811))
+            .unwrap();
+
+        assert_eq!(c64.cpu.PC, 0x0811);
+    }
+
+    #[test]
+    fn should_require_boot_for_run() {
+        let mut c64 = C64::new();
+        let result = c64.autostart(&PRG_WITH_SYS_LINE, Boot::Fast, Start::Run);
+
+        assert!(matches!(result, Err(AutostartError::RunWithoutBoot)));
+
+        // Nothing was loaded or set up before the error
+        assert_eq!(c64.peek(0x0801), 0x00);
+        assert_eq!(c64.peek(0x0001), C64::new().peek(0x0001));
+        assert_eq!(c64.cpu.PC, C64::new().cpu.PC);
+    }
+
+    #[test]
+    fn should_require_kernal_for_boot() {
+        let mut c64 = C64::new();
+        let result = c64.autostart(&PRG_WITH_SYS_LINE, Boot::Kernal, Start::Run);
+
+        assert!(matches!(result, Err(AutostartError::MissingKernal)));
+    }
+
+    #[test]
+    fn should_require_basic_for_boot() {
+        let mut c64 = C64::new();
+        c64.set_roms(Roms {
+            basic: None,
+            ..fake_kernal()
+        });
+        let result = c64.autostart(&PRG_WITH_SYS_LINE, Boot::Kernal, Start::Run);
+
+        assert!(matches!(result, Err(AutostartError::MissingBasic)));
+        assert_eq!(c64.peek(0x0801), 0x00);
+    }
+
+    #[test]
+    fn should_type_run_when_ready() {
+        let mut c64 = C64::new();
+        c64.set_roms(fake_kernal());
+        c64.autostart(&PRG_WITH_SYS_LINE, Boot::Kernal, Start::Run)
+            .unwrap();
+
+        assert_eq!(c64.peek(KEYBOARD_COUNT), 4);
+        assert_eq!(c64.peek(KEYBOARD_BUFFER), b'R');
+        assert_eq!(c64.peek(KEYBOARD_BUFFER + 3), b'\r');
+        assert_eq!(c64.peek(0x2d), 0x13);
+        assert_eq!(c64.peek(0x2e), 0x08);
+    }
+
+    #[test]
+    fn should_type_sys_when_ready() {
+        let mut c64 = C64::new();
+        c64.set_roms(fake_kernal());
+        c64.autostart(&PRG_WITH_SYS_LINE, Boot::Kernal, Start::Sys)
+            .unwrap();
+
+        let typed: Vec<u8> = (0..8)
+            .map(|offset| c64.peek(KEYBOARD_BUFFER + offset))
+            .collect();
+        assert_eq!(typed, b"SYS2061\r");
+        assert_eq!(c64.peek(KEYBOARD_COUNT), 8);
+    }
+}
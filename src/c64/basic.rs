@@ -0,0 +1,530 @@
+//! Most demos are machine code, but are still started with RUN, thanks to a one
+//! line BASIC program at $0801 like `10 SYS 2064` in front of the code.
+//!
+//! In memory, each line of a BASIC program is stored as:
+//!
+//! - a pointer to the next line, where a null pointer marks the end of the program
+//! - the line number
+//! - the tokenised line, where keywords are single bytes with bit 7 set
+//! - a null byte ending the line
+//!
+//! Listing a program turns the tokens back into keywords, while any PETSCII
+//! control codes in strings are shown as escapes like {clr} and {red}, or as
+//! {$xx} for anything else that can't be shown as is, the same way as most
+//! cross development tools do it.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use super::Block;
+
+// Where BASIC programs are normally loaded
+pub const BASIC_START: u16 = 0x0801;
+
+// Where the BASIC ROM starts, which a program loaded at $0801 has to end below
+const BASIC_END: u32 = 0xa000;
+
+// The most zeroes to put between a SYS line and the code, as anything more
+// only makes the PRG bigger, and code further away can be started with SYS
+const MAX_SYS_GAP: usize = 64;
+
+// The BASIC token for SYS
+const SYS: u8 = 0x9e;
+
+const REM: u8 = 0x8f;
+const DATA: u8 = 0x83;
+const PRINT: u8 = 0x99;
+const PI: u8 = 0xff;
+
+// The keywords of BASIC V2, in the order of their tokens from $80 and up
+#[rustfmt::skip]
+const KEYWORDS: [&str; 76] = [
+    "END", "FOR", "NEXT", "DATA", "INPUT#", "INPUT", "DIM", "READ",
+    "LET", "GOTO", "RUN", "IF", "RESTORE", "GOSUB", "RETURN", "REM",
+    "STOP", "ON", "WAIT", "LOAD", "SAVE", "VERIFY", "DEF", "POKE",
+    "PRINT#", "PRINT", "CONT", "LIST", "CLR", "CMD", "SYS", "OPEN",
+    "CLOSE", "GET", "NEW", "TAB(", "TO", "FN", "SPC(", "THEN",
+    "NOT", "STEP", "+", "-", "*", "/", "^", "AND",
+    "OR", ">", "=", "<", "SGN", "INT", "ABS", "USR",
+    "FRE", "POS", "SQR", "RND", "LOG", "EXP", "COS", "SIN",
+    "TAN", "ATN", "PEEK", "LEN", "STR$", "VAL", "ASC", "CHR$",
+    "LEFT$", "RIGHT$", "MID$", "GO",
+];
+
+// Names for the PETSCII control codes
+#[rustfmt::skip]
+const CONTROL_CODES: [(u8, &str); 35] = [
+    (0x05, "wht"), (0x0e, "swlc"), (0x11, "down"), (0x12, "rvon"),
+    (0x13, "home"), (0x14, "del"), (0x1c, "red"), (0x1d, "rght"),
+    (0x1e, "grn"), (0x1f, "blu"), (0x81, "orng"), (0x85, "f1"),
+    (0x86, "f3"), (0x87, "f5"), (0x88, "f7"), (0x89, "f2"),
+    (0x8a, "f4"), (0x8b, "f6"), (0x8c, "f8"), (0x8e, "swuc"),
+    (0x90, "blk"), (0x91, "up"), (0x92, "rvof"), (0x93, "clr"),
+    (0x94, "inst"), (0x95, "brn"), (0x96, "lred"), (0x97, "gry1"),
+    (0x98, "gry2"), (0x99, "lgrn"), (0x9a, "lblu"), (0x9b, "gry3"),
+    (0x9c, "pur"), (0x9d, "left"), (0x9e, "yel"),
+];
+
+// Characters that differ between PETSCII and what we show
+const POUND: (u8, char) = (0x5c, '£');
+const UP_ARROW: (u8, char) = (0x5e, '↑');
+const LEFT_ARROW: (u8, char) = (0x5f, '←');
+
+// The highest line number BASIC accepts
+const MAX_LINE_NUMBER: u32 = 63999;
+
+#[derive(Debug, PartialEq)]
+pub struct BasicError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for BasicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for BasicError {}
+
+impl Block {
+    /// The address to SYS to, when the block starts with a BASIC SYS line
+    pub fn sys_address(&self) -> Option<u16> {
+        if self.start != BASIC_START {
+            return None;
+        }
+
+        // Skip the link to the next line and the line number
+        let mut statement = self
+            .instructions
+            .iter()
+            .skip(4)
+            .skip_while(|byte| **byte == b' ');
+
+        if *statement.next()? != SYS {
+            return None;
+        }
+
+        let digits: String = statement
+            .skip_while(|byte| **byte == b' ' || **byte == b'(')
+            .take_while(|byte| byte.is_ascii_digit())
+            .map(|byte| *byte as char)
+            .collect();
+        digits.parse().ok()
+    }
+
+    /// Lists the BASIC program in the block, the same way as LIST does
+    pub fn list(&self) -> Vec<String> {
+        let mut result = vec![];
+        let mut lines = self.instructions.as_slice();
+
+        // A link with a high byte of zero marks the end of the program
+        while let [_, link_hi, number_lo, number_hi, rest @ ..] = lines {
+            if *link_hi == 0 {
+                break;
+            }
+
+            let end = rest
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(rest.len());
+            let number = u16::from_le_bytes([*number_lo, *number_hi]);
+            result.push(format!("{number} {}", detokenise(&rest[..end])));
+
+            lines = rest.get(end + 1..).unwrap_or_default();
+        }
+        result
+    }
+
+    /// Tokenises the lines of BASIC into a program that can be loaded at $0801.
+    /// The lines are sorted by their numbers, and a line number that is used
+    /// more than once keeps the last line, the same as typing them in would.
+    pub fn from_basic(source: &str) -> Result<Block, BasicError> {
+        // The source line and the tokens of every line number
+        let mut lines = BTreeMap::new();
+
+        for (index, text) in source.lines().enumerate() {
+            let error = |message: String| BasicError {
+                line: index + 1,
+                message,
+            };
+
+            let text = text.trim_start();
+            if text.is_empty() {
+                continue;
+            }
+
+            let digits = text
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(text.len());
+            let number = match text[..digits].parse::<u32>() {
+                Ok(number) if number <= MAX_LINE_NUMBER => number as u16,
+                _ => return Err(error(format!("expected a line number, found `{text}`"))),
+            };
+
+            let tokens = tokenise(text[digits..].trim_start()).map_err(error)?;
+            lines.insert(number, (index + 1, tokens));
+        }
+
+        let mut instructions = vec![];
+        for (number, (line, tokens)) in lines {
+            // The link to the next line, after this line and its null byte
+            let next = BASIC_START as u32 + instructions.len() as u32 + 4 + tokens.len() as u32 + 1;
+
+            // There has to be room for the end of the program below the ROM
+            if next + 2 > BASIC_END {
+                return Err(BasicError {
+                    line,
+                    message: format!("the program runs past ${BASIC_END:04X}"),
+                });
+            }
+            instructions.extend_from_slice(&(next as u16).to_le_bytes());
+            instructions.extend_from_slice(&number.to_le_bytes());
+            instructions.extend(tokens);
+            instructions.push(0x00);
+        }
+
+        // End of program
+        instructions.extend_from_slice(&[0x00, 0x00]);
+
+        Ok(Block {
+            start: BASIC_START,
+            instructions,
+        })
+    }
+
+    /// A BASIC program with a single line, e.g. `10 SYS 2064`
+    pub fn sys_stub(line: u16, address: u16) -> Block {
+        let digits = address.to_string();
+
+        // The link points at the end of the program, right after this line
+        let next = BASIC_START + 4 + 1 + digits.len() as u16 + 1;
+
+        let mut instructions = vec![];
+        instructions.extend_from_slice(&next.to_le_bytes());
+        instructions.extend_from_slice(&line.to_le_bytes());
+        instructions.push(SYS);
+        instructions.extend_from_slice(digits.as_bytes());
+        instructions.push(0x00);
+
+        // End of program
+        instructions.extend_from_slice(&[0x00, 0x00]);
+
+        Block {
+            start: BASIC_START,
+            instructions,
+        }
+    }
+
+    /// Puts a SYS line to the start of the block in front of it, so it can be
+    /// started with RUN. The gap between the two is filled with zeroes, so
+    /// the block has to start at most 64 bytes after the SYS line, and end
+    /// below the BASIC ROM at $a000.
+    pub fn with_sys_stub(&self) -> Option<Block> {
+        let mut stub = Block::sys_stub(10, self.start);
+        let end = stub.start as usize + stub.instructions.len();
+        let block_end = self.start as u32 + self.instructions.len() as u32;
+
+        if (self.start as usize) < end
+            || self.start as usize - end > MAX_SYS_GAP
+            || block_end > BASIC_END
+        {
+            return None;
+        }
+
+        stub.instructions
+            .resize(self.start as usize - stub.start as usize, 0x00);
+        stub.instructions.extend_from_slice(&self.instructions);
+        Some(stub)
+    }
+}
+
+fn detokenise(line: &[u8]) -> String {
+    let mut result = String::new();
+    let mut quoted = false;
+    let mut literal = false;
+
+    for byte in line {
+        match *byte {
+            b'"' => {
+                quoted = !quoted;
+                result.push('"');
+            }
+            token @ 0x80..=0xcb if !quoted && !literal => {
+                result += KEYWORDS[(token - 0x80) as usize];
+                literal = token == REM;
+            }
+            PI if !quoted => result.push('π'),
+            byte => result += &petscii_to_text(byte),
+        }
+    }
+    result
+}
+
+fn tokenise(text: &str) -> Result<Vec<u8>, String> {
+    let mut result = vec![];
+    let mut rest = text;
+    let mut quoted = false;
+    let mut data = false;
+    let mut remark = false;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '{' {
+            let end = rest
+                .find('}')
+                .ok_or(format!("unterminated escape in `{text}`"))?;
+            result.push(escape_to_petscii(&rest[1..end])?);
+            rest = &rest[end + 1..];
+            continue;
+        }
+
+        if !quoted && !data && !remark {
+            if c == '?' {
+                result.push(PRINT);
+                rest = &rest[1..];
+                continue;
+            }
+
+            let upper = rest.to_ascii_uppercase();
+            if let Some(index) = KEYWORDS
+                .iter()
+                .position(|keyword| upper.starts_with(keyword))
+            {
+                let token = 0x80 + index as u8;
+                result.push(token);
+                rest = &rest[KEYWORDS[index].len()..];
+
+                data = token == DATA;
+                remark = token == REM;
+                continue;
+            }
+        }
+
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => data = false,
+            _ => {}
+        }
+
+        result.push(text_to_petscii(c).ok_or(format!("`{c}` can't be written in PETSCII"))?);
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok(result)
+}
+
+fn petscii_to_text(byte: u8) -> String {
+    match byte {
+        0x20..=0x5b | 0x5d => (byte as char).to_string(),
+        _ if byte == POUND.0 => POUND.1.to_string(),
+        _ if byte == UP_ARROW.0 => UP_ARROW.1.to_string(),
+        _ if byte == LEFT_ARROW.0 => LEFT_ARROW.1.to_string(),
+        _ => match CONTROL_CODES.iter().find(|(code, _)| *code == byte) {
+            Some((_, name)) => format!("{{{name}}}"),
+            None => format!("{{${byte:02x}}}"),
+        },
+    }
+}
+
+fn text_to_petscii(c: char) -> Option<u8> {
+    match c {
+        ' '..='[' | ']' => Some(c as u8),
+        'a'..='z' => Some(c.to_ascii_uppercase() as u8),
+        'π' => Some(PI),
+        _ if c == POUND.1 => Some(POUND.0),
+        _ if c == UP_ARROW.1 => Some(UP_ARROW.0),
+        _ if c == LEFT_ARROW.1 => Some(LEFT_ARROW.0),
+        _ => None,
+    }
+}
+
+fn escape_to_petscii(escape: &str) -> Result<u8, String> {
+    if let Some(hex) = escape.strip_prefix('$') {
+        return u8::from_str_radix(hex, 16).map_err(|_| format!("invalid escape {{{escape}}}"));
+    }
+
+    CONTROL_CODES
+        .iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(escape))
+        .map(|(code, _)| *code)
+        .ok_or(format!("unknown escape {{{escape}}}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_find_sys_address() {
+        let block = Block::from_prg(&[
+            0x01, 0x08, 0x0b, 0x08, 0x0a, 0x00, 0x9e, 0x32, 0x30, 0x36, 0x31, 0x00, 0x00, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(block.sys_address(), Some(2061));
+
+        // 0 SYS (49152):REM
+        let block = Block {
+            start: 0x0801,
+            instructions: b"\x10\x08\x00\x00 \x9e (49152):\x8f\x00\x00\x00".to_vec(),
+        };
+        assert_eq!(block.sys_address(), Some(49152));
+    }
+
+    #[test]
+    fn should_not_find_sys_address_without_sys() {
+        // 10 PRINT"
+        let block = Block {
+            start: 0x0801,
+            instructions: vec![0x0b, 0x08, 0x0a, 0x00, 0x99, 0x22],
+        };
+        assert_eq!(block.sys_address(), None);
+
+        let block = Block {
+            start: 0xc000,
+            instructions: vec![0x0b, 0x08, 0x0a, 0x00, 0x9e, 0x32],
+        };
+        assert_eq!(block.sys_address(), None);
+    }
+
+    #[test]
+    fn should_generate_sys_stub() {
+        let stub = Block::sys_stub(10, 2061);
+
+        assert_eq!(
+            stub.instructions,
+            vec![0x0b, 0x08, 0x0a, 0x00, 0x9e, 0x32, 0x30, 0x36, 0x31, 0x00, 0x00, 0x00]
+        );
+        assert_eq!(stub.sys_address(), Some(2061));
+    }
+
+    #[test]
+    fn should_put_sys_stub_in_front_of_block() {
+        let block = Block {
+            start: 0x0810,
+            instructions: vec![0xee, 0x20, 0xd0],
+        };
+
+        let runnable = block.with_sys_stub().unwrap();
+        assert_eq!(runnable.start, 0x0801);
+        assert_eq!(runnable.sys_address(), Some(0x0810));
+        assert_eq!(runnable.instructions.len(), 0x0f + 3);
+        assert_eq!(runnable.instructions[0x0f..], [0xee, 0x20, 0xd0]);
+
+        let block = Block {
+            start: 0x0805,
+            instructions: vec![0xea],
+        };
+        assert_eq!(block.with_sys_stub(), None);
+
+        let block = Block {
+            start: 0xc000,
+            instructions: vec![0xea],
+        };
+        assert_eq!(block.with_sys_stub(), None);
+
+        let block = Block {
+            start: 0x0810,
+            instructions: vec![0xea; 0xa000 - 0x0810 + 1],
+        };
+        assert_eq!(block.with_sys_stub(), None);
+    }
+
+    #[test]
+    fn should_list_program() {
+        // 10 PRINT "{CLR}HELLO" : GOTO 10
+        #[rustfmt::skip]
+        let block = Block {
+            start: 0x0801,
+            instructions: vec![
+                0x13, 0x08, 0x0a, 0x00, 0x99, 0x20, 0x22, 0x93, 0x48, 0x45, 0x4c, 0x4c,
+                0x4f, 0x22, 0x3a, 0x89, 0x20, 0x31, 0x30, 0x00,
+                0x00, 0x00,
+            ],
+        };
+
+        assert_eq!(block.list(), vec!["10 PRINT \"{clr}HELLO\":GOTO 10"]);
+    }
+
+    #[test]
+    fn should_list_sys_stub() {
+        assert_eq!(Block::sys_stub(10, 2061).list(), vec!["10 SYS2061"]);
+    }
+
+    #[test]
+    fn should_not_expand_tokens_in_strings_or_remarks() {
+        let block = Block::from_basic("10 REM PRINT\n20 A$=\"{$99}AND\"").unwrap();
+
+        assert_eq!(block.instructions[4], REM);
+        assert_eq!(block.list(), vec!["10 REM PRINT", "20 A$=\"{lgrn}AND\""]);
+    }
+
+    #[test]
+    fn should_tokenise_program() {
+        let block = Block::from_basic("10 print \"{red}hi\"\n20 goto 10").unwrap();
+
+        #[rustfmt::skip]
+        let expected = vec![
+            0x0d, 0x08, 0x0a, 0x00, 0x99, 0x20, 0x22, 0x1c, 0x48, 0x49, 0x22, 0x00,
+            0x16, 0x08, 0x14, 0x00, 0x89, 0x20, 0x31, 0x30, 0x00,
+            0x00, 0x00,
+        ];
+        assert_eq!(block.start, 0x0801);
+        assert_eq!(block.instructions, expected);
+    }
+
+    #[test]
+    fn should_tokenise_the_way_basic_does() {
+        let block = Block::from_basic("10 ?X:GOSUB20:DATA TO,GO\n20 INPUT#1,A:π").unwrap();
+
+        assert_eq!(
+            block.list(),
+            vec!["10 PRINTX:GOSUB20:DATA TO,GO", "20 INPUT#1,A:π"]
+        );
+        assert_eq!(block.instructions[4], PRINT);
+    }
+
+    #[test]
+    fn should_round_trip_listing() {
+        let source = "10 POKE53280,0:POKE53281,0\n20 FORI=1TO10:PRINTCHR$(205.5+RND(1));:NEXT\n30 IFA<>1THEN20";
+        let block = Block::from_basic(source).unwrap();
+
+        assert_eq!(block.list().join("\n"), source);
+        assert_eq!(Block::from_basic(&block.list().join("\n")).unwrap(), block);
+    }
+
+    #[test]
+    fn should_fail_without_line_number() {
+        let result = Block::from_basic("10 PRINT\nPRINT");
+        assert_eq!(result.unwrap_err().line, 2);
+
+        let result = Block::from_basic("64000 PRINT");
+        assert!(result.is_err());
+
+        let result = Block::from_basic("10 PRINT\"{bogus}\"");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_sort_lines_and_keep_the_last_duplicate() {
+        let block = Block::from_basic("20 GOTO 10\n10 PRINT\n20 END").unwrap();
+        assert_eq!(block.list(), ["10 PRINT", "20 END"]);
+    }
+
+    #[test]
+    fn should_fail_when_program_runs_into_rom() {
+        // Lines of 64 bytes, where 607 of them and the end of the program
+        // stop at $9fc3, and one more runs just past $a000
+        let line = format!("REM {}", "X".repeat(57));
+        let source = |count: usize| -> String {
+            let lines: Vec<String> = (0..count)
+                .map(|number| format!("{number} {line}"))
+                .collect();
+            lines.join("\n")
+        };
+
+        let block = Block::from_basic(&source(607)).unwrap();
+        assert_eq!(BASIC_START as usize + block.instructions.len(), 0x9fc3);
+
+        let result = Block::from_basic(&source(608));
+        assert_eq!(result.unwrap_err().line, 608);
+    }
+}
@@ -1,28 +1,296 @@
-use super::{cpu::Cpu, Memory};
+//! The 6510 has an I/O port of its own at $00 (data direction) and $01 (data),
+//! where the lowest three bits of $01 control what the CPU sees in the address
+//! ranges normally occupied by the ROMs and the I/O chips:
+//!
+//! - LORAM  (bit 0) - BASIC ROM at $a000-$bfff, needs HIRAM as well
+//! - HIRAM  (bit 1) - KERNAL ROM at $e000-$ffff
+//! - CHAREN (bit 2) - I/O at $d000-$dfff when set, otherwise the character ROM,
+//!   as long as either LORAM or HIRAM is set
+//!
+//! Writes always end up in the RAM below the ROMs, but not below the I/O area.
+//!
+//! Reading some of the chip registers has side effects, like acknowledging an
+//! interrupt, so `peek` is there for reading without any, e.g. for a debugger.
+//!
+//! The keyboard and the joysticks are read through the ports of CIA 1, so the
+//! bus combines what the CIA drives with the keys and switches held down.
+//! CIA 1 pulls IRQ, like the VIC-II, while CIA 2 and the RESTORE key pull NMI.
+//!
+//! The model of the machine decides the timing of the VIC-II and the clock of
+//! the SID, and the mains frequency that is fed to the time of day clocks of
+//! the CIAs, which is counted in cycles of the bus.
 
+use super::cia::Cia;
+use super::joystick::{Joystick, Port};
+use super::keyboard::{Key, Keyboard};
+use super::model::Model;
+use super::roms::Roms;
+use super::sid::Sid;
+use super::vicII::{VicII, VicMemory};
+use super::Memory;
+
+/// Everything the CPU is connected to, the memory, the ROMs and the chips
 #[derive(Clone, Debug)]
-pub(crate) struct Bus {
-    cpu: Cpu,
+pub struct Bus {
     memory: Memory,
+    roms: Roms,
+    model: Model,
+
+    vic: VicII,
+    sid: Sid,
+    cia1: Cia,
+    cia2: Cia,
+    keyboard: Keyboard,
+    joysticks: [Joystick; 2],
+
+    // The colour RAM is only 4 bits wide
+    color_ram: [u8; 0x400],
+
+    // The rest of the I/O area, the two expansion areas at $de00-$dfff
+    io: [u8; 0x1000],
+
+    // The 6510 I/O port, data direction and data registers
+    ddr: u8,
+    port: u8,
+
+    // The amount of cycles the bus has been clocked, and how far into the
+    // current cycle of the mains, in steps of the mains frequency
+    cycles: u64,
+    mains_phase: u32,
 }
 
+const LORAM: u8 = 1 << 0;
+const HIRAM: u8 = 1 << 1;
+const CHAREN: u8 = 1 << 2;
+
 impl Bus {
-    pub fn new(cpu: Cpu, memory: Memory) -> Self {
-        Bus { cpu, memory }
+    /// A bus for a PAL machine, with the memory and without any ROMs
+    pub fn new(memory: Memory) -> Self {
+        Self::with_model(memory, Model::default())
+    }
+
+    pub fn with_model(memory: Memory, model: Model) -> Self {
+        Bus {
+            memory,
+            roms: Roms::default(),
+            model,
+            vic: VicII::new(model.timing()),
+            sid: Sid::new(model.clock()),
+            cia1: Cia::new(),
+            cia2: Cia::new(),
+            keyboard: Keyboard::new(),
+            joysticks: [Joystick::new(); 2],
+            color_ram: [0; 0x400],
+            io: [0; 0x1000],
+            ddr: 0x00,
+            port: 0x00,
+            cycles: 0,
+            mains_phase: 0,
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn roms(&self) -> &Roms {
+        &self.roms
+    }
+
+    pub fn set_roms(&mut self, roms: Roms) {
+        self.roms = roms;
+    }
+
+    pub fn vic(&self) -> &VicII {
+        &self.vic
+    }
+
+    pub fn vic_mut(&mut self) -> &mut VicII {
+        &mut self.vic
+    }
+
+    pub fn sid(&self) -> &Sid {
+        &self.sid
+    }
+
+    pub fn sid_mut(&mut self) -> &mut Sid {
+        &mut self.sid
+    }
+
+    pub fn keyboard(&self) -> &Keyboard {
+        &self.keyboard
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut Keyboard {
+        &mut self.keyboard
+    }
+
+    pub fn joystick(&self, port: Port) -> &Joystick {
+        &self.joysticks[port as usize]
+    }
+
+    pub fn joystick_mut(&mut self, port: Port) -> &mut Joystick {
+        &mut self.joysticks[port as usize]
+    }
+
+    /// The memory as the VIC-II sees it
+    pub fn vic_memory(&self) -> VicMemory<'_> {
+        VicMemory {
+            ram: &self.memory,
+            chargen: self.roms.chargen.as_deref(),
+            color_ram: &self.color_ram,
+            bank: self.cia2.vic_bank(),
+        }
+    }
+
+    /// Advances everything connected to the bus by one cycle
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+
+        let memory = VicMemory {
+            ram: &self.memory,
+            chargen: self.roms.chargen.as_deref(),
+            color_ram: &self.color_ram,
+            bank: self.cia2.vic_bank(),
+        };
+        self.vic.tick(&memory);
+        self.sid.tick();
+        self.cia1.tick();
+        self.cia2.tick();
+
+        self.mains_phase += self.model.mains_frequency();
+        if self.mains_phase >= self.model.clock() {
+            self.mains_phase -= self.model.clock();
+            self.cia1.tod_tick();
+            self.cia2.tod_tick();
+        }
+    }
+
+    /// Whether the CPU may read in the coming cycle, as RDY is pulled low by
+    /// BA of the VIC-II when it needs the bus
+    pub fn rdy(&self) -> bool {
+        !self.vic.ba_low()
+    }
+
+    /// Whether any of the chips pulls the IRQ line
+    pub fn irq(&self) -> bool {
+        self.vic.irq() || self.cia1.interrupt()
+    }
+
+    /// Whether CIA 2 or the RESTORE key pulls the NMI line
+    pub fn nmi(&self) -> bool {
+        self.cia2.interrupt() || self.keyboard.is_pressed(Key::Restore)
+    }
+
+    /// Reads like the CPU does, acknowledging interrupts and such when
+    /// reading the registers of the chips
+    pub fn read(&mut self, address: usize) -> u8 {
+        match address {
+            0xd000..=0xd3ff if self.io_visible() => self.vic.read(address),
+            0xdc00..=0xdcff if self.io_visible() => {
+                let value = self.cia1.read(address);
+                self.with_controllers(address, value)
+            }
+            0xdd00..=0xddff if self.io_visible() => self.cia2.read(address),
+            _ => self.peek(address),
+        }
     }
 
-    pub fn read(&self, address: usize) -> u8 {
+    /// Reads without any side effects
+    pub fn peek(&self, address: usize) -> u8 {
         if (0x0000..=0xffff).contains(&address) {
-            return self.memory[address];
+            let config = self.port_value();
+            let io_or_chargen = config & (LORAM | HIRAM) != 0;
+
+            return match address {
+                0x0000 => self.ddr,
+                0x0001 => config,
+                0xa000..=0xbfff if config & (LORAM | HIRAM) == LORAM | HIRAM => {
+                    rom_or(&self.roms.basic, address - 0xa000, self.memory[address])
+                }
+                0xd000..=0xdfff if self.io_visible() => self.read_io(address),
+                0xd000..=0xdfff if io_or_chargen => {
+                    rom_or(&self.roms.chargen, address - 0xd000, self.memory[address])
+                }
+                0xe000..=0xffff if config & HIRAM != 0 => {
+                    rom_or(&self.roms.kernal, address - 0xe000, self.memory[address])
+                }
+                _ => self.memory[address],
+            };
         }
         0x00 // Default
     }
 
+    /// Writes to the I/O area when it is visible, and to the RAM otherwise,
+    /// even below the ROMs
     pub fn write(&mut self, address: usize, value: u8) {
         if (0x0000..=0xffff).contains(&address) {
+            match address {
+                0x0000 => self.ddr = value,
+                0x0001 => self.port = value,
+                0xd000..=0xdfff if self.io_visible() => {
+                    self.write_io(address, value);
+                    return;
+                }
+                _ => {}
+            }
             self.memory[address] = value;
         }
     }
+
+    // Bits set up as inputs are pulled high, except for the cassette
+    // motor control and the unused bits.
+    fn port_value(&self) -> u8 {
+        (self.port & self.ddr) | (!self.ddr & 0x17)
+    }
+
+    // The I/O area replaces the character ROM when CHAREN is set
+    fn io_visible(&self) -> bool {
+        let config = self.port_value();
+        config & (LORAM | HIRAM) != 0 && config & CHAREN != 0
+    }
+
+    fn read_io(&self, address: usize) -> u8 {
+        match address {
+            0xd000..=0xd3ff => self.vic.peek(address),
+            0xd400..=0xd7ff => self.sid.read(address),
+            0xd800..=0xdbff => self.color_ram[address - 0xd800],
+            0xdc00..=0xdcff => self.with_controllers(address, self.cia1.peek(address)),
+            0xdd00..=0xddff => self.cia2.peek(address),
+            _ => self.io[address - 0xd000],
+        }
+    }
+
+    // The ports of CIA 1 pulled low by the keys and the joysticks, where a
+    // joystick pulls the lines of the keyboard matrix on its port low too
+    fn with_controllers(&self, address: usize, value: u8) -> u8 {
+        let port_1 = self.joystick(Port::One).lines();
+        let port_2 = self.joystick(Port::Two).lines();
+
+        match address & 0x0f {
+            0x00 => value & port_2 & self.keyboard.columns(self.cia1.port_b() & port_1),
+            0x01 => value & port_1 & self.keyboard.rows(self.cia1.port_a() & port_2),
+            _ => value,
+        }
+    }
+
+    fn write_io(&mut self, address: usize, value: u8) {
+        match address {
+            0xd000..=0xd3ff => self.vic.write(address, value),
+            0xd400..=0xd7ff => self.sid.write(address, value),
+            0xd800..=0xdbff => self.color_ram[address - 0xd800] = value & 0x0f,
+            0xdc00..=0xdcff => self.cia1.write(address, value),
+            0xdd00..=0xddff => self.cia2.write(address, value),
+            _ => self.io[address - 0xd000] = value,
+        }
+    }
+}
+
+fn rom_or(rom: &Option<Vec<u8>>, offset: usize, ram: u8) -> u8 {
+    match rom {
+        Some(rom) => rom[offset],
+        None => ram,
+    }
 }
 
 #[cfg(test)]
@@ -31,10 +299,7 @@ mod tests {
 
     #[test]
     fn should_read_and_write() {
-        let mut bus = Bus {
-            memory: [0xff; 0xffff],
-            cpu: Cpu::new(),
-        };
+        let mut bus = Bus::new([0xff; 0x10000]);
 
         let address = 0x1000;
 
@@ -45,4 +310,78 @@ mod tests {
         let value = bus.read(address);
         assert_eq!(value, 0x80);
     }
+
+    #[test]
+    fn should_bank_roms_in_and_out() {
+        let mut bus = Bus::new([0x00; 0x10000]);
+        bus.set_roms(Roms {
+            basic: Some(vec![0xba; 0x2000]),
+            kernal: Some(vec![0xea; 0x2000]),
+            chargen: Some(vec![0xcc; 0x1000]),
+        });
+
+        // Writes go to the RAM below the ROMs
+        bus.write(0xa000, 0x11);
+        bus.write(0xe000, 0x22);
+        assert_eq!(bus.read(0xa000), 0xba);
+        assert_eq!(bus.read(0xe000), 0xea);
+
+        // All RAM
+        bus.write(0x0000, 0x2f);
+        bus.write(0x0001, 0x34);
+        assert_eq!(bus.read(0xa000), 0x11);
+        assert_eq!(bus.read(0xe000), 0x22);
+
+        // Character ROM instead of I/O
+        bus.write(0x0001, 0x33);
+        assert_eq!(bus.read(0xd000), 0xcc);
+
+        // Only the KERNAL
+        bus.write(0x0001, 0x36);
+        assert_eq!(bus.read(0xa000), 0x11);
+        assert_eq!(bus.read(0xe000), 0xea);
+    }
+
+    #[test]
+    fn should_not_write_through_io() {
+        let mut bus = Bus::new([0x00; 0x10000]);
+
+        bus.write(0xd020, 0x0e);
+        assert_eq!(bus.read(0xd020), 0xfe);
+
+        bus.write(0x0000, 0x2f);
+        bus.write(0x0001, 0x34);
+        assert_eq!(bus.read(0xd020), 0x00);
+    }
+
+    #[test]
+    fn should_draw_from_the_bank_selected_by_cia2() {
+        let mut bus = Bus::new([0x00; 0x10000]);
+        bus.set_roms(Roms {
+            chargen: Some(vec![0xff; 0x1000]),
+            ..Default::default()
+        });
+
+        // Bank 1, where the character ROM isn't visible
+        bus.write(0xdd02, 0x03);
+        bus.write(0xdd00, 0x02);
+        bus.write(0xd011, 0x1b);
+        bus.write(0xd016, 0x08);
+        bus.write(0xd018, 0x14);
+        bus.write(0xd021, 0x06);
+        bus.write(0xd800, 0x01);
+        bus.write(0x5000, 0x80);
+
+        let frame = bus.vic().frames();
+        while bus.vic().frames() == frame {
+            bus.tick();
+        }
+
+        // The first pixel of the first character, from the RAM at $5000
+        let vic = bus.vic();
+        let first = 51 * vic.width() + 16 * 8;
+        assert_eq!(vic.frame()[first], 0x01);
+        assert_eq!(vic.frame()[first + 1], 0x06);
+        assert_eq!(bus.read(0xd800), 0x01);
+    }
 }
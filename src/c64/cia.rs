@@ -0,0 +1,484 @@
+//! The C64 has two 6526 CIAs, at $dc00 and $dd00, each with two 8 bit I/O ports
+//! and 16 registers that are repeated across their 256 byte pages.
+//!
+//! Every bit of a port is either an input or an output, depending on its data
+//! direction register. Inputs are pulled high, so a bit that isn't driven by
+//! anything reads as 1.
+//!
+//! CIA 2 uses the two lowest bits of port A to select which of the four 16K
+//! banks the VIC-II sees, inverted so that the default of %11 selects bank 0.
+//!
+//! The time of day clock counts tenths of seconds, seconds, minutes and hours in
+//! BCD, with the hours going from 1 to 12 and bit 7 set for PM. It's driven by
+//! the 50 or 60Hz of the mains, divided by 5 or 6 depending on bit 7 of control
+//! register A, so a program that sets it up for the wrong frequency gets a
+//! clock that runs too fast or too slow.
+//!
+//! - Reading the hours latches all four registers, until the tenths are read.
+//! - Writing the hours stops the clock, until the tenths are written.
+//! - With bit 7 of control register B set, the writes go to the alarm instead.
+//!
+//! Each CIA has two 16 bit timers that count down every cycle, or timer B can
+//! count the underflows of timer A instead. When a timer underflows, it's
+//! reloaded from its latch, and either keeps going or stops, in one-shot mode.
+//! The underflows, and the alarm of the time of day clock, set flags in the
+//! interrupt control register, which pulls the IRQ line of CIA 1, or the NMI
+//! line of CIA 2, for the flags that are enabled in its mask. Reading the
+//! register clears the flags, acknowledging the interrupt.
+//!
+//! The KERNAL uses timer A of CIA 1 for the interrupt 60 times per second that
+//! scans the keyboard and blinks the cursor.
+
+const PORT_A: usize = 0x00;
+const PORT_B: usize = 0x01;
+const DDR_A: usize = 0x02;
+const DDR_B: usize = 0x03;
+const TIMER_A_LO: usize = 0x04;
+const TIMER_A_HI: usize = 0x05;
+const TIMER_B_LO: usize = 0x06;
+const TIMER_B_HI: usize = 0x07;
+const TOD_TENTHS: usize = 0x08;
+const TOD_HOURS: usize = 0x0b;
+const INTERRUPT_CONTROL: usize = 0x0d;
+const CONTROL_A: usize = 0x0e;
+const CONTROL_B: usize = 0x0f;
+
+// Both control registers
+const START: u8 = 1 << 0;
+const ONE_SHOT: u8 = 1 << 3;
+const FORCE_LOAD: u8 = 1 << 4;
+
+// Control register A, where the time of day clock counts at 50Hz when set
+const TOD_50HZ: u8 = 1 << 7;
+
+// Control register B, what timer B counts, where only the cycles and the
+// underflows of timer A are supported, while counting the CNT pin never counts
+const TIMER_B_MODE: u8 = 0b11 << 5;
+const TIMER_B_CYCLES: u8 = 0b00 << 5;
+const TIMER_B_UNDERFLOWS: u8 = 0b10 << 5;
+const TIMER_B_UNDERFLOWS_WITH_CNT: u8 = 0b11 << 5;
+
+// Control register B, where writes to the time of day go to the alarm
+const TOD_ALARM: u8 = 1 << 7;
+
+// The interrupt control register, where bit 7 is set on reads when any of the
+// enabled flags are, and on writes decides whether to set or clear the mask
+const INTERRUPT_TIMER_A: u8 = 1 << 0;
+const INTERRUPT_TIMER_B: u8 = 1 << 1;
+const INTERRUPT_ALARM: u8 = 1 << 2;
+const INTERRUPT_ANY: u8 = 1 << 7;
+
+#[derive(Clone, Debug, Default)]
+struct Timer {
+    latch: u16,
+    counter: u16,
+}
+
+impl Timer {
+    // Counts down, returning whether it underflowed and was reloaded
+    fn count(&mut self) -> bool {
+        if self.counter == 0 {
+            self.counter = self.latch;
+            true
+        } else {
+            self.counter -= 1;
+            false
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Cia {
+    registers: [u8; 0x10],
+
+    // The time of day, tenths first, the copy of it latched by reading the
+    // hours, and the alarm
+    tod: [u8; 4],
+    tod_latch: Option<[u8; 4]>,
+    alarm: [u8; 4],
+
+    // Whether the clock was stopped by writing the hours, and the number of
+    // mains cycles counted towards the next tenth of a second
+    tod_stopped: bool,
+    tod_cycles: u8,
+
+    timer_a: Timer,
+    timer_b: Timer,
+    interrupt_flags: u8,
+    interrupt_mask: u8,
+}
+
+impl Cia {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The value on the pins of port A
+    pub fn port_a(&self) -> u8 {
+        self.registers[PORT_A] | !self.registers[DDR_A]
+    }
+
+    /// The value on the pins of port B
+    pub fn port_b(&self) -> u8 {
+        self.registers[PORT_B] | !self.registers[DDR_B]
+    }
+
+    /// The 16K bank the VIC-II sees, as selected by port A of CIA 2
+    pub fn vic_bank(&self) -> u16 {
+        (!self.port_a() & 0x03) as u16 * 0x4000
+    }
+
+    /// Counts a cycle of the mains, 50 or 60 times per second
+    pub fn tod_tick(&mut self) {
+        let divider = match self.registers[CONTROL_A] & TOD_50HZ {
+            0 => 6,
+            _ => 5,
+        };
+
+        self.tod_cycles += 1;
+        if self.tod_cycles < divider {
+            return;
+        }
+        self.tod_cycles = 0;
+
+        if !self.tod_stopped {
+            self.tod = next_tenth(self.tod);
+            if self.tod == self.alarm {
+                self.interrupt_flags |= INTERRUPT_ALARM;
+            }
+        }
+    }
+
+    /// Advances the timers by one cycle
+    pub fn tick(&mut self) {
+        let control_a = self.registers[CONTROL_A];
+        let control_b = self.registers[CONTROL_B];
+
+        let mut underflow_a = false;
+        if control_a & START != 0 {
+            underflow_a = self.timer_a.count();
+            if underflow_a {
+                self.interrupt_flags |= INTERRUPT_TIMER_A;
+                if control_a & ONE_SHOT != 0 {
+                    self.registers[CONTROL_A] &= !START;
+                }
+            }
+        }
+
+        let count_b = match control_b & TIMER_B_MODE {
+            TIMER_B_CYCLES => true,
+            TIMER_B_UNDERFLOWS | TIMER_B_UNDERFLOWS_WITH_CNT => underflow_a,
+            _ => false,
+        };
+        if control_b & START != 0 && count_b && self.timer_b.count() {
+            self.interrupt_flags |= INTERRUPT_TIMER_B;
+            if control_b & ONE_SHOT != 0 {
+                self.registers[CONTROL_B] &= !START;
+            }
+        }
+    }
+
+    /// Whether any of the enabled interrupts has happened, which pulls IRQ
+    /// for CIA 1 and NMI for CIA 2
+    pub fn interrupt(&self) -> bool {
+        self.interrupt_flags & self.interrupt_mask != 0
+    }
+
+    /// Reads a register, where reading the time of day latches it
+    pub fn read(&mut self, address: usize) -> u8 {
+        let value = self.peek(address);
+
+        match address & 0x0f {
+            TOD_TENTHS => self.tod_latch = None,
+            TOD_HOURS => self.tod_latch = Some(self.tod_latch.unwrap_or(self.tod)),
+            INTERRUPT_CONTROL => self.interrupt_flags = 0,
+            _ => {}
+        }
+        value
+    }
+
+    /// Reads a register without any side effects
+    pub fn peek(&self, address: usize) -> u8 {
+        match address & 0x0f {
+            PORT_A => self.port_a(),
+            PORT_B => self.port_b(),
+            TIMER_A_LO => self.timer_a.counter as u8,
+            TIMER_A_HI => (self.timer_a.counter >> 8) as u8,
+            TIMER_B_LO => self.timer_b.counter as u8,
+            TIMER_B_HI => (self.timer_b.counter >> 8) as u8,
+            INTERRUPT_CONTROL => match self.interrupt() {
+                true => self.interrupt_flags | INTERRUPT_ANY,
+                false => self.interrupt_flags,
+            },
+            register @ TOD_TENTHS..=TOD_HOURS => {
+                self.tod_latch.unwrap_or(self.tod)[register - TOD_TENTHS]
+            }
+            register => self.registers[register],
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        match address & 0x0f {
+            register @ TOD_TENTHS..=TOD_HOURS => {
+                let value = match register {
+                    TOD_TENTHS => value & 0x0f,
+                    TOD_HOURS => value & 0x9f,
+                    _ => value & 0x7f,
+                };
+
+                if self.registers[CONTROL_B] & TOD_ALARM != 0 {
+                    self.alarm[register - TOD_TENTHS] = value;
+                } else {
+                    self.tod[register - TOD_TENTHS] = value;
+                    match register {
+                        TOD_TENTHS => self.tod_stopped = false,
+                        TOD_HOURS => self.tod_stopped = true,
+                        _ => {}
+                    }
+                }
+            }
+            TIMER_A_LO | TIMER_A_HI => {
+                let running = self.registers[CONTROL_A] & START != 0;
+                write_latch(&mut self.timer_a, address, value, running);
+            }
+            TIMER_B_LO | TIMER_B_HI => {
+                let running = self.registers[CONTROL_B] & START != 0;
+                write_latch(&mut self.timer_b, address, value, running);
+            }
+            INTERRUPT_CONTROL => match value & INTERRUPT_ANY {
+                0 => self.interrupt_mask &= !value,
+                _ => self.interrupt_mask |= value & 0x1f,
+            },
+            register @ (CONTROL_A | CONTROL_B) => {
+                if value & FORCE_LOAD != 0 {
+                    let timer = match register {
+                        CONTROL_A => &mut self.timer_a,
+                        _ => &mut self.timer_b,
+                    };
+                    timer.counter = timer.latch;
+                }
+                self.registers[register] = value & !FORCE_LOAD;
+            }
+            register => self.registers[register] = value,
+        }
+    }
+}
+
+// Writes the low or high byte of the latch, where writing the high byte of a
+// stopped timer loads the counter as well
+fn write_latch(timer: &mut Timer, address: usize, value: u8, running: bool) {
+    match address & 0x01 {
+        0 => timer.latch = (timer.latch & 0xff00) | value as u16,
+        _ => {
+            timer.latch = (timer.latch & 0x00ff) | (value as u16) << 8;
+            if !running {
+                timer.counter = timer.latch;
+            }
+        }
+    }
+}
+
+// The time of day a tenth of a second later, carrying into the seconds,
+// minutes and hours, where 11 turns into 12 with AM/PM flipped
+fn next_tenth(tod: [u8; 4]) -> [u8; 4] {
+    let [tenths, seconds, minutes, hours] = tod;
+
+    if tenths < 9 {
+        return [tenths + 1, seconds, minutes, hours];
+    }
+    if seconds != 0x59 {
+        return [0, next_bcd(seconds), minutes, hours];
+    }
+    if minutes != 0x59 {
+        return [0, 0, next_bcd(minutes), hours];
+    }
+
+    let hours = match hours & 0x1f {
+        0x11 => (hours ^ 0x80) & 0x80 | 0x12,
+        0x12 => hours & 0x80 | 0x01,
+        hour => hours & 0x80 | next_bcd(hour),
+    };
+    [0, 0, 0, hours]
+}
+
+fn next_bcd(value: u8) -> u8 {
+    match value & 0x0f {
+        9 => (value & 0xf0) + 0x10,
+        _ => value + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pull_inputs_high() {
+        let mut cia = Cia::new();
+        assert_eq!(cia.read(0xdd00), 0xff);
+
+        cia.write(0xdd02, 0x3f);
+        cia.write(0xdd00, 0x14);
+        assert_eq!(cia.read(0xdd00), 0xd4);
+
+        // Mirrored every 16 bytes
+        assert_eq!(cia.read(0xddf0), 0xd4);
+    }
+
+    #[test]
+    fn should_select_vic_bank() {
+        let mut cia = Cia::new();
+        assert_eq!(cia.vic_bank(), 0x0000);
+
+        cia.write(0xdd02, 0x03);
+        for (value, bank) in [
+            (0x03, 0x0000),
+            (0x02, 0x4000),
+            (0x01, 0x8000),
+            (0x00, 0xc000),
+        ] {
+            cia.write(0xdd00, value);
+            assert_eq!(cia.vic_bank(), bank);
+        }
+    }
+
+    fn tick(cia: &mut Cia, mains_cycles: usize) {
+        for _ in 0..mains_cycles {
+            cia.tod_tick();
+        }
+    }
+
+    #[test]
+    fn should_count_time_of_day_from_mains() {
+        let mut cia = Cia::new();
+
+        // 60Hz is the default
+        tick(&mut cia, 60);
+        assert_eq!(cia.read(0xdc09), 0x01);
+        assert_eq!(cia.read(0xdc08), 0x00);
+
+        // Set up for 50Hz, but still getting 60Hz
+        cia.write(0xdc0e, 0x80);
+        tick(&mut cia, 60);
+        assert_eq!(cia.read(0xdc09), 0x02);
+        assert_eq!(cia.read(0xdc08), 0x02);
+    }
+
+    #[test]
+    fn should_carry_into_hours() {
+        let mut cia = Cia::new();
+        cia.write(0xdc0b, 0x11);
+        cia.write(0xdc0a, 0x59);
+        cia.write(0xdc09, 0x59);
+        cia.write(0xdc08, 0x09);
+
+        tick(&mut cia, 6);
+        assert_eq!(cia.read(0xdc0b), 0x92);
+        assert_eq!(cia.read(0xdc0a), 0x00);
+        assert_eq!(cia.read(0xdc09), 0x00);
+        assert_eq!(cia.read(0xdc08), 0x00);
+    }
+
+    #[test]
+    fn should_latch_time_of_day_when_reading_hours() {
+        let mut cia = Cia::new();
+        cia.write(0xdc0b, 0x01);
+        cia.write(0xdc08, 0x00);
+
+        assert_eq!(cia.read(0xdc0b), 0x01);
+        tick(&mut cia, 6);
+        assert_eq!(cia.read(0xdc08), 0x00);
+
+        // Until the tenths have been read
+        assert_eq!(cia.read(0xdc08), 0x01);
+    }
+
+    #[test]
+    fn should_stop_time_of_day_when_writing_hours() {
+        let mut cia = Cia::new();
+        cia.write(0xdc0b, 0x01);
+        tick(&mut cia, 6);
+        assert_eq!(cia.read(0xdc08), 0x00);
+
+        cia.write(0xdc08, 0x05);
+        tick(&mut cia, 6);
+        assert_eq!(cia.read(0xdc08), 0x06);
+
+        // Writing the alarm leaves the clock running
+        cia.write(0xdc0f, 0x80);
+        cia.write(0xdc0b, 0x02);
+        tick(&mut cia, 6);
+        assert_eq!(cia.read(0xdc0b), 0x01);
+        assert_eq!(cia.read(0xdc08), 0x07);
+    }
+
+    #[test]
+    fn should_interrupt_when_timer_a_underflows() {
+        let mut cia = Cia::new();
+        cia.write(0xdc04, 0x03);
+        cia.write(0xdc05, 0x00);
+        cia.write(0xdc0d, 0x81);
+        cia.write(0xdc0e, 0x01);
+
+        // Counting 3, 2, 1, 0 before reloading
+        for _ in 0..3 {
+            cia.tick();
+        }
+        assert_eq!(cia.read(0xdc04), 0x00);
+        assert!(!cia.interrupt());
+
+        cia.tick();
+        assert!(cia.interrupt());
+        assert_eq!(cia.read(0xdc04), 0x03);
+
+        // Reading the flags acknowledges the interrupt
+        assert_eq!(cia.read(0xdc0d), 0x81);
+        assert!(!cia.interrupt());
+        assert_eq!(cia.read(0xdc0d), 0x00);
+
+        // Masked, the flag is set without interrupting
+        cia.write(0xdc0d, 0x01);
+        for _ in 0..4 {
+            cia.tick();
+        }
+        assert!(!cia.interrupt());
+        assert_eq!(cia.read(0xdc0d), 0x01);
+    }
+
+    #[test]
+    fn should_stop_one_shot_timer() {
+        let mut cia = Cia::new();
+        cia.write(0xdc06, 0x01);
+        cia.write(0xdc07, 0x00);
+        cia.write(0xdc0f, 0x09);
+
+        cia.tick();
+        cia.tick();
+        assert_eq!(cia.peek(0xdc0d), 0x02);
+        assert_eq!(cia.peek(0xdc0f) & 0x01, 0x00);
+
+        // Forcing a load of the latch
+        cia.write(0xdc07, 0x12);
+        cia.write(0xdc0f, 0x10);
+        assert_eq!(cia.read(0xdc07), 0x12);
+    }
+
+    #[test]
+    fn should_count_underflows_of_timer_a_with_timer_b() {
+        let mut cia = Cia::new();
+        cia.write(0xdc04, 0x09);
+        cia.write(0xdc05, 0x00);
+        cia.write(0xdc06, 0x02);
+        cia.write(0xdc07, 0x00);
+        cia.write(0xdc0f, 0x41);
+        cia.write(0xdc0e, 0x01);
+
+        // Timer B underflows after 3 underflows of timer A, 10 cycles each
+        for _ in 0..29 {
+            cia.tick();
+        }
+        assert_eq!(cia.peek(0xdc0d) & 0x02, 0x00);
+        cia.tick();
+        assert_eq!(cia.peek(0xdc0d) & 0x02, 0x02);
+    }
+}
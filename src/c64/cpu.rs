@@ -1,28 +1,34 @@
 #![allow(non_snake_case)]
-/*
-  The 6510 microprocessor is a relatively simple 8 bit CPU with only a few internal
-  registers capable of addressing at most 64kb of memory via it's 16 bit address bus.
-  The processor is little endian and expects addresses to be stored in memory least
-  significant byte first.
+//! The 6510 microprocessor is a relatively simple 8 bit CPU with only a few internal
+//! registers capable of addressing at most 64kb of memory via it's 16 bit address bus.
+//! The processor is little endian and expects addresses to be stored in memory least
+//! significant byte first.
+//!
+//! The first 256 byte page of memory ($0000-$00ff) is referred to as 'Zero Page'
+//! and is the focus of a number of special addressing modes that result in shorter
+//! (and quicker) instructions or allow indirect access to the memory.
+//!
+//! The second page of memory ($0100-$01ff) is reserved for the system stack and
+//! which cannot be relocated.
+//!
+//! The only other reserved locations in the memory map are the very last 6 bytes
+//! of the memory $fffa-$ffff which must be programmed with the addresses of the
+//! non-maskable interrupt handler ($fffa/b), the power on reset location ($fffc/d)
+//! and the BRK/interrupt request handler ($fffe/f) respectively.
+//!
+//! The 6510 does not have any special support of hardware devices so they must be
+//! mapped to regions of memory in order to exchanges data with the hardware latches.
 
-  The first 256 byte page of memory ($0000-$00ff) is referred to as 'Zero Page'
-  and is the focus of a number of special addressing modes that result in shorter
-  (and quicker) instructions or allow indirect access to the memory.
-
-  The second page of memory ($0100-$01ff) is reserved for the system stack and
-  which cannot be relocated.
-
-  The only other reserved locations in the memory map are the very last 6 bytes
-  of the memory $fffa-$ffff which must be programmed with the addresses of the
-  non-maskable interrupt handler ($fffa/b), the power on reset location ($fffc/d)
-  and the BRK/interrupt request handler ($fffe/f) respectively.
+use super::bus::Bus;
+use super::{decode, AddressingMode, Instruction};
 
-  The 6510 does not have any special support of hardware devices so they must be
-  mapped to regions of memory in order to exchanges data with the hardware latches.
-*/
+const STACK: u16 = 0x0100;
 
-use super::bus::Bus;
+const NMI_VECTOR: u16 = 0xfffa;
+const RESET_VECTOR: u16 = 0xfffc;
+const IRQ_VECTOR: u16 = 0xfffe;
 
+/// The 6510, with its registers
 #[derive(Clone, Default, Debug)]
 pub struct Cpu {
     /// Program Counter
@@ -32,7 +38,10 @@ pub struct Cpu {
     /// are executed
     pub PC: u16,
     /// Stack Pointer
-    pub SP: u16,
+    ///
+    /// The stack is fixed to the second page of memory, $0100-$01ff, so the stack
+    /// pointer only holds the low byte of the address
+    pub SP: u8,
     /// Accumulator
     pub A: u8,
     /// Index register X
@@ -42,18 +51,43 @@ pub struct Cpu {
     /// Status Registers
     pub SR: u8,
 
-    // The amount of cycles still left for the last operation to complete
-    cycles: u8,
+    // The amount of cycles executed since power on, every access to the bus
+    // is one cycle, including the "dummy" reads and writes a real 6510 does.
+    cycles: u64,
+
+    // The reset sequence is run as the next step after a reset
+    resetting: bool,
+
+    // NMI is taken when the line goes from high to low, so the level it had
+    // in the last step is kept to find the edge
+    nmi_line: bool,
 
     // The connected bus
     bus: Option<Box<Bus>>,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum Interrupt {
+    Brk,
+    Irq,
+    Nmi,
+    Reset,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
 impl Cpu {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// The PC is left pointing at the reset vector, and the address to start
+    /// executing at is read from there as the first step after the reset.
     pub fn reset(&mut self) {
         self.PC = 0xfffc;
         self.SP = 0x00;
@@ -61,14 +95,483 @@ impl Cpu {
         self.X = 0x00;
         self.Y = 0x00;
         self.clear_flag(StatusFlags::D);
+        self.resetting = true;
+    }
+
+    /// Executes the next instruction, returning the amount of cycles it took.
+    ///
+    /// Instead of counting down the cycles of each instruction, the bus is
+    /// clocked on every read and write, so everything connected to the bus
+    /// sees the accesses happen at the same cycle as on real hardware.
+    pub fn step(&mut self) -> u32 {
+        let start = self.cycles;
+
+        let nmi = self.bus.as_ref().is_some_and(|bus| bus.nmi());
+        let nmi_edge = nmi && !self.nmi_line;
+        self.nmi_line = nmi;
+
+        if self.resetting {
+            self.resetting = false;
+            self.interrupt(Interrupt::Reset);
+        } else if nmi_edge {
+            self.interrupt(Interrupt::Nmi);
+        } else if self.irq() && !self.get_flag(StatusFlags::I) {
+            self.interrupt(Interrupt::Irq);
+        } else {
+            self.execute();
+        }
+        (self.cycles - start) as u32
+    }
+
+    /// Continues executing at the address, instead of any pending reset
+    pub fn jump(&mut self, address: u16) {
+        self.PC = address;
+        self.resetting = false;
     }
 
-    pub fn clock() {
-        // Should this function handle the logic of fetching an op-code and it's
-        // corresponding fetching of data?
+    /// The cycles executed since power on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn irq(&self) -> bool {
+        self.bus.as_ref().is_some_and(|bus| bus.irq())
+    }
+
+    fn execute(&mut self) {
+        use AddressingMode::*;
+        use StatusFlags::*;
+
+        let opcode = self.fetch();
+        let Instruction { name, mode, .. } = decode(&opcode);
+
+        match (name.as_str(), *mode) {
+            // Branch Instructions
+            ("BCC", _) => self.branch(!self.get_flag(C)),
+            ("BCS", _) => self.branch(self.get_flag(C)),
+            ("BNE", _) => self.branch(!self.get_flag(Z)),
+            ("BEQ", _) => self.branch(self.get_flag(Z)),
+            ("BPL", _) => self.branch(!self.get_flag(N)),
+            ("BMI", _) => self.branch(self.get_flag(N)),
+            ("BVC", _) => self.branch(!self.get_flag(V)),
+            ("BVS", _) => self.branch(self.get_flag(V)),
 
-        // Maybe a simple start could be to keep a counter of the remaining cycles
-        // to work through for the last op?
+            // Jump Instructions
+            ("JMP", Absolute) => self.PC = self.fetch_word(),
+            ("JMP", Indirect) => {
+                let pointer = self.fetch_word();
+                // The high byte is read without carrying into the next page
+                let lo = self.read_cycle(pointer);
+                let hi = self.read_cycle((pointer & 0xff00) | (pointer.wrapping_add(1) & 0x00ff));
+                self.PC = u16::from_le_bytes([lo, hi]);
+            }
+            ("JSR", _) => {
+                let lo = self.fetch();
+                self.read_cycle(STACK | self.SP as u16);
+                self.push((self.PC >> 8) as u8);
+                self.push(self.PC as u8);
+                let hi = self.fetch();
+                self.PC = u16::from_le_bytes([lo, hi]);
+            }
+            ("RTS", _) => {
+                self.read_cycle(self.PC);
+                self.read_cycle(STACK | self.SP as u16);
+                let lo = self.pull();
+                let hi = self.pull();
+                self.PC = u16::from_le_bytes([lo, hi]);
+                self.read_cycle(self.PC);
+                self.PC = self.PC.wrapping_add(1);
+            }
+            ("RTI", _) => {
+                self.read_cycle(self.PC);
+                self.read_cycle(STACK | self.SP as u16);
+                let status = self.pull();
+                self.set_status(status);
+                let lo = self.pull();
+                let hi = self.pull();
+                self.PC = u16::from_le_bytes([lo, hi]);
+            }
+            ("BRK", _) => {
+                self.fetch();
+                self.interrupt(Interrupt::Brk);
+            }
+
+            // Stack Instructions
+            ("PHA", _) => {
+                self.read_cycle(self.PC);
+                self.push(self.A);
+            }
+            ("PHP", _) => {
+                self.read_cycle(self.PC);
+                self.push(self.SR | B as u8 | U as u8);
+            }
+            ("PLA", _) => {
+                self.read_cycle(self.PC);
+                self.read_cycle(STACK | self.SP as u16);
+                self.A = self.pull();
+                self.set_nz(self.A);
+            }
+            ("PLP", _) => {
+                self.read_cycle(self.PC);
+                self.read_cycle(STACK | self.SP as u16);
+                let status = self.pull();
+                self.set_status(status);
+            }
+
+            // Register, flag and accumulator instructions, unknown opcodes
+            // are treated as a NOP for now.
+            (name, Implied) => {
+                self.read_cycle(self.PC);
+                self.implied(name);
+            }
+
+            ("STA" | "STX" | "STY", mode) => {
+                let address = self.address(mode, Access::Write);
+                let value = match name.as_str() {
+                    "STA" => self.A,
+                    "STX" => self.X,
+                    _ => self.Y,
+                };
+                self.write_cycle(address, value);
+            }
+
+            ("ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC", mode) => {
+                let address = self.address(mode, Access::Modify);
+                let value = self.read_cycle(address);
+                // The unmodified value is written back first
+                self.write_cycle(address, value);
+                let value = self.modify(name, value);
+                self.write_cycle(address, value);
+            }
+
+            (name, Immediate) => {
+                let value = self.fetch();
+                self.operate(name, value);
+            }
+            (name, mode) => {
+                let address = self.address(mode, Access::Read);
+                let value = self.read_cycle(address);
+                self.operate(name, value);
+            }
+        }
+    }
+
+    // Instructions that only read a value from memory
+    fn operate(&mut self, name: &str, value: u8) {
+        use StatusFlags::*;
+
+        match name {
+            "LDA" => {
+                self.A = value;
+                self.set_nz(value);
+            }
+            "LDX" => {
+                self.X = value;
+                self.set_nz(value);
+            }
+            "LDY" => {
+                self.Y = value;
+                self.set_nz(value);
+            }
+            "AND" => {
+                self.A &= value;
+                self.set_nz(self.A);
+            }
+            "ORA" => {
+                self.A |= value;
+                self.set_nz(self.A);
+            }
+            "EOR" => {
+                self.A ^= value;
+                self.set_nz(self.A);
+            }
+            "ADC" => self.add(value),
+            "SBC" => self.subtract(value),
+            "CMP" => self.compare(self.A, value),
+            "CPX" => self.compare(self.X, value),
+            "CPY" => self.compare(self.Y, value),
+            "BIT" => {
+                self.update_flag(Z, self.A & value == 0);
+                self.update_flag(N, value & 0x80 != 0);
+                self.update_flag(V, value & 0x40 != 0);
+            }
+            _ => unreachable!("{name} doesn't read from memory"),
+        }
+    }
+
+    // Instructions that modify a value in memory, or in the accumulator
+    fn modify(&mut self, name: &str, value: u8) -> u8 {
+        use StatusFlags::*;
+
+        let carry = self.get_flag(C) as u8;
+        let result = match name {
+            "ASL" => {
+                self.update_flag(C, value & 0x80 != 0);
+                value << 1
+            }
+            "LSR" => {
+                self.update_flag(C, value & 0x01 != 0);
+                value >> 1
+            }
+            "ROL" => {
+                self.update_flag(C, value & 0x80 != 0);
+                value << 1 | carry
+            }
+            "ROR" => {
+                self.update_flag(C, value & 0x01 != 0);
+                value >> 1 | carry << 7
+            }
+            "INC" => value.wrapping_add(1),
+            "DEC" => value.wrapping_sub(1),
+            _ => unreachable!("{name} doesn't modify memory"),
+        };
+        self.set_nz(result);
+        result
+    }
+
+    fn implied(&mut self, name: &str) {
+        use StatusFlags::*;
+
+        match name {
+            "TAX" => {
+                self.X = self.A;
+                self.set_nz(self.X);
+            }
+            "TAY" => {
+                self.Y = self.A;
+                self.set_nz(self.Y);
+            }
+            "TXA" => {
+                self.A = self.X;
+                self.set_nz(self.A);
+            }
+            "TYA" => {
+                self.A = self.Y;
+                self.set_nz(self.A);
+            }
+            "TSX" => {
+                self.X = self.SP;
+                self.set_nz(self.X);
+            }
+            "TXS" => self.SP = self.X,
+            "INX" => {
+                self.X = self.X.wrapping_add(1);
+                self.set_nz(self.X);
+            }
+            "INY" => {
+                self.Y = self.Y.wrapping_add(1);
+                self.set_nz(self.Y);
+            }
+            "DEX" => {
+                self.X = self.X.wrapping_sub(1);
+                self.set_nz(self.X);
+            }
+            "DEY" => {
+                self.Y = self.Y.wrapping_sub(1);
+                self.set_nz(self.Y);
+            }
+            "CLC" => self.clear_flag(C),
+            "CLD" => self.clear_flag(D),
+            "CLI" => self.clear_flag(I),
+            "CLV" => self.clear_flag(V),
+            "SEC" => self.set_flag(C),
+            "SED" => self.set_flag(D),
+            "SEI" => self.set_flag(I),
+            "ASL" | "LSR" | "ROL" | "ROR" => self.A = self.modify(name, self.A),
+            _ => {}
+        }
+    }
+
+    fn add(&mut self, value: u8) {
+        use StatusFlags::*;
+
+        let a = self.A as u16;
+        let v = value as u16;
+        let carry = self.get_flag(C) as u16;
+
+        if self.get_flag(D) {
+            // The NMOS 6502 sets Z from the binary result, while N and V
+            // come from the intermediate result before the high nibble
+            // is adjusted.
+            let mut lo = (a & 0x0f) + (v & 0x0f) + carry;
+            if lo > 0x09 {
+                lo += 0x06;
+            }
+            let mut hi = (a >> 4) + (v >> 4) + (lo > 0x0f) as u16;
+
+            self.update_flag(Z, (a + v + carry) & 0xff == 0);
+            self.update_flag(N, hi & 0x08 != 0);
+            self.update_flag(V, ((hi << 4) ^ a) & 0x80 != 0 && (a ^ v) & 0x80 == 0);
+
+            if hi > 0x09 {
+                hi += 0x06;
+            }
+            self.update_flag(C, hi > 0x0f);
+            self.A = ((hi << 4) | (lo & 0x0f)) as u8;
+        } else {
+            let sum = a + v + carry;
+            self.update_flag(C, sum > 0xff);
+            self.update_flag(V, (!(a ^ v) & (a ^ sum)) & 0x80 != 0);
+            self.A = sum as u8;
+            self.set_nz(self.A);
+        }
+    }
+
+    fn subtract(&mut self, value: u8) {
+        use StatusFlags::*;
+
+        let a = self.A as i16;
+        let v = value as i16;
+        let borrow = !self.get_flag(C) as i16;
+
+        // The flags are always set from the binary result
+        let difference = a - v - borrow;
+        self.update_flag(C, difference >= 0);
+        self.update_flag(V, ((a ^ v) & (a ^ difference)) & 0x80 != 0);
+        self.set_nz(difference as u8);
+
+        if self.get_flag(D) {
+            let mut lo = (a & 0x0f) - (v & 0x0f) - borrow;
+            let mut hi = (a >> 4) - (v >> 4);
+            if lo < 0 {
+                lo -= 0x06;
+                hi -= 1;
+            }
+            if hi < 0 {
+                hi -= 0x06;
+            }
+            self.A = ((hi << 4) | (lo & 0x0f)) as u8;
+        } else {
+            self.A = difference as u8;
+        }
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.update_flag(StatusFlags::C, register >= value);
+        self.set_nz(register.wrapping_sub(value));
+    }
+
+    fn branch(&mut self, condition: bool) {
+        let offset = self.fetch();
+
+        if condition {
+            self.read_cycle(self.PC);
+            let target = self.PC.wrapping_add(offset as i8 as u16);
+
+            // One more cycle to fix up the high byte when crossing a page
+            if target & 0xff00 != self.PC & 0xff00 {
+                self.read_cycle((self.PC & 0xff00) | (target & 0x00ff));
+            }
+            self.PC = target;
+        }
+    }
+
+    // BRK, IRQ and NMI push the PC and status to the stack before jumping
+    // through their vector, while reset only goes through the motions.
+    fn interrupt(&mut self, kind: Interrupt) {
+        if kind != Interrupt::Brk {
+            self.read_cycle(self.PC);
+            self.read_cycle(self.PC);
+        }
+
+        if kind == Interrupt::Reset {
+            for _ in 0..3 {
+                self.read_cycle(STACK | self.SP as u16);
+                self.SP = self.SP.wrapping_sub(1);
+            }
+        } else {
+            self.push((self.PC >> 8) as u8);
+            self.push(self.PC as u8);
+
+            let status = match kind {
+                Interrupt::Brk => self.SR | StatusFlags::B as u8,
+                _ => self.SR & !(StatusFlags::B as u8),
+            };
+            self.push(status | StatusFlags::U as u8);
+        }
+        self.set_flag(StatusFlags::I);
+
+        let vector = match kind {
+            Interrupt::Nmi => NMI_VECTOR,
+            Interrupt::Reset => RESET_VECTOR,
+            Interrupt::Brk | Interrupt::Irq => IRQ_VECTOR,
+        };
+        let lo = self.read_cycle(vector);
+        let hi = self.read_cycle(vector + 1);
+        self.PC = u16::from_le_bytes([lo, hi]);
+    }
+
+    // Resolves the address to operate on, including the extra reads the
+    // 6510 does while it adds the index registers.
+    fn address(&mut self, mode: AddressingMode, access: Access) -> u16 {
+        use AddressingMode::*;
+
+        match mode {
+            ZeroPage => self.fetch() as u16,
+            ZeroPageX | ZeroPageY => {
+                let base = self.fetch();
+                self.read_cycle(base as u16);
+                let index = if mode == ZeroPageX { self.X } else { self.Y };
+                base.wrapping_add(index) as u16
+            }
+            Absolute => self.fetch_word(),
+            AbsoluteX => {
+                let base = self.fetch_word();
+                self.indexed(base, self.X, access)
+            }
+            AbsoluteY => {
+                let base = self.fetch_word();
+                self.indexed(base, self.Y, access)
+            }
+            IndirectX => {
+                let pointer = self.fetch();
+                self.read_cycle(pointer as u16);
+                let pointer = pointer.wrapping_add(self.X);
+                let lo = self.read_cycle(pointer as u16);
+                let hi = self.read_cycle(pointer.wrapping_add(1) as u16);
+                u16::from_le_bytes([lo, hi])
+            }
+            IndirectY => {
+                let pointer = self.fetch();
+                let lo = self.read_cycle(pointer as u16);
+                let hi = self.read_cycle(pointer.wrapping_add(1) as u16);
+                self.indexed(u16::from_le_bytes([lo, hi]), self.Y, access)
+            }
+            _ => unreachable!("{mode:?} doesn't address memory"),
+        }
+    }
+
+    // Reads and writes always take the extra cycle, reads only when the
+    // index makes the address cross into the next page.
+    fn indexed(&mut self, base: u16, index: u8, access: Access) -> u16 {
+        let address = base.wrapping_add(index as u16);
+
+        if access != Access::Read || address & 0xff00 != base & 0xff00 {
+            self.read_cycle((base & 0xff00) | (address & 0x00ff));
+        }
+        address
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let value = self.read_cycle(self.PC);
+        self.PC = self.PC.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let lo = self.fetch();
+        let hi = self.fetch();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write_cycle(STACK | self.SP as u16, value);
+        self.SP = self.SP.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.SP = self.SP.wrapping_add(1);
+        self.read_cycle(STACK | self.SP as u16)
     }
 
     // Status Register - SR - Manipulation
@@ -84,14 +587,44 @@ impl Cpu {
         self.SR |= flag as u8;
     }
 
+    fn update_flag(&mut self, flag: StatusFlags, value: bool) {
+        if value {
+            self.set_flag(flag);
+        } else {
+            self.clear_flag(flag);
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) {
+        self.update_flag(StatusFlags::Z, value == 0);
+        self.update_flag(StatusFlags::N, value & 0x80 != 0);
+    }
+
+    // The break and unused bits only exist on the stack
+    fn set_status(&mut self, value: u8) {
+        self.SR = (value | StatusFlags::U as u8) & !(StatusFlags::B as u8);
+    }
+
     // Bus related
+    /// Connects the bus, which everything the CPU reads and writes goes
+    /// through
     pub fn connect_bus(&mut self, bus: Bus) {
         self.bus = Some(Box::new(bus))
     }
 
+    /// The bus, which panics when none is connected
+    pub fn bus(&self) -> &Bus {
+        self.bus.as_ref().expect("no bus connected")
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        self.bus.as_mut().expect("no bus connected")
+    }
+
+    /// Reads without spending any cycles, e.g. for a debugger
     pub fn read(&self, address: usize) -> u8 {
         if let Some(bus) = &self.bus {
-            bus.read(address)
+            bus.peek(address)
         } else {
             0x00
         }
@@ -102,8 +635,32 @@ impl Cpu {
             bus.write(address, value);
         }
     }
+
+    // Reads are halted for as long as RDY is low, while writes aren't
+    fn read_cycle(&mut self, address: u16) -> u8 {
+        self.cycles += 1;
+        if let Some(bus) = &mut self.bus {
+            while !bus.rdy() {
+                bus.tick();
+                self.cycles += 1;
+            }
+            bus.tick();
+            bus.read(address as usize)
+        } else {
+            0x00
+        }
+    }
+
+    fn write_cycle(&mut self, address: u16, value: u8) {
+        self.cycles += 1;
+        if let Some(bus) = &mut self.bus {
+            bus.tick();
+        }
+        self.write(address as usize, value);
+    }
 }
 
+/// The bits of the status register
 #[derive(Clone, Copy)]
 #[repr(u8)]
 pub enum StatusFlags {
@@ -128,6 +685,7 @@ pub enum StatusFlags {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::c64::Block;
 
     #[test]
     fn test_reset() {
@@ -156,4 +714,367 @@ mod tests {
         cpu.clear_flag(StatusFlags::D);
         assert!(!cpu.get_flag(StatusFlags::D));
     }
+
+    // Assembles the source, starting at $c000, and runs it until it reaches a BRK
+    fn run(source: &str) -> Cpu {
+        let block = Block::assemble(&format!("*= $c000\n{source}"));
+
+        let mut cpu = Cpu::new();
+        cpu.connect_bus(Bus::new([0; 0x10000]));
+        for (offset, byte) in block.instructions.iter().enumerate() {
+            cpu.write(0xc000 + offset, *byte);
+        }
+        cpu.jump(0xc000);
+
+        while cpu.read(cpu.PC as usize) != 0x00 {
+            cpu.step();
+        }
+        cpu
+    }
+
+    fn cycles(source: &str) -> u64 {
+        run(source).cycles()
+    }
+
+    #[test]
+    fn should_start_at_reset_vector() {
+        let mut cpu = Cpu::new();
+        cpu.connect_bus(Bus::new([0; 0x10000]));
+        cpu.write(0xfffc, 0x34);
+        cpu.write(0xfffd, 0x12);
+
+        cpu.reset();
+        assert_eq!(cpu.step(), 7);
+        assert_eq!(cpu.PC, 0x1234);
+        assert_eq!(cpu.SP, 0xfd);
+        assert!(cpu.get_flag(StatusFlags::I));
+    }
+
+    #[test]
+    fn should_load_and_store() {
+        let cpu = run("
+            LDA #$42
+            STA $0200
+            LDX $0200
+            STX $10
+            LDY $10
+        ");
+
+        assert_eq!(cpu.read(0x0200), 0x42);
+        assert_eq!(cpu.X, 0x42);
+        assert_eq!(cpu.Y, 0x42);
+    }
+
+    #[test]
+    fn should_add_with_carry_and_overflow() {
+        let cpu = run("
+            CLC
+            LDA #$50
+            ADC #$50
+        ");
+        assert_eq!(cpu.A, 0xa0);
+        assert!(cpu.get_flag(StatusFlags::V));
+        assert!(cpu.get_flag(StatusFlags::N));
+        assert!(!cpu.get_flag(StatusFlags::C));
+
+        let cpu = run("
+            SEC
+            LDA #$FF
+            ADC #$00
+        ");
+        assert_eq!(cpu.A, 0x00);
+        assert!(cpu.get_flag(StatusFlags::Z));
+        assert!(cpu.get_flag(StatusFlags::C));
+    }
+
+    #[test]
+    fn should_subtract_with_borrow() {
+        let cpu = run("
+            SEC
+            LDA #$10
+            SBC #$20
+        ");
+        assert_eq!(cpu.A, 0xf0);
+        assert!(!cpu.get_flag(StatusFlags::C));
+        assert!(cpu.get_flag(StatusFlags::N));
+
+        let cpu = run("
+            CLC
+            LDA #$10
+            SBC #$0F
+        ");
+        assert_eq!(cpu.A, 0x00);
+        assert!(cpu.get_flag(StatusFlags::C));
+    }
+
+    #[test]
+    fn should_add_and_subtract_in_decimal_mode() {
+        let cpu = run("
+            SED
+            CLC
+            LDA #$19
+            ADC #$28
+        ");
+        assert_eq!(cpu.A, 0x47);
+
+        let cpu = run("
+            SED
+            CLC
+            LDA #$99
+            ADC #$01
+        ");
+        assert_eq!(cpu.A, 0x00);
+        assert!(cpu.get_flag(StatusFlags::C));
+
+        let cpu = run("
+            SED
+            SEC
+            LDA #$10
+            SBC #$01
+        ");
+        assert_eq!(cpu.A, 0x09);
+        assert!(cpu.get_flag(StatusFlags::C));
+    }
+
+    #[test]
+    fn should_run_a_loop() {
+        // Sum 1..10
+        let cpu = run("
+            LDA #$00
+            LDX #$0A
+            STX $10
+            CLC
+            ADC $10
+            DEC $10
+            BNE $C006
+        ");
+        assert_eq!(cpu.A, 55);
+        assert_eq!(cpu.read(0x10), 0x00);
+    }
+
+    #[test]
+    fn should_call_and_return() {
+        let cpu = run("
+            LDX #$FF
+            TXS
+            JSR $C008
+            INY
+            BRK
+            LDY #$10
+            PHA
+            PLA
+            RTS
+        ");
+        assert_eq!(cpu.Y, 0x11);
+        assert_eq!(cpu.SP, 0xff);
+        assert_eq!(cpu.read(0x01ff), 0xc0);
+        assert_eq!(cpu.read(0x01fe), 0x05);
+    }
+
+    #[test]
+    fn should_shift_and_rotate() {
+        let cpu = run("
+            LDA #$81
+            ASL
+            ROL
+            STA $20
+            LSR $20
+            ROR $20
+        ");
+        assert_eq!(cpu.A, 0x05);
+        assert_eq!(cpu.read(0x20), 0x81);
+        assert!(!cpu.get_flag(StatusFlags::C));
+    }
+
+    #[test]
+    fn should_compare() {
+        let cpu = run("
+            LDA #$40
+            CMP #$40
+        ");
+        assert!(cpu.get_flag(StatusFlags::Z));
+        assert!(cpu.get_flag(StatusFlags::C));
+
+        let cpu = run("
+            LDY #$10
+            CPY #$20
+        ");
+        assert!(!cpu.get_flag(StatusFlags::C));
+        assert!(cpu.get_flag(StatusFlags::N));
+    }
+
+    #[test]
+    fn should_use_indirect_addressing() {
+        let cpu = run("
+            LDA #$00
+            STA $FE
+            LDA #$04
+            STA $FF
+            LDY #$05
+            LDA #$2A
+            STA ($FE),Y
+            LDX #$02
+            LDA ($FC,X)
+        ");
+        assert_eq!(cpu.read(0x0405), 0x2a);
+        assert_eq!(cpu.A, 0x00);
+    }
+
+    #[test]
+    fn should_wrap_indirect_jump_within_page() {
+        let mut cpu = Cpu::new();
+        cpu.connect_bus(Bus::new([0; 0x10000]));
+        cpu.write(0x10ff, 0x34);
+        cpu.write(0x1000, 0x12);
+        cpu.write(0x1100, 0x56);
+        for (offset, byte) in [0x6c, 0xff, 0x10].iter().enumerate() {
+            cpu.write(0xc000 + offset, *byte);
+        }
+        cpu.jump(0xc000);
+
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.PC, 0x1234);
+    }
+
+    #[test]
+    fn should_count_cycles() {
+        assert_eq!(cycles("LDA #$00"), 2);
+        assert_eq!(cycles("LDA $1000"), 4);
+        assert_eq!(cycles("STA $1000"), 4);
+        assert_eq!(cycles("INC $1000"), 6);
+        assert_eq!(cycles("NOP"), 2);
+        assert_eq!(cycles("PHA"), 3);
+        assert_eq!(cycles("PLA"), 4);
+
+        // Page crossing costs one more cycle for reads, but always for writes
+        assert_eq!(cycles("LDX #$01\nLDA $1000,X"), 2 + 4);
+        assert_eq!(cycles("LDX #$01\nLDA $10FF,X"), 2 + 5);
+        assert_eq!(cycles("LDX #$01\nSTA $1000,X"), 2 + 5);
+        assert_eq!(cycles("LDX #$01\nINC $1000,X"), 2 + 7);
+
+        // Branches taken cost one more cycle, two when crossing a page
+        assert_eq!(cycles("LDA #$01\nBNE $C004"), 2 + 3);
+        assert_eq!(cycles("LDA #$00\nBNE $C004"), 2 + 2);
+        assert_eq!(cycles("LDA #$01\nBNE $BFF0"), 2 + 4);
+    }
+
+    #[test]
+    fn should_break_and_return_from_interrupt() {
+        let mut cpu = Cpu::new();
+        cpu.connect_bus(Bus::new([0; 0x10000]));
+        cpu.write(0xfffe, 0x00);
+        cpu.write(0xffff, 0x20);
+        cpu.write(0x2000, 0x40); // RTI
+        cpu.jump(0xc000);
+        cpu.SP = 0xff;
+        cpu.SR = StatusFlags::C as u8;
+
+        assert_eq!(cpu.step(), 7);
+        assert_eq!(cpu.PC, 0x2000);
+        assert!(cpu.get_flag(StatusFlags::I));
+        assert_eq!(
+            cpu.read(0x01fd) & StatusFlags::B as u8,
+            StatusFlags::B as u8
+        );
+
+        assert_eq!(cpu.step(), 6);
+        assert_eq!(cpu.PC, 0xc002);
+        assert!(cpu.get_flag(StatusFlags::C));
+        assert!(!cpu.get_flag(StatusFlags::I));
+    }
+
+    #[test]
+    fn should_take_irq_unless_masked() {
+        let mut cpu = Cpu::new();
+        cpu.connect_bus(Bus::new([0; 0x10000]));
+        cpu.write(0xfffe, 0x00);
+        cpu.write(0xffff, 0x20);
+        // JMP $C000
+        for (offset, byte) in [0x4c, 0x00, 0xc0].iter().enumerate() {
+            cpu.write(0xc000 + offset, *byte);
+        }
+        cpu.jump(0xc000);
+        cpu.SP = 0xff;
+        cpu.set_flag(StatusFlags::I);
+
+        // A raster interrupt on line 1
+        cpu.write(0xd012, 0x01);
+        cpu.write(0xd01a, 0x01);
+
+        while cpu.cycles() < 100 {
+            cpu.step();
+        }
+        assert_eq!(cpu.PC, 0xc000);
+
+        cpu.clear_flag(StatusFlags::I);
+        assert_eq!(cpu.step(), 7);
+        assert_eq!(cpu.PC, 0x2000);
+        assert!(cpu.get_flag(StatusFlags::I));
+        assert_eq!(cpu.read(0x01fe), 0x00);
+        assert_eq!(cpu.read(0x01ff), 0xc0);
+    }
+
+    #[test]
+    fn should_take_nmi_on_edge_even_when_masked() {
+        let mut cpu = Cpu::new();
+        cpu.connect_bus(Bus::new([0; 0x10000]));
+        cpu.write(0xfffa, 0x00);
+        cpu.write(0xfffb, 0x30);
+        // JMP $C000, with the NMI handler looping as well
+        for (offset, byte) in [0x4c, 0x00, 0xc0].iter().enumerate() {
+            cpu.write(0xc000 + offset, *byte);
+        }
+        for (offset, byte) in [0x4c, 0x00, 0x30].iter().enumerate() {
+            cpu.write(0x3000 + offset, *byte);
+        }
+        cpu.jump(0xc000);
+        cpu.SP = 0xff;
+        cpu.set_flag(StatusFlags::I);
+
+        // Timer A of CIA 2 underflowing after 20 cycles
+        cpu.write(0xdd04, 20);
+        cpu.write(0xdd05, 0);
+        cpu.write(0xdd0d, 0x81);
+        cpu.write(0xdd0e, 0x01);
+
+        while cpu.PC != 0x3000 {
+            assert!(cpu.cycles() < 100);
+            cpu.step();
+        }
+        assert_eq!(cpu.read(0x01fe), 0x00);
+        assert_eq!(cpu.read(0x01ff), 0xc0);
+
+        // The line stays low until acknowledged, which isn't another edge
+        let sp = cpu.SP;
+        for _ in 0..20 {
+            cpu.step();
+        }
+        assert_eq!(cpu.SP, sp);
+    }
+
+    #[test]
+    fn should_only_halt_on_reads_during_bad_lines() {
+        // INC $1000 reads for 4 cycles and then writes for 2
+        let instruction_at = |cycle: u16| {
+            let mut bus = Bus::new([0; 0x10000]);
+            bus.write(0xc000, 0xee);
+            bus.write(0xc001, 0x00);
+            bus.write(0xc002, 0x10);
+            bus.write(0xd011, 0x1b);
+            while (bus.vic().raster_line(), bus.vic().cycle()) != (0x33, cycle) {
+                bus.tick();
+            }
+
+            let mut cpu = Cpu::new();
+            cpu.connect_bus(bus);
+            cpu.jump(0xc000);
+            cpu.step()
+        };
+
+        // The writes at cycle 12 and 13 happen after BA is pulled low
+        assert_eq!(instruction_at(8), 6);
+
+        // The last read is halted until the VIC-II is done at cycle 54
+        assert_eq!(instruction_at(9), 6 + 43);
+    }
 }
@@ -0,0 +1,744 @@
+//! A .d64 file is an image of a 1541 floppy, the 256 byte sectors of every
+//! track one after the other. The outer tracks are longer, so they hold more
+//! sectors than the inner ones:
+//!
+//! ```text
+//! tracks  1-17   21 sectors
+//! tracks 18-24   19 sectors
+//! tracks 25-30   18 sectors
+//! tracks 31-40   17 sectors
+//! ```
+//!
+//! Most images have 35 tracks, some have the 40 that a 1541 can reach, and
+//! either of them can have a byte per sector with the read errors at the end.
+//!
+//! Files are chains of sectors, where the first two bytes of a sector point at
+//! the track and sector of the next one. The last sector has track 0, and the
+//! second byte is the position of the last byte used in it instead.
+//!
+//! Track 18 holds the disk itself: sector 0 is the BAM, with the disk name and
+//! ID, and the directory is the chain starting at sector 1, with 8 entries of
+//! 32 bytes in every sector:
+//!
+//! ```text
+//! $02     file type, with bit 7 set when closed and bit 6 when locked
+//! $03-$04 track and sector of the first sector of the file
+//! $05-$14 file name, padded with $a0
+//! $1e-$1f size of the file in sectors, least significant byte first
+//! ```
+//!
+//! The BAM has 4 bytes for every track from $04, the number of free sectors
+//! followed by a bit for every sector, set when it is free. There is only room
+//! for 35 tracks, where the extra tracks of a 40 track image are kept depends
+//! on the DOS that wrote it, so files are only added to the first 35.
+//!
+//! New files go on the tracks closest to the directory first, to keep the
+//! head movements short, with 10 sectors between the sectors of a file so
+//! the next one has yet to pass the head once the 1541 has dealt with the
+//! previous one, the same interleave as the 1541 uses.
+
+use std::fmt::Display;
+use std::path::Path;
+
+use super::petscii::{petscii_to_char, Charset};
+
+pub const SECTOR_SIZE: usize = 256;
+
+const DIRECTORY_TRACK: u8 = 18;
+const BAM_SECTOR: u8 = 0;
+const DIRECTORY_SECTOR: u8 = 1;
+const ENTRY_SIZE: usize = 32;
+
+// Where the disk name and ID are in the BAM
+const DISK_NAME: usize = 0x90;
+const DISK_ID: usize = 0xa2;
+
+// The BAM entries of the tracks, the DOS version and the DOS type
+const BAM_ENTRIES: usize = 0x04;
+const BAM_ENTRY_SIZE: usize = 4;
+const BAM_TRACKS: u8 = 35;
+const DOS_VERSION: u8 = b'A';
+const DOS_TYPE: &[u8] = b"2A";
+
+// The sectors skipped between the sectors of a file, and of the directory
+const FILE_INTERLEAVE: u8 = 10;
+const DIRECTORY_INTERLEAVE: u8 = 3;
+
+const NAME_LENGTH: usize = 16;
+const PADDING: u8 = 0xa0;
+
+// The sectors of the images with 35 and 40 tracks
+const SECTORS_35: usize = 683;
+const SECTORS_40: usize = 768;
+
+// The sizes of the images, without and with the error bytes
+const SIZE_35: usize = SECTORS_35 * SECTOR_SIZE;
+const SIZE_35_ERRORS: usize = SECTORS_35 * (SECTOR_SIZE + 1);
+const SIZE_40: usize = SECTORS_40 * SECTOR_SIZE;
+const SIZE_40_ERRORS: usize = SECTORS_40 * (SECTOR_SIZE + 1);
+
+#[derive(Debug)]
+pub enum D64Error {
+    Io(std::io::Error),
+    /// The size doesn't match any of the kinds of images
+    WrongSize(usize),
+    /// A chain of sectors points at a sector that isn't on the disk
+    BadSector {
+        track: u8,
+        sector: u8,
+    },
+    /// A chain of sectors goes on for longer than there are sectors
+    Loop {
+        track: u8,
+        sector: u8,
+    },
+    /// A name longer than 16 characters, or that can't be written in PETSCII
+    BadName(String),
+    FileExists(String),
+    /// There aren't enough free sectors for the file
+    DiskFull {
+        needed: usize,
+        free: usize,
+    },
+    /// Every sector of the directory track is used
+    DirectoryFull,
+}
+
+impl Display for D64Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            D64Error::Io(err) => write!(f, "unable to access image: {err}"),
+            D64Error::WrongSize(size) => write!(f, "{size} bytes isn't the size of a D64 image"),
+            D64Error::BadSector { track, sector } => {
+                write!(f, "track {track} sector {sector} isn't on the disk")
+            }
+            D64Error::Loop { track, sector } => {
+                write!(
+                    f,
+                    "the chain of sectors from track {track} sector {sector} loops"
+                )
+            }
+            D64Error::BadName(name) => write!(f, "`{name}` can't be the name of a file"),
+            D64Error::FileExists(name) => write!(f, "there is already a file named {name}"),
+            D64Error::DiskFull { needed, free } => {
+                write!(f, "the file needs {needed} blocks, but {free} are free")
+            }
+            D64Error::DirectoryFull => write!(f, "there is no room for more files"),
+        }
+    }
+}
+
+impl std::error::Error for D64Error {}
+
+impl From<std::io::Error> for D64Error {
+    fn from(err: std::io::Error) -> Self {
+        D64Error::Io(err)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileType {
+    Del,
+    Seq,
+    Prg,
+    Usr,
+    Rel,
+}
+
+impl Display for FileType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            FileType::Del => "DEL",
+            FileType::Seq => "SEQ",
+            FileType::Prg => "PRG",
+            FileType::Usr => "USR",
+            FileType::Rel => "REL",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// A file in the directory
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// The name in PETSCII, without the padding
+    pub name: Vec<u8>,
+    pub file_type: FileType,
+    /// Unclosed files, "splat files", were never completely written
+    pub closed: bool,
+    pub locked: bool,
+    pub track: u8,
+    pub sector: u8,
+    /// The size in sectors, as listed, which isn't necessarily the real size
+    pub blocks: u16,
+}
+
+impl Entry {
+    /// The name, decoded the way it is shown after power on
+    pub fn name_text(&self) -> String {
+        decode(&self.name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct D64 {
+    bytes: Vec<u8>,
+    tracks: u8,
+}
+
+impl D64 {
+    /// A formatted disk with 35 tracks and nothing on it, like the NEW command of the DOS
+    pub fn new(name: &str, id: &str) -> Result<Self, D64Error> {
+        let mut d64 = D64 {
+            bytes: vec![0; SIZE_35],
+            tracks: 35,
+        };
+        let name = encode(name, NAME_LENGTH)?;
+        let id = encode(id, 2)?;
+
+        let bam = d64.sector_mut(DIRECTORY_TRACK, BAM_SECTOR)?;
+        bam[0..4].copy_from_slice(&[DIRECTORY_TRACK, DIRECTORY_SECTOR, DOS_VERSION, 0]);
+        bam[DISK_NAME..DISK_ID + 9].fill(PADDING);
+        bam[DISK_NAME..DISK_NAME + name.len()].copy_from_slice(&name);
+        bam[DISK_ID..DISK_ID + id.len()].copy_from_slice(&id);
+        bam[DISK_ID + 3..DISK_ID + 5].copy_from_slice(DOS_TYPE);
+
+        for track in 1..=BAM_TRACKS {
+            for sector in 0..sectors_in_track(track) {
+                d64.set_free(track, sector, true);
+            }
+        }
+        d64.set_free(DIRECTORY_TRACK, BAM_SECTOR, false);
+        d64.set_free(DIRECTORY_TRACK, DIRECTORY_SECTOR, false);
+        d64.sector_mut(DIRECTORY_TRACK, DIRECTORY_SECTOR)?[0..2].copy_from_slice(&[0, 0xff]);
+        Ok(d64)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, D64Error> {
+        let tracks = match bytes.len() {
+            SIZE_35 | SIZE_35_ERRORS => 35,
+            SIZE_40 | SIZE_40_ERRORS => 40,
+            size => return Err(D64Error::WrongSize(size)),
+        };
+        Ok(D64 { bytes, tracks })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, D64Error> {
+        Self::from_bytes(std::fs::read(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), D64Error> {
+        Ok(std::fs::write(path, &self.bytes)?)
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn tracks(&self) -> u8 {
+        self.tracks
+    }
+
+    /// The free sectors, as listed after the directory, which leaves out the
+    /// directory track
+    pub fn blocks_free(&self) -> usize {
+        (1..=BAM_TRACKS)
+            .filter(|track| *track != DIRECTORY_TRACK)
+            .map(|track| self.bam()[bam_entry(track)] as usize)
+            .sum()
+    }
+
+    /// The name of the disk, decoded the way it is shown after power on
+    pub fn name(&self) -> String {
+        let bam = self.bam();
+        decode(unpadded(&bam[DISK_NAME..DISK_NAME + NAME_LENGTH]))
+    }
+
+    pub fn id(&self) -> String {
+        decode(&self.bam()[DISK_ID..DISK_ID + 2])
+    }
+
+    /// The files on the disk, leaving out the entries that aren't used
+    pub fn directory(&self) -> Result<Vec<Entry>, D64Error> {
+        let sectors = self.chain(DIRECTORY_TRACK, DIRECTORY_SECTOR)?;
+
+        let entries = sectors
+            .iter()
+            .flat_map(|sector| sector.chunks_exact(ENTRY_SIZE))
+            .filter_map(|entry| {
+                let file_type = match entry[2] & 0x0f {
+                    _ if entry[2] == 0 => return None,
+                    0 => FileType::Del,
+                    1 => FileType::Seq,
+                    2 => FileType::Prg,
+                    3 => FileType::Usr,
+                    _ => FileType::Rel,
+                };
+                Some(Entry {
+                    name: unpadded(&entry[0x05..0x05 + NAME_LENGTH]).to_vec(),
+                    file_type,
+                    closed: entry[2] & 0x80 != 0,
+                    locked: entry[2] & 0x40 != 0,
+                    track: entry[3],
+                    sector: entry[4],
+                    blocks: u16::from_le_bytes([entry[0x1e], entry[0x1f]]),
+                })
+            })
+            .collect();
+        Ok(entries)
+    }
+
+    /// The first file with a name matching the pattern, where `*` matches the
+    /// rest of the name and `?` any character, the way the 1541 does it
+    pub fn find(&self, pattern: &str) -> Result<Option<Entry>, D64Error> {
+        let found = self
+            .directory()?
+            .into_iter()
+            .find(|entry| entry.file_type != FileType::Del && matches(pattern, &entry.name));
+        Ok(found)
+    }
+
+    /// The contents of the file, which for a PRG starts with the load address
+    pub fn read(&self, entry: &Entry) -> Result<Vec<u8>, D64Error> {
+        let sectors = self.chain(entry.track, entry.sector)?;
+
+        let mut bytes = vec![];
+        for (index, sector) in sectors.iter().enumerate() {
+            let end = match index == sectors.len() - 1 {
+                // The position of the last byte used, at least the first one
+                true => (sector[1] as usize).max(1) + 1,
+                false => SECTOR_SIZE,
+            };
+            bytes.extend_from_slice(&sector[2..end]);
+        }
+        Ok(bytes)
+    }
+
+    /// Writes the file to free sectors and adds it to the directory, where the
+    /// name is typed in ASCII the same way as for `find`
+    pub fn add(&mut self, name: &str, file_type: FileType, data: &[u8]) -> Result<Entry, D64Error> {
+        let name = encode(name, NAME_LENGTH)?;
+        if name.is_empty() {
+            return Err(D64Error::BadName(String::new()));
+        }
+        let exists = self.directory()?.iter().any(|entry| entry.name == name);
+        if exists {
+            return Err(D64Error::FileExists(decode(&name)));
+        }
+
+        // Every sector holds 254 bytes, and even an empty file takes one
+        let chunks: Vec<&[u8]> = match data.is_empty() {
+            true => vec![&[]],
+            false => data.chunks(SECTOR_SIZE - 2).collect(),
+        };
+        let free = self.blocks_free();
+        if chunks.len() > free {
+            return Err(D64Error::DiskFull {
+                needed: chunks.len(),
+                free,
+            });
+        }
+
+        let (slot_track, slot_sector, slot) = self.free_entry()?;
+
+        let mut sectors = vec![];
+        let mut last = None;
+        for _ in &chunks {
+            let (track, sector) = self.allocate(last).expect("there are enough free sectors");
+            sectors.push((track, sector));
+            last = Some((track, sector));
+        }
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            let (track, sector) = sectors[index];
+            let link = match sectors.get(index + 1) {
+                Some(next) => [next.0, next.1],
+                None => [0, chunk.len() as u8 + 1],
+            };
+            let data = self.sector_mut(track, sector)?;
+            data.fill(0);
+            data[0..2].copy_from_slice(&link);
+            data[2..2 + chunk.len()].copy_from_slice(chunk);
+        }
+
+        let entry = Entry {
+            name,
+            file_type,
+            closed: true,
+            locked: false,
+            track: sectors[0].0,
+            sector: sectors[0].1,
+            blocks: sectors.len() as u16,
+        };
+
+        let type_code = match file_type {
+            FileType::Del => 0,
+            FileType::Seq => 1,
+            FileType::Prg => 2,
+            FileType::Usr => 3,
+            FileType::Rel => 4,
+        };
+        let bytes = &mut self.sector_mut(slot_track, slot_sector)?[slot..slot + ENTRY_SIZE];
+        bytes[2..].fill(0);
+        bytes[2..5].copy_from_slice(&[0x80 | type_code, entry.track, entry.sector]);
+        bytes[0x05..0x05 + NAME_LENGTH].fill(PADDING);
+        bytes[0x05..0x05 + entry.name.len()].copy_from_slice(&entry.name);
+        bytes[0x1e..0x20].copy_from_slice(&entry.blocks.to_le_bytes());
+        Ok(entry)
+    }
+
+    pub fn sector(&self, track: u8, sector: u8) -> Result<&[u8], D64Error> {
+        let start = self.offset(track, sector)?;
+        Ok(&self.bytes[start..start + SECTOR_SIZE])
+    }
+
+    fn sector_mut(&mut self, track: u8, sector: u8) -> Result<&mut [u8], D64Error> {
+        let start = self.offset(track, sector)?;
+        Ok(&mut self.bytes[start..start + SECTOR_SIZE])
+    }
+
+    fn offset(&self, track: u8, sector: u8) -> Result<usize, D64Error> {
+        if track == 0 || track > self.tracks || sector >= sectors_in_track(track) {
+            return Err(D64Error::BadSector { track, sector });
+        }
+
+        let before: usize = (1..track)
+            .map(|track| sectors_in_track(track) as usize)
+            .sum();
+        Ok((before + sector as usize) * SECTOR_SIZE)
+    }
+
+    fn is_free(&self, track: u8, sector: u8) -> bool {
+        let entry = bam_entry(track);
+        let bit = 1 << (sector % 8);
+        self.bam()[entry + 1 + sector as usize / 8] & bit != 0
+    }
+
+    // Marks the sector as free or used, keeping the count of the track
+    fn set_free(&mut self, track: u8, sector: u8, free: bool) {
+        if self.is_free(track, sector) == free {
+            return;
+        }
+        let entry = bam_entry(track);
+        let bit = 1 << (sector % 8);
+        let bam = self
+            .sector_mut(DIRECTORY_TRACK, BAM_SECTOR)
+            .expect("the BAM is on every disk");
+
+        match free {
+            true => {
+                bam[entry] += 1;
+                bam[entry + 1 + sector as usize / 8] |= bit;
+            }
+            false => {
+                bam[entry] -= 1;
+                bam[entry + 1 + sector as usize / 8] &= !bit;
+            }
+        }
+    }
+
+    // Takes a free sector for the next sector of a file, on the same track as
+    // the last one when there is one free there, or else on the closest track
+    // to the directory with one
+    fn allocate(&mut self, last: Option<(u8, u8)>) -> Option<(u8, u8)> {
+        let (track, start) = match last {
+            Some((track, sector)) => (track, sector + FILE_INTERLEAVE),
+            None => (DIRECTORY_TRACK - 1, 0),
+        };
+
+        let distance = |other: u8| other.abs_diff(DIRECTORY_TRACK);
+        let mut tracks: Vec<u8> = (1..=BAM_TRACKS)
+            .filter(|other| *other != DIRECTORY_TRACK)
+            .collect();
+        tracks.sort_by_key(|other| (*other != track, distance(*other), *other > DIRECTORY_TRACK));
+
+        for track in tracks {
+            let count = sectors_in_track(track);
+            let Some(sector) = (0..count)
+                .map(|offset| (start + offset) % count)
+                .find(|sector| self.is_free(track, *sector))
+            else {
+                continue;
+            };
+            self.set_free(track, sector, false);
+            return Some((track, sector));
+        }
+        None
+    }
+
+    // The first unused entry of the directory, as the sector it is in and the
+    // offset in it, adding a sector to the directory when they are all used
+    fn free_entry(&mut self) -> Result<(u8, u8, usize), D64Error> {
+        let mut next = (DIRECTORY_TRACK, DIRECTORY_SECTOR);
+        let mut visited = 0;
+        loop {
+            let sector = self.sector(next.0, next.1)?;
+            if let Some(slot) = (0..SECTOR_SIZE)
+                .step_by(ENTRY_SIZE)
+                .find(|slot| sector[slot + 2] == 0)
+            {
+                return Ok((next.0, next.1, slot));
+            }
+
+            visited += 1;
+            match sector[0] {
+                0 => break,
+                _ if visited == sectors_in_track(DIRECTORY_TRACK) => {
+                    return Err(D64Error::Loop {
+                        track: DIRECTORY_TRACK,
+                        sector: DIRECTORY_SECTOR,
+                    })
+                }
+                track => next = (track, sector[1]),
+            }
+        }
+
+        let count = sectors_in_track(DIRECTORY_TRACK);
+        let added = (0..count)
+            .map(|offset| (next.1 + DIRECTORY_INTERLEAVE + offset) % count)
+            .find(|sector| self.is_free(DIRECTORY_TRACK, *sector))
+            .ok_or(D64Error::DirectoryFull)?;
+        self.set_free(DIRECTORY_TRACK, added, false);
+
+        self.sector_mut(next.0, next.1)?[0..2].copy_from_slice(&[DIRECTORY_TRACK, added]);
+        let sector = self.sector_mut(DIRECTORY_TRACK, added)?;
+        sector.fill(0);
+        sector[1] = 0xff;
+        Ok((DIRECTORY_TRACK, added, 0))
+    }
+
+    fn bam(&self) -> &[u8] {
+        self.sector(DIRECTORY_TRACK, BAM_SECTOR)
+            .expect("the BAM is on every disk")
+    }
+
+    // The sectors of the chain starting at the track and sector
+    fn chain(&self, track: u8, sector: u8) -> Result<Vec<&[u8]>, D64Error> {
+        let total = match self.tracks {
+            35 => SECTORS_35,
+            _ => SECTORS_40,
+        };
+        let mut sectors = vec![];
+
+        let mut next = (track, sector);
+        loop {
+            if sectors.len() == total {
+                return Err(D64Error::Loop { track, sector });
+            }
+            let data = self.sector(next.0, next.1)?;
+            sectors.push(data);
+
+            match data[0] {
+                0 => return Ok(sectors),
+                track => next = (track, data[1]),
+            }
+        }
+    }
+}
+
+pub fn sectors_in_track(track: u8) -> u8 {
+    match track {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+// Where the entry of the track is in the BAM
+fn bam_entry(track: u8) -> usize {
+    BAM_ENTRIES + (track as usize - 1) * BAM_ENTRY_SIZE
+}
+
+fn unpadded(name: &[u8]) -> &[u8] {
+    let length = name.iter().position(|byte| *byte == PADDING);
+    &name[..length.unwrap_or(name.len())]
+}
+
+fn decode(petscii: &[u8]) -> String {
+    petscii
+        .iter()
+        .map(|byte| petscii_to_char(*byte, Charset::Uppercase).unwrap_or('?'))
+        .collect()
+}
+
+// A name typed in ASCII as PETSCII, with the letters in upper case the same
+// as in `matches`
+fn encode(name: &str, length: usize) -> Result<Vec<u8>, D64Error> {
+    let bad_name = || D64Error::BadName(name.to_string());
+    if name.len() > length {
+        return Err(bad_name());
+    }
+
+    name.chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ' '..=']' => Ok(c as u8),
+            _ => Err(bad_name()),
+        })
+        .collect()
+}
+
+// Whether the name matches the pattern, which is typed in ASCII, so the
+// letters are the same as in PETSCII as long as they are upper case
+fn matches(pattern: &str, name: &[u8]) -> bool {
+    let mut name = name.iter();
+    for c in pattern.chars() {
+        match (c, name.next()) {
+            ('*', _) => return true,
+            ('?', Some(_)) => {}
+            (c, Some(byte)) if c.to_ascii_uppercase() as u32 == *byte as u32 => {}
+            _ => return false,
+        }
+    }
+    name.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty image, with the name in the BAM and the directory at 18/1
+    fn image() -> Vec<u8> {
+        let mut bytes = vec![0; SIZE_35];
+        let bam = 357 * SECTOR_SIZE;
+        bytes[bam..bam + 2].copy_from_slice(&[18, 1]);
+        bytes[bam + DISK_NAME..bam + DISK_NAME + NAME_LENGTH].fill(PADDING);
+        bytes[bam + DISK_NAME..bam + DISK_NAME + 4].copy_from_slice(b"DEMO");
+        bytes[bam + DISK_ID..bam + DISK_ID + 2].copy_from_slice(b"42");
+        bytes[bam + SECTOR_SIZE + 1] = 0xff;
+        bytes
+    }
+
+    // Puts a PRG in the first entry, in sectors 0 and 1 of track 17
+    fn with_prg(mut bytes: Vec<u8>, data: &[u8]) -> Vec<u8> {
+        let entry = 358 * SECTOR_SIZE;
+        bytes[entry + 2..entry + 5].copy_from_slice(&[0x82, 17, 0]);
+        bytes[entry + 5..entry + 5 + NAME_LENGTH].fill(PADDING);
+        bytes[entry + 5..entry + 10].copy_from_slice(b"HELLO");
+        bytes[entry + 0x1e] = 2;
+
+        let first = 16 * 21 * SECTOR_SIZE;
+        bytes[first..first + 2].copy_from_slice(&[17, 1]);
+        bytes[first + 2..first + SECTOR_SIZE].copy_from_slice(&data[..254]);
+
+        let second = first + SECTOR_SIZE;
+        let rest = &data[254..];
+        bytes[second..second + 2].copy_from_slice(&[0, rest.len() as u8 + 1]);
+        bytes[second + 2..second + 2 + rest.len()].copy_from_slice(rest);
+        bytes
+    }
+
+    #[test]
+    fn should_read_directory_and_files() {
+        let data: Vec<u8> = (0..300).map(|n| n as u8).collect();
+        let d64 = D64::from_bytes(with_prg(image(), &data)).unwrap();
+
+        assert_eq!(d64.name(), "DEMO");
+        assert_eq!(d64.id(), "42");
+
+        let directory = d64.directory().unwrap();
+        assert_eq!(directory.len(), 1);
+        let entry = &directory[0];
+        assert_eq!(entry.name_text(), "HELLO");
+        assert_eq!(entry.file_type, FileType::Prg);
+        assert!(entry.closed && !entry.locked);
+        assert_eq!(entry.blocks, 2);
+
+        assert_eq!(d64.read(entry).unwrap(), data);
+        assert_eq!(d64.find("*").unwrap().as_ref(), Some(entry));
+        assert_eq!(d64.find("he?lo").unwrap().as_ref(), Some(entry));
+        assert_eq!(d64.find("HELL").unwrap(), None);
+    }
+
+    #[test]
+    fn should_know_sizes_of_images() {
+        let d64 = D64::from_bytes(vec![0; SIZE_40_ERRORS]).unwrap();
+        assert_eq!(d64.tracks(), 40);
+        assert!(d64.sector(40, 16).is_ok());
+        assert!(matches!(
+            d64.sector(18, 19),
+            Err(D64Error::BadSector {
+                track: 18,
+                sector: 19
+            })
+        ));
+
+        assert!(matches!(
+            D64::from_bytes(vec![0; 1000]),
+            Err(D64Error::WrongSize(1000))
+        ));
+    }
+
+    #[test]
+    fn should_format_new_disks() {
+        let d64 = D64::new("demo", "42").unwrap();
+        assert_eq!(d64.name(), "DEMO");
+        assert_eq!(d64.id(), "42");
+        assert_eq!(d64.directory().unwrap(), vec![]);
+        assert_eq!(d64.blocks_free(), 664);
+
+        assert!(matches!(
+            D64::new("a name that is too long", "42"),
+            Err(D64Error::BadName(_))
+        ));
+    }
+
+    #[test]
+    fn should_add_files() {
+        let mut d64 = D64::new("demo", "42").unwrap();
+        let data: Vec<u8> = (0..600).map(|n| n as u8).collect();
+
+        let entry = d64.add("hello", FileType::Prg, &data).unwrap();
+        assert_eq!(entry.name_text(), "HELLO");
+        assert_eq!((entry.track, entry.sector, entry.blocks), (17, 0, 3));
+        assert_eq!(d64.blocks_free(), 661);
+
+        let d64 = D64::from_bytes(d64.bytes().to_vec()).unwrap();
+        let found = d64.find("HELLO").unwrap().unwrap();
+        assert_eq!(found, entry);
+        assert_eq!(d64.read(&found).unwrap(), data);
+        assert_eq!(d64.sector(17, 0).unwrap()[0..2], [17, 10]);
+
+        let mut d64 = d64;
+        assert!(matches!(
+            d64.add("HELLO", FileType::Prg, &data),
+            Err(D64Error::FileExists(_))
+        ));
+        assert!(matches!(
+            d64.add("huge", FileType::Seq, &vec![0; 170_000]),
+            Err(D64Error::DiskFull {
+                needed: 670,
+                free: 661
+            })
+        ));
+    }
+
+    #[test]
+    fn should_add_sectors_to_directory() {
+        let mut d64 = D64::new("demo", "42").unwrap();
+        for n in 0..9 {
+            d64.add(&format!("FILE {n}"), FileType::Prg, &[n]).unwrap();
+        }
+
+        let directory = d64.directory().unwrap();
+        assert_eq!(directory.len(), 9);
+        assert_eq!(d64.read(&directory[8]).unwrap(), vec![8]);
+        assert_eq!(d64.sector(18, 1).unwrap()[0..2], [18, 4]);
+    }
+
+    #[test]
+    fn should_stop_at_loops() {
+        let mut bytes = image();
+        let directory = 358 * SECTOR_SIZE;
+        bytes[directory..directory + 2].copy_from_slice(&[18, 1]);
+
+        let d64 = D64::from_bytes(bytes).unwrap();
+        assert!(matches!(
+            d64.directory(),
+            Err(D64Error::Loop {
+                track: 18,
+                sector: 1
+            })
+        ));
+    }
+}
@@ -0,0 +1,337 @@
+//! A linear sweep through a block decodes everything as instructions, which turns
+//! sprite data, tables and text into junk. Instead we can follow the flow of the
+//! code, the same way the CPU would, starting from a set of known entry points:
+//!
+//! - the start address of the block, or the address of the SYS line at the start
+//! - the hardware vectors, if the block covers $fffa-$ffff
+//! - any addresses given by the user, e.g. the targets of an IRQ setup
+//!
+//! Each path is followed through JSR, JMP and the branches until it ends with a
+//! RTS, RTI, BRK, an indirect JMP or an unknown opcode. Everything that was never
+//! reached is considered data and printed as `.byte` rows instead.
+//!
+//! The output is source for our own assembler, with the address and bytes of each
+//! row kept as a comment, so that it can be assembled back into the same block.
+//! It can also have labels, e.g. `L1002`, at the entry points and wherever the
+//! code jumps or branches to, used in place of the addresses in the jumps and
+//! branches, which makes the loops and subroutines easier to follow.
+
+use std::collections::BTreeSet;
+
+use super::petscii::Decoding;
+use super::{decode, memory_row, AddressingMode, Block, Instruction};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mark {
+    Data,
+    Opcode,
+    Operand,
+}
+
+// The NMI, RESET and IRQ/BRK vectors
+const VECTORS: [u16; 3] = [0xfffa, 0xfffc, 0xfffe];
+
+impl Block {
+    /// The entry points that can be found without any help, the start address
+    /// and any hardware vectors that are part of the block. When the block
+    /// starts with a BASIC SYS line, the address of the SYS is used instead.
+    pub fn entry_points(&self) -> Vec<u16> {
+        let mut entries = vec![self.sys_address().unwrap_or(self.start)];
+
+        for vector in VECTORS {
+            if let (Some(lo), Some(hi)) =
+                (self.byte_at(vector), self.byte_at(vector.wrapping_add(1)))
+            {
+                entries.push(u16::from_le_bytes([lo, hi]));
+            }
+        }
+        entries
+    }
+
+    /// Disassembles the block by following the flow of the code from the
+    /// entry points of the block, together with the given `entries`.
+    pub fn disassemble_flow(&self, entries: &[u16]) -> Vec<String> {
+        self.flow(entries, false)
+    }
+
+    /// The same as `disassemble_flow`, but with labels for the code that is
+    /// jumped or branched to inside the block
+    pub fn disassemble_labelled(&self, entries: &[u16]) -> Vec<String> {
+        self.flow(entries, true)
+    }
+
+    fn flow(&self, entries: &[u16], with_labels: bool) -> Vec<String> {
+        let marks = self.trace(entries);
+        let labels = match with_labels {
+            true => self.labels(entries, &marks),
+            false => BTreeSet::new(),
+        };
+        let mut result = vec![format!("*= ${:04X}", self.start)];
+
+        if let Some(address) = self.sys_address() {
+            let line = u16::from_le_bytes([self.instructions[2], self.instructions[3]]);
+            result.push(format!("; {line} SYS {address}"));
+        }
+
+        let mut pos = 0;
+        while pos < self.instructions.len() {
+            let addr = self.start.wrapping_add(pos as u16);
+
+            if marks[pos] == Mark::Opcode {
+                if let Some((bytes, mut decoded, length)) = self.decode_at(pos) {
+                    if labels.contains(&addr) {
+                        result.push(format!("L{addr:04X}:"));
+                    }
+                    if let Some(target) = self.target_at(pos).filter(|t| labels.contains(t)) {
+                        let name = &decode(&self.instructions[pos]).name;
+                        decoded = format!("{name} L{target:04X}");
+                    }
+
+                    let line = format!("    {decoded:46}; {addr:04X}   {bytes}");
+                    result.push(line.trim_end().into());
+                    pos += length;
+                    continue;
+                }
+            }
+
+            // Collect the data up until the next instruction, at most 8 bytes per row
+            let end = (pos + 1..self.instructions.len())
+                .take(7)
+                .find(|&pos| marks[pos] == Mark::Opcode)
+                .unwrap_or_else(|| (pos + 8).min(self.instructions.len()));
+
+            let bytes = &self.instructions[pos..end];
+            let values: Vec<String> = bytes.iter().map(|byte| format!("${byte:02X}")).collect();
+            let values = format!(".byte {}", values.join(", "));
+
+            result.push(format!(
+                "    {values:46}; {}",
+                memory_row(addr, bytes, Decoding::Ascii)
+            ));
+            pos = end;
+        }
+        result
+    }
+
+    // Marks every byte that can be reached as code from the entry points
+    fn trace(&self, entries: &[u16]) -> Vec<Mark> {
+        let mut marks = vec![Mark::Data; self.instructions.len()];
+
+        let mut pending = self.entry_points();
+        pending.extend_from_slice(entries);
+
+        while let Some(entry) = pending.pop() {
+            let mut addr = entry;
+
+            while let Some(pos) = self.offset_of(addr) {
+                // Either already visited, or the middle of another instruction
+                if marks[pos] != Mark::Data {
+                    break;
+                }
+
+                let Instruction {
+                    name, length, mode, ..
+                } = decode(&self.instructions[pos]);
+                let length = *length as usize;
+
+                if name == "???" || pos + length > self.instructions.len() {
+                    break;
+                }
+                if marks[pos + 1..pos + length]
+                    .iter()
+                    .any(|mark| *mark != Mark::Data)
+                {
+                    break;
+                }
+
+                marks[pos] = Mark::Opcode;
+                marks[pos + 1..pos + length].fill(Mark::Operand);
+
+                let operand = &self.instructions[pos + 1..pos + length];
+                let next = addr.wrapping_add(length as u16);
+
+                match (name.as_str(), mode, operand) {
+                    ("JMP", AddressingMode::Absolute, [lo, hi]) => {
+                        pending.push(u16::from_le_bytes([*lo, *hi]));
+                        break;
+                    }
+                    ("JSR", _, [lo, hi]) => pending.push(u16::from_le_bytes([*lo, *hi])),
+                    (_, AddressingMode::Relative, [offset]) => {
+                        pending.push(super::branch_target(addr, *offset))
+                    }
+                    ("JMP" | "RTS" | "RTI" | "BRK", _, _) => break,
+                    _ => {}
+                }
+                addr = next;
+            }
+        }
+        marks
+    }
+
+    // The entry points and the targets of the jumps and branches, that are
+    // the start of an instruction in the block
+    fn labels(&self, entries: &[u16], marks: &[Mark]) -> BTreeSet<u16> {
+        let targets = (0..marks.len())
+            .filter(|pos| marks[*pos] == Mark::Opcode)
+            .filter_map(|pos| self.target_at(pos));
+
+        self.entry_points()
+            .into_iter()
+            .chain(entries.iter().copied())
+            .chain(targets)
+            .filter(|addr| self.offset_of(*addr).map(|pos| marks[pos]) == Some(Mark::Opcode))
+            .collect()
+    }
+
+    // The address that the instruction at `pos` jumps or branches to
+    fn target_at(&self, pos: usize) -> Option<u16> {
+        let Instruction {
+            name, length, mode, ..
+        } = decode(&self.instructions[pos]);
+        let operand = self.instructions.get(pos + 1..pos + *length as usize)?;
+        let addr = self.start.wrapping_add(pos as u16);
+
+        match (name.as_str(), mode, operand) {
+            ("JMP", AddressingMode::Absolute, [lo, hi]) | ("JSR", _, [lo, hi]) => {
+                Some(u16::from_le_bytes([*lo, *hi]))
+            }
+            (_, AddressingMode::Relative, [offset]) => Some(super::branch_target(addr, *offset)),
+            _ => None,
+        }
+    }
+
+    fn offset_of(&self, addr: u16) -> Option<usize> {
+        let offset = addr.wrapping_sub(self.start) as usize;
+        (offset < self.instructions.len()).then_some(offset)
+    }
+
+    fn byte_at(&self, addr: u16) -> Option<u8> {
+        self.offset_of(addr).map(|pos| self.instructions[pos])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    fn code_with_data() -> Block {
+        Block {
+            start: 0x1000,
+            instructions: vec![
+                0xa2, 0x00,             // LDX #$00
+                0xbd, 0x10, 0x10,       // LDA $1010,X
+                0xf0, 0x06,             // BEQ $100D
+                0x20, 0xd2, 0xff,       // JSR $FFD2
+                0xe8,                   // INX
+                0xd0, 0xf5,             // BNE $1002
+                0x60,                   // RTS
+                0x00, 0x00,
+                b'H', b'E', b'L', b'L', b'O', 0x00,
+            ],
+        }
+    }
+
+    #[test]
+    fn should_separate_code_from_data() {
+        let marks = code_with_data().trace(&[]);
+
+        assert_eq!(marks[0], Mark::Opcode);
+        assert_eq!(marks[1], Mark::Operand);
+        assert_eq!(marks[13], Mark::Opcode);
+        assert!(marks[14..].iter().all(|mark| *mark == Mark::Data));
+    }
+
+    #[test]
+    fn should_print_unreached_bytes_as_data() {
+        let result = code_with_data().disassemble_flow(&[]);
+
+        // cspell: disable
+        let expected = vec![
+            "*= $1000",
+            "    LDX #$00                                      ; 1000   A2 00",
+            "    LDA $1010,X                                   ; 1002   BD 10 10",
+            "    BEQ $100D                                     ; 1005   F0 06",
+            "    JSR $FFD2                                     ; 1007   20 D2 FF",
+            "    INX                                           ; 100A   E8",
+            "    BNE $1002                                     ; 100B   D0 F5",
+            "    RTS                                           ; 100D   60",
+            "    .byte $00, $00, $48, $45, $4C, $4C, $4F, $00  ; 100E   00 00 48 45   4C 4C 4F 00   ..HELLO.",
+        ];
+        // cspell: enable
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn should_follow_user_specified_entries() {
+        let block = Block {
+            start: 0x2000,
+            instructions: vec![0x60, 0xa9, 0x01, 0x60],
+        };
+
+        let marks = block.trace(&[]);
+        assert_eq!(marks[1], Mark::Data);
+
+        let marks = block.trace(&[0x2001]);
+        assert_eq!(marks[1], Mark::Opcode);
+    }
+
+    #[test]
+    fn should_follow_vectors() {
+        let mut instructions = vec![0x00; 0x10];
+        instructions[0x00] = 0x40; // RTI at $fff0
+        instructions[0x0e] = 0xf0; // IRQ vector -> $fff0
+        instructions[0x0f] = 0xff;
+
+        let block = Block {
+            start: 0xfff0,
+            instructions,
+        };
+
+        assert!(block.entry_points().contains(&0xfff0));
+        assert_eq!(block.trace(&[])[0], Mark::Opcode);
+    }
+
+    #[test]
+    fn should_round_trip_through_the_assembler() {
+        let block = code_with_data();
+        let source = block.disassemble_flow(&[]).join("\n");
+
+        assert_eq!(Block::assemble(&source), block);
+    }
+
+    #[test]
+    fn should_label_jumps_and_branches() {
+        let block = code_with_data();
+        let result = block.disassemble_labelled(&[]);
+
+        assert_eq!(result[1], "L1000:");
+        assert_eq!(result[3], "L1002:");
+        assert!(result[5].starts_with("    BEQ L100D "));
+        assert!(result[6].starts_with("    JSR $FFD2 "));
+        assert!(result[8].starts_with("    BNE L1002 "));
+        assert_eq!(result[9], "L100D:");
+
+        let source = result.join("\n");
+        assert_eq!(Block::assemble(&source), block);
+    }
+
+    #[test]
+    fn should_start_from_sys_line() {
+        let code = Block {
+            start: 0x080d,
+            instructions: vec![0xee, 0x20, 0xd0, 0x4c, 0x0d, 0x08],
+        };
+        let block = code.with_sys_stub().unwrap();
+
+        let result = block.disassemble_flow(&[]);
+        assert_eq!(result[1], "; 10 SYS 2061");
+        assert!(result[2].starts_with("    .byte $0B, $08, $0A, $00, $9E"));
+        assert!(result[4].starts_with("    INC $D020"));
+        assert!(result[5].starts_with("    JMP $080D"));
+
+        let source = result.join("\n");
+        assert_eq!(Block::assemble(&source), block);
+    }
+}
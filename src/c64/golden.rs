@@ -0,0 +1,336 @@
+//! Golden image tests boot a PRG, run it for a while, and compare what the
+//! VIC-II drew with a reference PNG, like the ones the VICE testprogs come
+//! with. Everything is read from files next to each other, so it runs offline.
+//!
+//! A test runs either for a number of frames, or until the CPU reaches a
+//! trigger address, e.g. the loop a test program ends up in once it's done,
+//! with a number of frames as a limit in case it never gets there.
+//!
+//! When the frame doesn't match the reference, two images are written next to
+//! the reference to help finding out why:
+//!
+//! - name.actual.png - what was drawn
+//! - name.diff.png   - the reference darkened, with the pixels that differ in
+//!   bright red
+//!
+//! A missing reference is written from the frame when updating, which is also
+//! how a reference is brought up to date after a change that is known to be
+//! right.
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use super::autostart::{AutostartError, Boot, Start};
+use super::screenshot::{Image, ImageError, Screenshot};
+use super::C64;
+
+/// When to capture the frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Until {
+    /// After this many frames
+    Frames(u64),
+    /// At the end of the frame in which the CPU reaches the address, giving
+    /// up after the frames
+    Address { address: u16, max_frames: u64 },
+}
+
+/// A PRG and the frame it is expected to draw
+#[derive(Clone, Debug, PartialEq)]
+pub struct GoldenTest {
+    pub prg: Vec<u8>,
+    pub boot: Boot,
+    pub start: Start,
+    pub until: Until,
+    pub screenshot: Screenshot,
+    pub reference: PathBuf,
+    /// Write the frame as the reference instead of comparing with it
+    pub update: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Match,
+    /// The reference was written from the frame
+    Updated,
+    /// The frame differs from the reference in the number of pixels
+    Mismatch {
+        pixels: usize,
+        actual: PathBuf,
+        diff: PathBuf,
+    },
+    /// The frame isn't the same size as the reference
+    WrongSize {
+        expected: (usize, usize),
+        found: (usize, usize),
+        actual: PathBuf,
+    },
+}
+
+#[derive(Debug)]
+pub enum GoldenError {
+    Autostart(AutostartError),
+    Image(ImageError),
+    /// The CPU never reached the trigger address
+    NotTriggered {
+        address: u16,
+        frames: u64,
+    },
+    /// There is no reference to compare with
+    MissingReference(PathBuf),
+}
+
+impl Display for GoldenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GoldenError::Autostart(err) => write!(f, "{err}"),
+            GoldenError::Image(err) => write!(f, "{err}"),
+            GoldenError::NotTriggered { address, frames } => {
+                write!(f, "${address:04x} wasn't reached within {frames} frames")
+            }
+            GoldenError::MissingReference(path) => {
+                write!(f, "there is no reference image at {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for GoldenError {}
+
+impl From<AutostartError> for GoldenError {
+    fn from(err: AutostartError) -> Self {
+        GoldenError::Autostart(err)
+    }
+}
+
+impl From<ImageError> for GoldenError {
+    fn from(err: ImageError) -> Self {
+        GoldenError::Image(err)
+    }
+}
+
+impl GoldenTest {
+    /// A test of the PRG, compared with the reference, after the frames
+    pub fn new(prg: Vec<u8>, reference: impl AsRef<Path>, frames: u64) -> Self {
+        GoldenTest {
+            prg,
+            boot: Boot::Kernal,
+            start: Start::Run,
+            until: Until::Frames(frames),
+            screenshot: Screenshot::default(),
+            reference: reference.as_ref().to_path_buf(),
+            update: false,
+        }
+    }
+
+    /// Runs the test on the machine, which should already have the ROMs and
+    /// model it needs
+    pub fn run(&self, c64: &mut C64) -> Result<Outcome, GoldenError> {
+        c64.autostart(&self.prg, self.boot, self.start)?;
+
+        match self.until {
+            Until::Frames(frames) => (0..frames).for_each(|_| c64.run_frame()),
+            Until::Address {
+                address,
+                max_frames,
+            } => run_until(c64, address, max_frames)?,
+        }
+
+        let actual = c64.screenshot(self.screenshot);
+        if self.update {
+            actual.save_png(&self.reference)?;
+            return Ok(Outcome::Updated);
+        }
+        if !self.reference.exists() {
+            return Err(GoldenError::MissingReference(self.reference.clone()));
+        }
+
+        let reference = Image::load_png(&self.reference)?;
+        let actual_path = self.sibling("actual");
+
+        if (actual.width, actual.height) != (reference.width, reference.height) {
+            actual.save_png(&actual_path)?;
+            return Ok(Outcome::WrongSize {
+                expected: (reference.width, reference.height),
+                found: (actual.width, actual.height),
+                actual: actual_path,
+            });
+        }
+
+        let (diff, pixels) = difference(&reference, &actual);
+        if pixels == 0 {
+            return Ok(Outcome::Match);
+        }
+
+        let diff_path = self.sibling("diff");
+        actual.save_png(&actual_path)?;
+        diff.save_png(&diff_path)?;
+        Ok(Outcome::Mismatch {
+            pixels,
+            actual: actual_path,
+            diff: diff_path,
+        })
+    }
+
+    // The path of the reference with another word before the extension, e.g.
+    // border.png becomes border.diff.png
+    fn sibling(&self, kind: &str) -> PathBuf {
+        self.reference.with_extension(format!("{kind}.png"))
+    }
+}
+
+// Runs whole frames, until the CPU has been at the address in one of them
+fn run_until(c64: &mut C64, address: u16, max_frames: u64) -> Result<(), GoldenError> {
+    for _ in 0..max_frames {
+        let frame = c64.vic().frames();
+        let mut triggered = false;
+        while c64.vic().frames() == frame {
+            triggered |= c64.cpu.PC == address;
+            c64.step();
+        }
+        if triggered {
+            return Ok(());
+        }
+    }
+
+    Err(GoldenError::NotTriggered {
+        address,
+        frames: max_frames,
+    })
+}
+
+// An image of the differences, and how many pixels differ
+fn difference(reference: &Image, actual: &Image) -> (Image, usize) {
+    let mut pixels = 0;
+
+    let rgb = reference
+        .rgb
+        .chunks_exact(3)
+        .zip(actual.rgb.chunks_exact(3))
+        .flat_map(|(expected, found)| {
+            if expected == found {
+                [expected[0] / 4, expected[1] / 4, expected[2] / 4]
+            } else {
+                pixels += 1;
+                [0xff, 0x00, 0x00]
+            }
+        })
+        .collect();
+
+    let diff = Image {
+        width: reference.width,
+        height: reference.height,
+        rgb,
+    };
+    (diff, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c64::screenshot::Region;
+    use crate::c64::test_directory;
+
+    // Turns on the screen in blue with the border colour, and loops at $081c
+    #[rustfmt::skip]
+    fn prg(border: u8) -> Vec<u8> {
+        vec![
+            0x01, 0x08,
+            0x0b, 0x08, 0x0a, 0x00, 0x9e, 0x32, 0x30, 0x36, 0x31, 0x00, // 10 SYS 2061
+            0x00, 0x00,
+            0xa9, 0x1b, 0x8d, 0x11, 0xd0, // LDA #$1B / STA $D011
+            0xa9, 0x06, 0x8d, 0x21, 0xd0, // LDA #$06 / STA $D021
+            0xa9, border, 0x8d, 0x20, 0xd0, // LDA #border / STA $D020
+            0x4c, 0x1c, 0x08, // JMP *
+        ]
+    }
+
+    fn test(border: u8, directory: &Path) -> GoldenTest {
+        GoldenTest {
+            boot: Boot::Fast,
+            start: Start::Sys,
+            until: Until::Address {
+                address: 0x081c,
+                max_frames: 5,
+            },
+            screenshot: Screenshot {
+                region: Region::Full,
+                ..Screenshot::default()
+            },
+            ..GoldenTest::new(prg(border), directory.join("border.png"), 0)
+        }
+    }
+
+    #[test]
+    fn should_match_reference() {
+        let directory = test_directory("golden-match");
+
+        let result = test(0x02, &directory).run(&mut C64::new());
+        assert!(matches!(result, Err(GoldenError::MissingReference(_))));
+
+        let update = GoldenTest {
+            update: true,
+            ..test(0x02, &directory)
+        };
+        assert_eq!(update.run(&mut C64::new()).unwrap(), Outcome::Updated);
+
+        let outcome = test(0x02, &directory).run(&mut C64::new()).unwrap();
+        assert_eq!(outcome, Outcome::Match);
+        assert!(!directory.join("border.diff.png").exists());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn should_write_diff_on_mismatch() {
+        let directory = test_directory("golden-mismatch");
+
+        let update = GoldenTest {
+            update: true,
+            ..test(0x02, &directory)
+        };
+        update.run(&mut C64::new()).unwrap();
+
+        let Outcome::Mismatch {
+            pixels,
+            actual,
+            diff,
+        } = test(0x05, &directory).run(&mut C64::new()).unwrap()
+        else {
+            panic!("expected a mismatch");
+        };
+
+        // The border is all that differs, so the middle of the screen is the
+        // same, and darkened, in the diff
+        assert!(pixels > 10_000);
+        let diff = Image::load_png(diff).unwrap();
+        let actual = Image::load_png(actual).unwrap();
+        let corner = 0;
+        let middle = (150 * diff.width + 250) * 3;
+        assert_eq!(diff.rgb[corner..corner + 3], [0xff, 0x00, 0x00]);
+        assert_ne!(actual.rgb[middle + 2], 0x00);
+        assert_eq!(diff.rgb[middle + 2], actual.rgb[middle + 2] / 4);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn should_give_up_when_never_triggered() {
+        let directory = test_directory("golden-trigger");
+        let test = GoldenTest {
+            until: Until::Address {
+                address: 0x1234,
+                max_frames: 2,
+            },
+            ..test(0x02, &directory)
+        };
+
+        assert!(matches!(
+            test.run(&mut C64::new()),
+            Err(GoldenError::NotTriggered {
+                address: 0x1234,
+                frames: 2
+            })
+        ));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
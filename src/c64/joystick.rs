@@ -0,0 +1,99 @@
+//! The two joystick ports are wired straight to the ports of CIA 1, port 1 to
+//! port B and port 2 to port A, the same lines as the keyboard matrix. Every
+//! switch of the joystick pulls a line low when closed:
+//!
+//! ```text
+//! bit 0 - up
+//! bit 1 - down
+//! bit 2 - left
+//! bit 3 - right
+//! bit 4 - fire
+//! ```
+//!
+//! As the lines are shared, a joystick in port 1 looks like keys being pressed
+//! to the KERNAL, and port 2 like the columns being scanned, which is why most
+//! games use port 2.
+
+/// The port a joystick is plugged into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Port {
+    /// Read on port B of CIA 1, shared with the rows of the keyboard
+    One,
+    /// Read on port A of CIA 1, shared with the columns of the keyboard
+    Two,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Switch {
+    Up,
+    Down,
+    Left,
+    Right,
+    Fire,
+}
+
+impl Switch {
+    fn bit(&self) -> u8 {
+        match self {
+            Switch::Up => 1 << 0,
+            Switch::Down => 1 << 1,
+            Switch::Left => 1 << 2,
+            Switch::Right => 1 << 3,
+            Switch::Fire => 1 << 4,
+        }
+    }
+}
+
+/// The switches closed in a joystick
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Joystick {
+    closed: u8,
+}
+
+impl Joystick {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&mut self, switch: Switch) {
+        self.closed |= switch.bit();
+    }
+
+    pub fn release(&mut self, switch: Switch) {
+        self.closed &= !switch.bit();
+    }
+
+    pub fn release_all(&mut self) {
+        self.closed = 0;
+    }
+
+    pub fn is_pressed(&self, switch: Switch) -> bool {
+        self.closed & switch.bit() != 0
+    }
+
+    /// The lines pulled low, as read on the port of the CIA
+    pub fn lines(&self) -> u8 {
+        !self.closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_pull_lines_low() {
+        let mut joystick = Joystick::new();
+        assert_eq!(joystick.lines(), 0xff);
+
+        joystick.press(Switch::Up);
+        joystick.press(Switch::Fire);
+        assert_eq!(joystick.lines(), !0x11);
+        assert!(joystick.is_pressed(Switch::Fire));
+
+        joystick.release(Switch::Up);
+        assert_eq!(joystick.lines(), !0x10);
+        joystick.release_all();
+        assert_eq!(joystick.lines(), 0xff);
+    }
+}
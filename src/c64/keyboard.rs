@@ -0,0 +1,355 @@
+//! The keyboard is a matrix of 8 columns by 8 rows, read through CIA 1: a
+//! program pulls the columns it wants to scan low on port A, and reads back
+//! which rows are pulled low on port B, through the switches of the keys that
+//! are held down. It works the other way around too, driving the rows on port B
+//! and reading the columns on port A.
+//!
+//! ```text
+//!        PA0      PA1   PA2   PA3   PA4   PA5   PA6     PA7
+//! PB0    DEL      3     5     7     9     +     £       1
+//! PB1    RETURN   W     R     Y     I     P     *       ←
+//! PB2    CRSR →   A     D     G     J     L     ;       CTRL
+//! PB3    F7       4     6     8     0     -     HOME    2
+//! PB4    F1       Z     C     B     M     .     RSHIFT  SPACE
+//! PB5    F3       S     F     H     K     :     =       C=
+//! PB6    F5       E     T     U     O     @     ↑       Q
+//! PB7    CRSR ↓   LSHIFT X    V     N     ,     /       STOP
+//! ```
+//!
+//! RESTORE isn't part of the matrix, but is wired to the NMI line instead.
+//!
+//! There are no keys for the cursor up and left, or F2, F4, F6 and F8, those
+//! are typed with SHIFT together with the keys for the other direction or
+//! number, so `for_char` gives the keys needed to type a character.
+//!
+//! The keys of a PC keyboard map to the C64 keyboard by where they are, rather
+//! than what is printed on them, the way VICE does it, so the keys next to 0
+//! are + and -, and so on. The keys the C64 doesn't have map to the closest
+//! ones:
+//!
+//! - cursor up and left   - SHIFT with cursor down and right
+//! - Home                 - CLR/HOME
+//! - Backspace and Delete - INST/DEL
+//! - Insert               - £
+//! - Tab                  - CTRL
+//! - Left Alt             - C=
+//! - Escape               - RUN/STOP
+//! - Page Up              - RESTORE
+//! - Page Down            - ↑
+//! - F1-F8                - F1-F7, the even ones with SHIFT
+//!
+//! The keys are named the way the `code` of a KeyboardEvent names them in a
+//! browser, e.g. KeyA, Digit7 and ArrowUp, by where they are on a US keyboard.
+
+use std::collections::HashSet;
+
+/// The keys of the C64, named by what's printed on them
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Key {
+    InstDel,
+    Return,
+    CursorRight,
+    F7,
+    F1,
+    F3,
+    F5,
+    CursorDown,
+    Num3,
+    W,
+    A,
+    Num4,
+    Z,
+    S,
+    E,
+    LeftShift,
+    Num5,
+    R,
+    D,
+    Num6,
+    C,
+    F,
+    T,
+    X,
+    Num7,
+    Y,
+    G,
+    Num8,
+    B,
+    H,
+    U,
+    V,
+    Num9,
+    I,
+    J,
+    Num0,
+    M,
+    K,
+    O,
+    N,
+    Plus,
+    P,
+    L,
+    Minus,
+    Period,
+    Colon,
+    At,
+    Comma,
+    Pound,
+    Asterisk,
+    Semicolon,
+    ClrHome,
+    RightShift,
+    Equals,
+    ArrowUp,
+    Slash,
+    Num1,
+    ArrowLeft,
+    Control,
+    Num2,
+    Space,
+    Commodore,
+    Q,
+    RunStop,
+    /// Not part of the matrix, pulls NMI
+    Restore,
+}
+
+// The keys in the order of the matrix, column by column, row by row
+#[rustfmt::skip]
+const MATRIX: [Key; 64] = {
+    use Key::*;
+    [
+        InstDel, Return, CursorRight, F7, F1, F3, F5, CursorDown,
+        Num3, W, A, Num4, Z, S, E, LeftShift,
+        Num5, R, D, Num6, C, F, T, X,
+        Num7, Y, G, Num8, B, H, U, V,
+        Num9, I, J, Num0, M, K, O, N,
+        Plus, P, L, Minus, Period, Colon, At, Comma,
+        Pound, Asterisk, Semicolon, ClrHome, RightShift, Equals, ArrowUp, Slash,
+        Num1, ArrowLeft, Control, Num2, Space, Commodore, Q, RunStop,
+    ]
+};
+
+impl Key {
+    /// The column and row of the key in the matrix, if it is part of it
+    pub fn position(&self) -> Option<(usize, usize)> {
+        MATRIX
+            .iter()
+            .position(|key| key == self)
+            .map(|index| (index / 8, index % 8))
+    }
+
+    /// The key that types the character, and whether SHIFT is needed too.
+    /// Letters are typed without SHIFT, whatever their case, as that is
+    /// what gives upper case letters after power on.
+    pub fn for_char(c: char) -> Option<(Key, bool)> {
+        use Key::*;
+
+        let letter = |c: char| {
+            const LETTERS: [Key; 26] = [
+                A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+            ];
+            LETTERS[(c.to_ascii_lowercase() as u8 - b'a') as usize]
+        };
+        let digit = |c: char| {
+            const DIGITS: [Key; 10] = [Num0, Num1, Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9];
+            DIGITS[(c as u8 - b'0') as usize]
+        };
+
+        let typed = match c {
+            'a'..='z' | 'A'..='Z' => (letter(c), false),
+            '0'..='9' => (digit(c), false),
+            '!' | '"' | '#' | '$' | '%' | '&' | '\'' | '(' | ')' => {
+                (digit((b'1' + "!\"#$%&'()".find(c)? as u8) as char), true)
+            }
+            ' ' => (Space, false),
+            '\n' | '\r' => (Return, false),
+            '+' => (Plus, false),
+            '-' => (Minus, false),
+            '*' => (Asterisk, false),
+            '/' => (Slash, false),
+            '=' => (Equals, false),
+            ':' => (Colon, false),
+            ';' => (Semicolon, false),
+            ',' => (Comma, false),
+            '.' => (Period, false),
+            '@' => (At, false),
+            '£' => (Pound, false),
+            '^' | '↑' => (ArrowUp, false),
+            '_' | '←' => (ArrowLeft, false),
+            '<' => (Comma, true),
+            '>' => (Period, true),
+            '?' => (Slash, true),
+            '[' => (Colon, true),
+            ']' => (Semicolon, true),
+            _ => return None,
+        };
+        Some(typed)
+    }
+
+    /// The keys to hold down for a key of a PC keyboard, named by its
+    /// position the way a KeyboardEvent does, or None when it has no
+    /// counterpart on the C64
+    pub fn for_position(name: &str) -> Option<Vec<Key>> {
+        use Key::*;
+        let shifted = |key| vec![LeftShift, key];
+
+        if let Some(c) = name
+            .strip_prefix("Key")
+            .or_else(|| name.strip_prefix("Digit"))
+            .and_then(|rest| rest.chars().next().filter(|_| rest.len() == 1))
+        {
+            let (key, _) = Key::for_char(c)?;
+            return Some(vec![key]);
+        }
+
+        let keys = match name {
+            "Minus" => vec![Plus],
+            "Equal" => vec![Minus],
+            "Backquote" => vec![ArrowLeft],
+            "BracketLeft" => vec![At],
+            "BracketRight" => vec![Asterisk],
+            "Semicolon" => vec![Colon],
+            "Quote" => vec![Semicolon],
+            "Backslash" => vec![Equals],
+            "Comma" => vec![Comma],
+            "Period" => vec![Period],
+            "Slash" => vec![Slash],
+            "Space" => vec![Space],
+            "Enter" | "NumpadEnter" => vec![Return],
+            "Backspace" | "Delete" => vec![InstDel],
+            "Insert" => vec![Pound],
+            "Home" => vec![ClrHome],
+            "ShiftLeft" => vec![LeftShift],
+            "ShiftRight" => vec![RightShift],
+            "ControlLeft" | "Tab" => vec![Control],
+            "AltLeft" => vec![Commodore],
+            "Escape" => vec![RunStop],
+            "PageUp" => vec![Restore],
+            "PageDown" => vec![ArrowUp],
+            "ArrowRight" => vec![CursorRight],
+            "ArrowLeft" => shifted(CursorRight),
+            "ArrowDown" => vec![CursorDown],
+            "ArrowUp" => shifted(CursorDown),
+            "F1" => vec![F1],
+            "F2" => shifted(F1),
+            "F3" => vec![F3],
+            "F4" => shifted(F3),
+            "F5" => vec![F5],
+            "F6" => shifted(F5),
+            "F7" => vec![F7],
+            "F8" => shifted(F7),
+            _ => return None,
+        };
+        Some(keys)
+    }
+}
+
+/// The keys held down
+#[derive(Clone, Debug, Default)]
+pub struct Keyboard {
+    pressed: HashSet<Key>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&mut self, key: Key) {
+        self.pressed.insert(key);
+    }
+
+    pub fn release(&mut self, key: Key) {
+        self.pressed.remove(&key);
+    }
+
+    pub fn release_all(&mut self) {
+        self.pressed.clear();
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    /// The rows pulled low by the keys held down in the columns that are
+    /// pulled low, as read on port B
+    pub fn rows(&self, columns: u8) -> u8 {
+        !self.connected(|column, row| (columns >> column, row))
+    }
+
+    /// The columns pulled low by the keys held down in the rows that are
+    /// pulled low, as read on port A
+    pub fn columns(&self, rows: u8) -> u8 {
+        !self.connected(|column, row| (rows >> row, column))
+    }
+
+    // The bits of the other side of the matrix connected to a side that is
+    // pulled low, given the bits of the pulled side shifted to the key, and
+    // the bit of the other side
+    fn connected(&self, side: impl Fn(usize, usize) -> (u8, usize)) -> u8 {
+        self.pressed
+            .iter()
+            .filter_map(Key::position)
+            .filter_map(|(column, row)| {
+                let (pulled, other) = side(column, row);
+                (pulled & 1 == 0).then_some(1 << other)
+            })
+            .fold(0, |bits, bit| bits | bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_keys_through_the_matrix() {
+        let mut keyboard = Keyboard::new();
+        assert_eq!(keyboard.rows(0x00), 0xff);
+
+        // A is in column 1, row 2
+        keyboard.press(Key::A);
+        assert_eq!(keyboard.rows(!0x02), !0x04);
+        assert_eq!(keyboard.rows(!0x01), 0xff);
+        assert_eq!(keyboard.rows(0x00), !0x04);
+        assert_eq!(keyboard.columns(!0x04), !0x02);
+
+        keyboard.press(Key::RunStop);
+        assert_eq!(keyboard.rows(0x00), !0x84);
+        assert_eq!(keyboard.rows(!0x80), !0x80);
+
+        keyboard.release(Key::A);
+        assert_eq!(keyboard.rows(!0x02), 0xff);
+
+        // RESTORE isn't part of the matrix
+        keyboard.press(Key::Restore);
+        assert_eq!(keyboard.rows(!0x80), !0x80);
+        assert!(keyboard.is_pressed(Key::Restore));
+    }
+
+    #[test]
+    fn should_map_key_positions() {
+        assert_eq!(Key::for_position("KeyA"), Some(vec![Key::A]));
+        assert_eq!(Key::for_position("Digit7"), Some(vec![Key::Num7]));
+        assert_eq!(Key::for_position("Minus"), Some(vec![Key::Plus]));
+        assert_eq!(
+            Key::for_position("ArrowUp"),
+            Some(vec![Key::LeftShift, Key::CursorDown])
+        );
+        assert_eq!(Key::for_position("KeyAB"), None);
+        assert_eq!(Key::for_position("F9"), None);
+    }
+
+    #[test]
+    fn should_find_keys_for_chars() {
+        assert_eq!(Key::for_char('a'), Some((Key::A, false)));
+        assert_eq!(Key::for_char('Z'), Some((Key::Z, false)));
+        assert_eq!(Key::for_char('0'), Some((Key::Num0, false)));
+        assert_eq!(Key::for_char('"'), Some((Key::Num2, true)));
+        assert_eq!(Key::for_char(')'), Some((Key::Num9, true)));
+        assert_eq!(Key::for_char('?'), Some((Key::Slash, true)));
+        assert_eq!(Key::for_char('\r'), Some((Key::Return, false)));
+        assert_eq!(Key::for_char('~'), None);
+    }
+}
@@ -1,7 +1,24 @@
-#![allow(dead_code)]
-
+pub mod autostart;
+pub mod basic;
 pub mod bus;
+pub mod cia;
 pub mod cpu;
+pub mod d64;
+pub mod disassembler;
+pub mod golden;
+pub mod joystick;
+pub mod keyboard;
+pub mod model;
+pub mod monitor;
+pub mod petscii;
+pub mod prg;
+pub mod recording;
+pub mod roms;
+pub mod screenshot;
+pub mod sid;
+pub mod text_screen;
+#[allow(non_snake_case)]
+pub mod vicII;
 
 use once_cell::sync::Lazy;
 use std::collections::HashMap;
@@ -9,15 +26,26 @@ use std::fmt::Display;
 
 use self::bus::Bus;
 use self::cpu::Cpu;
-
+use self::joystick::{Port, Switch};
+use self::keyboard::{Key, Keyboard};
+use self::model::Model;
+use self::petscii::Decoding;
+use self::roms::Roms;
+use self::sid::Sid;
+use self::vicII::palette::Palette;
+use self::vicII::VicII;
+
+/// Bytes of code or data, and the address they belong at
 #[derive(Debug, PartialEq)]
 pub struct Block {
     pub start: u16,
+    /// The bytes, whether they are instructions or not
     pub instructions: Vec<u8>,
 }
 
+/// How an instruction finds its operand
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub(crate) enum AddressingMode {
+pub enum AddressingMode {
     Absolute,
     AbsoluteX,
     AbsoluteY,
@@ -32,7 +60,8 @@ pub(crate) enum AddressingMode {
     ZeroPageY,
 }
 
-pub(crate) struct Instruction {
+/// An opcode of the 6510, as decoded by `decode`
+pub struct Instruction {
     code: u8,
     mode: AddressingMode,
     name: String,
@@ -51,11 +80,36 @@ impl Instruction {
         }
     }
 
+    /// An opcode that isn't known, as a single byte named ???
     pub fn unknown(code: u8) -> Self {
         Instruction::new(code, AddressingMode::Implied, String::from("???"), 1, 0)
     }
+
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    pub fn mode(&self) -> AddressingMode {
+        self.mode
+    }
+
+    /// The mnemonic, e.g. LDA
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The length in bytes, including the opcode
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    /// The cycles it takes, without any for crossing pages or taken branches
+    pub fn cycles(&self) -> u8 {
+        self.cycles
+    }
 }
 
+/// The instruction of the opcode, or one named ??? when it isn't known
 pub fn decode(opcode: &u8) -> &'static Instruction {
     INSTRUCTIONS
         .get(opcode)
@@ -69,29 +123,133 @@ static UNKNOWN_INSTRUCTION: Lazy<Instruction> =
 static INSTRUCTIONS: Lazy<HashMap<u8, Instruction>> = Lazy::new(|| {
     use AddressingMode::*;
     HashMap::from([
-        (0xa2, Instruction::new(0xa2, Immediate, "LDX".into(), 2, 0)),
-        (0x78, Instruction::new(0x78, Implied,   "SEI".into(), 1, 0)),
-        (0x9a, Instruction::new(0x9a, Implied,   "TXS".into(), 1, 0)),
-        (0xd8, Instruction::new(0xd8, Implied,   "CLD".into(), 1, 0)),
-        (0x20, Instruction::new(0x20, Absolute,  "JSR".into(), 3, 0)),
-        (0xd0, Instruction::new(0xd0, Relative,  "BNE".into(), 2, 0)),
-        (0x58, Instruction::new(0x58, Implied,   "CLI".into(), 1, 0)),
-        (0x8e, Instruction::new(0x8e, Absolute,  "STX".into(), 3, 0)),
-        (0x6c, Instruction::new(0x6c, Indirect,  "JMP".into(), 3, 0)),
-
         // Bitwise Instructions
+        (0x29, Instruction::new(0x29, Immediate, "AND".into(), 2, 2)),
+        (0x25, Instruction::new(0x25, ZeroPage,  "AND".into(), 2, 3)),
+        (0x35, Instruction::new(0x35, ZeroPageX, "AND".into(), 2, 4)),
+        (0x2d, Instruction::new(0x2d, Absolute,  "AND".into(), 3, 4)),
+        (0x3d, Instruction::new(0x3d, AbsoluteX, "AND".into(), 3, 4)),
+        (0x39, Instruction::new(0x39, AbsoluteY, "AND".into(), 3, 4)),
+        (0x21, Instruction::new(0x21, IndirectX, "AND".into(), 2, 6)),
+        (0x31, Instruction::new(0x31, IndirectY, "AND".into(), 2, 5)),
+
+        (0x49, Instruction::new(0x49, Immediate, "EOR".into(), 2, 2)),
+        (0x45, Instruction::new(0x45, ZeroPage,  "EOR".into(), 2, 3)),
+        (0x55, Instruction::new(0x55, ZeroPageX, "EOR".into(), 2, 4)),
+        (0x4d, Instruction::new(0x4d, Absolute,  "EOR".into(), 3, 4)),
+        (0x5d, Instruction::new(0x5d, AbsoluteX, "EOR".into(), 3, 4)),
+        (0x59, Instruction::new(0x59, AbsoluteY, "EOR".into(), 3, 4)),
+        (0x41, Instruction::new(0x41, IndirectX, "EOR".into(), 2, 6)),
+        (0x51, Instruction::new(0x51, IndirectY, "EOR".into(), 2, 5)),
+
+        (0x09, Instruction::new(0x09, Immediate, "ORA".into(), 2, 2)),
+        (0x05, Instruction::new(0x05, ZeroPage,  "ORA".into(), 2, 3)),
+        (0x15, Instruction::new(0x15, ZeroPageX, "ORA".into(), 2, 4)),
+        (0x0d, Instruction::new(0x0d, Absolute,  "ORA".into(), 3, 4)),
+        (0x1d, Instruction::new(0x1d, AbsoluteX, "ORA".into(), 3, 4)),
+        (0x19, Instruction::new(0x19, AbsoluteY, "ORA".into(), 3, 4)),
+        (0x01, Instruction::new(0x01, IndirectX, "ORA".into(), 2, 6)),
+        (0x11, Instruction::new(0x11, IndirectY, "ORA".into(), 2, 5)),
+
+        (0x0a, Instruction::new(0x0a, Implied,   "ASL".into(), 1, 2)),
+        (0x06, Instruction::new(0x06, ZeroPage,  "ASL".into(), 2, 5)),
+        (0x16, Instruction::new(0x16, ZeroPageX, "ASL".into(), 2, 6)),
+        (0x0e, Instruction::new(0x0e, Absolute,  "ASL".into(), 3, 6)),
+        (0x1e, Instruction::new(0x1e, AbsoluteX, "ASL".into(), 3, 7)),
+
+        (0x4a, Instruction::new(0x4a, Implied,   "LSR".into(), 1, 2)),
+        (0x46, Instruction::new(0x46, ZeroPage,  "LSR".into(), 2, 5)),
+        (0x56, Instruction::new(0x56, ZeroPageX, "LSR".into(), 2, 6)),
+        (0x4e, Instruction::new(0x4e, Absolute,  "LSR".into(), 3, 6)),
+        (0x5e, Instruction::new(0x5e, AbsoluteX, "LSR".into(), 3, 7)),
+
+        (0x2a, Instruction::new(0x2a, Implied,   "ROL".into(), 1, 2)),
+        (0x26, Instruction::new(0x26, ZeroPage,  "ROL".into(), 2, 5)),
+        (0x36, Instruction::new(0x36, ZeroPageX, "ROL".into(), 2, 6)),
+        (0x2e, Instruction::new(0x2e, Absolute,  "ROL".into(), 3, 6)),
+        (0x3e, Instruction::new(0x3e, AbsoluteX, "ROL".into(), 3, 7)),
+
+        (0x6a, Instruction::new(0x6a, Implied,   "ROR".into(), 1, 2)),
+        (0x66, Instruction::new(0x66, ZeroPage,  "ROR".into(), 2, 5)),
+        (0x76, Instruction::new(0x76, ZeroPageX, "ROR".into(), 2, 6)),
+        (0x6e, Instruction::new(0x6e, Absolute,  "ROR".into(), 3, 6)),
+        (0x7e, Instruction::new(0x7e, AbsoluteX, "ROR".into(), 3, 7)),
+
+        (0x24, Instruction::new(0x24, ZeroPage,  "BIT".into(), 2, 3)),
+        (0x2c, Instruction::new(0x2c, Absolute,  "BIT".into(), 3, 4)),
 
         // Branch Instructions
+        (0x90, Instruction::new(0x90, Relative,  "BCC".into(), 2, 2)),
+        (0xb0, Instruction::new(0xb0, Relative,  "BCS".into(), 2, 2)),
+        (0xf0, Instruction::new(0xf0, Relative,  "BEQ".into(), 2, 2)),
+        (0x30, Instruction::new(0x30, Relative,  "BMI".into(), 2, 2)),
+        (0xd0, Instruction::new(0xd0, Relative,  "BNE".into(), 2, 2)),
+        (0x10, Instruction::new(0x10, Relative,  "BPL".into(), 2, 2)),
+        (0x50, Instruction::new(0x50, Relative,  "BVC".into(), 2, 2)),
+        (0x70, Instruction::new(0x70, Relative,  "BVS".into(), 2, 2)),
 
         // Compare Instructions
+        (0xc9, Instruction::new(0xc9, Immediate, "CMP".into(), 2, 2)),
+        (0xc5, Instruction::new(0xc5, ZeroPage,  "CMP".into(), 2, 3)),
+        (0xd5, Instruction::new(0xd5, ZeroPageX, "CMP".into(), 2, 4)),
+        (0xcd, Instruction::new(0xcd, Absolute,  "CMP".into(), 3, 4)),
+        (0xdd, Instruction::new(0xdd, AbsoluteX, "CMP".into(), 3, 4)),
+        (0xd9, Instruction::new(0xd9, AbsoluteY, "CMP".into(), 3, 4)),
+        (0xc1, Instruction::new(0xc1, IndirectX, "CMP".into(), 2, 6)),
+        (0xd1, Instruction::new(0xd1, IndirectY, "CMP".into(), 2, 5)),
+
+        (0xe0, Instruction::new(0xe0, Immediate, "CPX".into(), 2, 2)),
+        (0xe4, Instruction::new(0xe4, ZeroPage,  "CPX".into(), 2, 3)),
+        (0xec, Instruction::new(0xec, Absolute,  "CPX".into(), 3, 4)),
+
+        (0xc0, Instruction::new(0xc0, Immediate, "CPY".into(), 2, 2)),
+        (0xc4, Instruction::new(0xc4, ZeroPage,  "CPY".into(), 2, 3)),
+        (0xcc, Instruction::new(0xcc, Absolute,  "CPY".into(), 3, 4)),
 
         // Flag Instructions
+        (0x18, Instruction::new(0x18, Implied,   "CLC".into(), 1, 2)),
+        (0xd8, Instruction::new(0xd8, Implied,   "CLD".into(), 1, 2)),
+        (0x58, Instruction::new(0x58, Implied,   "CLI".into(), 1, 2)),
+        (0xb8, Instruction::new(0xb8, Implied,   "CLV".into(), 1, 2)),
+        (0x38, Instruction::new(0x38, Implied,   "SEC".into(), 1, 2)),
+        (0xf8, Instruction::new(0xf8, Implied,   "SED".into(), 1, 2)),
+        (0x78, Instruction::new(0x78, Implied,   "SEI".into(), 1, 2)),
 
         // Jump Instructions
+        (0x4c, Instruction::new(0x4c, Absolute,  "JMP".into(), 3, 3)),
+        (0x6c, Instruction::new(0x6c, Indirect,  "JMP".into(), 3, 5)),
+        (0x20, Instruction::new(0x20, Absolute,  "JSR".into(), 3, 6)),
+        (0x60, Instruction::new(0x60, Implied,   "RTS".into(), 1, 6)),
+        (0x40, Instruction::new(0x40, Implied,   "RTI".into(), 1, 6)),
 
         // Math Instructions
-
-
+        (0x69, Instruction::new(0x69, Immediate, "ADC".into(), 2, 2)),
+        (0x65, Instruction::new(0x65, ZeroPage,  "ADC".into(), 2, 3)),
+        (0x75, Instruction::new(0x75, ZeroPageX, "ADC".into(), 2, 4)),
+        (0x6d, Instruction::new(0x6d, Absolute,  "ADC".into(), 3, 4)),
+        (0x7d, Instruction::new(0x7d, AbsoluteX, "ADC".into(), 3, 4)),
+        (0x79, Instruction::new(0x79, AbsoluteY, "ADC".into(), 3, 4)),
+        (0x61, Instruction::new(0x61, IndirectX, "ADC".into(), 2, 6)),
+        (0x71, Instruction::new(0x71, IndirectY, "ADC".into(), 2, 5)),
+
+        (0xe9, Instruction::new(0xe9, Immediate, "SBC".into(), 2, 2)),
+        (0xe5, Instruction::new(0xe5, ZeroPage,  "SBC".into(), 2, 3)),
+        (0xf5, Instruction::new(0xf5, ZeroPageX, "SBC".into(), 2, 4)),
+        (0xed, Instruction::new(0xed, Absolute,  "SBC".into(), 3, 4)),
+        (0xfd, Instruction::new(0xfd, AbsoluteX, "SBC".into(), 3, 4)),
+        (0xf9, Instruction::new(0xf9, AbsoluteY, "SBC".into(), 3, 4)),
+        (0xe1, Instruction::new(0xe1, IndirectX, "SBC".into(), 2, 6)),
+        (0xf1, Instruction::new(0xf1, IndirectY, "SBC".into(), 2, 5)),
+
+        (0xe6, Instruction::new(0xe6, ZeroPage,  "INC".into(), 2, 5)),
+        (0xf6, Instruction::new(0xf6, ZeroPageX, "INC".into(), 2, 6)),
+        (0xee, Instruction::new(0xee, Absolute,  "INC".into(), 3, 6)),
+        (0xfe, Instruction::new(0xfe, AbsoluteX, "INC".into(), 3, 7)),
+
+        (0xc6, Instruction::new(0xc6, ZeroPage,  "DEC".into(), 2, 5)),
+        (0xd6, Instruction::new(0xd6, ZeroPageX, "DEC".into(), 2, 6)),
+        (0xce, Instruction::new(0xce, Absolute,  "DEC".into(), 3, 6)),
+        (0xde, Instruction::new(0xde, AbsoluteX, "DEC".into(), 3, 7)),
 
         // Memory Instructions
         (0xa9, Instruction::new(0xa9, Immediate, "LDA".into(), 2, 2)),
@@ -103,6 +261,18 @@ static INSTRUCTIONS: Lazy<HashMap<u8, Instruction>> = Lazy::new(|| {
         (0xa1, Instruction::new(0xa1, IndirectX, "LDA".into(), 2, 6)),
         (0xb1, Instruction::new(0xb1, IndirectY, "LDA".into(), 2, 5)),
 
+        (0xa2, Instruction::new(0xa2, Immediate, "LDX".into(), 2, 2)),
+        (0xa6, Instruction::new(0xa6, ZeroPage,  "LDX".into(), 2, 3)),
+        (0xb6, Instruction::new(0xb6, ZeroPageY, "LDX".into(), 2, 4)),
+        (0xae, Instruction::new(0xae, Absolute,  "LDX".into(), 3, 4)),
+        (0xbe, Instruction::new(0xbe, AbsoluteY, "LDX".into(), 3, 4)),
+
+        (0xa0, Instruction::new(0xa0, Immediate, "LDY".into(), 2, 2)),
+        (0xa4, Instruction::new(0xa4, ZeroPage,  "LDY".into(), 2, 3)),
+        (0xb4, Instruction::new(0xb4, ZeroPageX, "LDY".into(), 2, 4)),
+        (0xac, Instruction::new(0xac, Absolute,  "LDY".into(), 3, 4)),
+        (0xbc, Instruction::new(0xbc, AbsoluteX, "LDY".into(), 3, 4)),
+
         (0x85, Instruction::new(0x85, ZeroPage,  "STA".into(), 2, 3)),
         (0x95, Instruction::new(0x95, ZeroPageX, "STA".into(), 2, 4)),
         (0x8d, Instruction::new(0x8d, Absolute,  "STA".into(), 3, 4)),
@@ -111,8 +281,14 @@ static INSTRUCTIONS: Lazy<HashMap<u8, Instruction>> = Lazy::new(|| {
         (0x81, Instruction::new(0x81, IndirectX, "STA".into(), 2, 6)),
         (0x91, Instruction::new(0x91, IndirectY, "STA".into(), 2, 6)),
 
-        // more...
-        
+        (0x86, Instruction::new(0x86, ZeroPage,  "STX".into(), 2, 3)),
+        (0x96, Instruction::new(0x96, ZeroPageY, "STX".into(), 2, 4)),
+        (0x8e, Instruction::new(0x8e, Absolute,  "STX".into(), 3, 4)),
+
+        (0x84, Instruction::new(0x84, ZeroPage,  "STY".into(), 2, 3)),
+        (0x94, Instruction::new(0x94, ZeroPageX, "STY".into(), 2, 4)),
+        (0x8c, Instruction::new(0x8c, Absolute,  "STY".into(), 3, 4)),
+
         // Register Instructions
         (0xaa, Instruction::new(0xaa, Implied,    "TAX".into(), 1, 2)),
         (0xa8, Instruction::new(0xa8, Implied,    "TAY".into(), 1, 2)),
@@ -143,236 +319,406 @@ static INSTRUCTIONS: Lazy<HashMap<u8, Instruction>> = Lazy::new(|| {
     ])
 });
 
+// Reverse lookup used by the assembler, derived from the instruction table
+// so the two can never disagree.
 static MNEMONICS: Lazy<HashMap<(&str, AddressingMode), u8>> = Lazy::new(|| {
-    use AddressingMode::*;
-    HashMap::from([
-        (("LDX", Immediate), 0xa2),
-        (("SEI", Implied), 0x78),
-        (("TXS", Implied), 0x9a),
-        (("CLD", Implied), 0xd8),
-        (("JSR", Absolute), 0x20),
-        (("BNE", Absolute), 0xd0),
-        (("CLI", Implied), 0x58),
-        (("STX", Absolute), 0x8e),
-        (("JMP", Indirect), 0x6c),
-        // (("LDA", Absolute), 0x00), (("LDA", Immediate), 0x00)
-    ])
+    INSTRUCTIONS
+        .values()
+        .filter(|inst| inst.name != "???")
+        .map(|inst| ((inst.name.as_str(), inst.mode), inst.code))
+        .collect()
 });
 
-// #[rustfmt::skip]
-// static LOOKUP: Lazy<HashMap<>
-
 impl Block {
+    /// A dump of the bytes, 8 to a line, in hex and as ASCII
     pub fn memory(&self) -> Vec<String> {
-        let mut result: Vec<String> = vec![];
+        self.memory_as(Decoding::Ascii)
+    }
 
+    /// The same as `memory`, but with the bytes decoded as PETSCII or screen
+    /// codes instead of ASCII
+    pub fn memory_as(&self, decoding: Decoding) -> Vec<String> {
         let step = 8;
-        let max = self.instructions.len();
 
-        (0..max).step_by(step).for_each(|pos| {
-            let addr = self.start + pos as u16;
+        self.instructions
+            .chunks(step)
+            .enumerate()
+            .map(|(row, bytes)| {
+                let addr = self.start.wrapping_add((row * step) as u16);
+                memory_row(addr, bytes, decoding)
+            })
+            .collect()
+    }
 
-            let mut bytes = String::from("");
-            let mut decoded = String::from("");
+    /// The bytes as instructions, one to a line, from the start to the end
+    pub fn disassemble(&self) -> Vec<String> {
+        let mut result: Vec<String> = vec![];
 
-            let inner_max = std::cmp::min(pos + step, max);
+        let mut pos = 0;
 
-            for pos in pos..inner_max {
-                let byte = self.instructions[pos];
-                bytes += &format!("{byte:02X} ");
-                decoded += &format!("{}", byte as char);
+        while pos < self.instructions.len() {
+            let addr = self.start.wrapping_add(pos as u16);
 
-                // Add inner spacing
-                if pos % 4 == 3 {
-                    bytes += "  ";
+            match self.decode_at(pos) {
+                Some((bytes, decoded, length)) => {
+                    result.push(format!("{addr:04X}   {bytes}   {decoded}"));
+                    pos += length;
+                }
+                None => {
+                    // The last instruction is cut off by the end of the block
+                    let byte = self.instructions[pos];
+                    result.push(format!("{addr:04X}   {byte:02X}         .byte ${byte:02X}"));
+                    pos += 1;
                 }
             }
-            result.push(format!("{addr:04X}   {bytes:28}{decoded}",));
-        });
+        }
         result
     }
 
-    pub fn disassemble(&self) -> Vec<String> {
-        let mut result: Vec<String> = vec![];
+    // Decodes the instruction at `pos`, returning the formatted bytes, the
+    // decoded text and the length of the instruction. Returns None if the
+    // instruction doesn't fit within the block.
+    fn decode_at(&self, pos: usize) -> Option<(String, String, usize)> {
+        let opcode = self.instructions[pos];
+        let Instruction {
+            name, length, mode, ..
+        } = decode(&opcode);
+
+        let length = *length as usize;
+        let operand = self.instructions.get(pos + 1..pos + length)?;
+        let addr = self.start.wrapping_add(pos as u16);
+
+        let bytes = match operand {
+            [] => format!("{opcode:02X}      "),
+            [lo] => format!("{opcode:02X} {lo:02X}   "),
+            [lo, hi] => format!("{opcode:02X} {lo:02X} {hi:02X}"),
+            _ => panic!(),
+        };
 
-        let mut pos = 0;
+        let decoded = match operand {
+            [] => name.to_string(),
+            _ => format!("{name} {}", format_operand(*mode, addr, operand)),
+        };
 
-        while pos < self.instructions.len() {
-            let inst = self.instructions[pos];
-            let Instruction {
-                code,
-                name,
-                length,
-                mode,
-                cycles,
-            } = decode(&inst);
-
-            let addr = self.start + pos as u16;
-
-            let (bytes, decoded) = match length {
-                1 => {
-                    let bytes = format!("{code:02X}      ");
-                    let decoded = name.to_string();
-                    (bytes, decoded)
-                }
-                2 => {
-                    let lo = self.instructions[pos + 1];
-                    let bytes = format!("{code:02X} {lo:02X}   ");
-                    let decoded = match mode {
-                        AddressingMode::Relative => format!("{name} ${:04X}", addr + lo as u16 + 2),
-                        _ => format!("{name} #${lo:02X}"),
-                    };
-                    (bytes, decoded)
-                }
-                3 => {
-                    let lo = self.instructions[pos + 1];
-                    let hi = self.instructions[pos + 2];
-                    let bytes = format!("{code:02X} {lo:02X} {hi:02X}");
-                    let decoded = match mode {
-                        AddressingMode::Indirect => format!("{name} (${hi:02X}{lo:02X})"),
-                        _ => format!("{name} ${hi:02X}{lo:02X}"),
-                    };
-                    (bytes, decoded)
-                }
-                _ => panic!(),
-            };
+        Some((bytes, decoded, length))
+    }
 
-            result.push(format!("{addr:04X}   {bytes}   {decoded}",));
-            pos += *length as usize;
+    /// A really simple assembler function, to be able to
+    /// enter some code easily into the emulator, to test
+    /// it out a bit simpler during development.
+    ///
+    /// Panics when the source doesn't assemble, see `try_assemble`.
+    pub fn assemble(source: &str) -> Self {
+        match Self::try_assemble(source) {
+            Ok(block) => block,
+            Err(err) => panic!("{err}"),
         }
-        result
     }
 
-    // A really simple assembler function, to be able to
-    // enter some code easily into the emulator, to test
-    // it out a bit simpler during development.
-    fn assemble(source: &str) -> Self {
-        let mut found_start = false;
-        let mut start: u16 = 0x0000;
-        let mut instructions: Vec<u8> = vec![];
+    /// Assembles the source, one instruction or `.byte` per line, after a
+    /// first line with the start address, e.g. `*= $c000`. A line can start
+    /// with a label, e.g. `loop:`, to use instead of the address it is at.
+    pub fn try_assemble(source: &str) -> Result<Self, AsmError> {
+        // The first pass finds where the labels are, for the second to use
+        let (_, labels) = assemble_pass(source, None)?;
+        let (block, _) = assemble_pass(source, Some(&labels))?;
+        Ok(block)
+    }
+}
+
+// Assembles the source with the labels found by an earlier pass, returning
+// the block and the labels found in it. Without any labels yet, every label
+// is the address of the instruction using it, so the instructions still get
+// the right length, as the address of a label is always written as a word.
+fn assemble_pass(
+    source: &str,
+    labels: Option<&HashMap<String, u16>>,
+) -> Result<(Block, HashMap<String, u16>), AsmError> {
+    let mut start: Option<u16> = None;
+    let mut instructions: Vec<u8> = vec![];
+    let mut found: HashMap<String, u16> = HashMap::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: String| AsmError {
+            line: index + 1,
+            message,
+        };
+
+        let instruction = line.split(';').next().unwrap_or_default().trim();
+
+        // We're not interested in empty lines or comments
+        if instruction.is_empty() {
+            continue;
+        }
 
         // For now, require the start-address to be the first "instruction".
         // E.g. *= $0810
-        for line in source.lines() {
-            let instruction = {
-                let l: Vec<&str> = line.split(';').collect();
-                l[0].trim()
-            };
-
-            // Find the start address first
-            if !found_start {
-                let op = instruction.trim_start_matches("*= $");
-                if let Ok(val) = u16::from_str_radix(op, 16) {
-                    start = val;
-                    found_start = true;
+        let Some(origin) = start else {
+            let op = instruction.trim_start_matches("*=").trim();
+            match parse_params(op).map(|bytes| bytes_to_word(&bytes)) {
+                Ok(val) if instruction.starts_with("*=") => start = Some(val),
+                _ => {
+                    return Err(error(format!(
+                        "expected a start address, found `{instruction}`"
+                    )))
                 }
-            } else {
-                // We're not interested in empty lines or comments
-                if instruction.is_empty() {
-                    continue;
+            }
+            continue;
+        };
+
+        let address = origin.wrapping_add(instructions.len() as u16);
+        let instruction = match instruction.split_once(':') {
+            Some((label, rest)) if is_label(label.trim()) => {
+                let label = label.trim();
+                if found.insert(label.to_string(), address).is_some() {
+                    return Err(error(format!("the label `{label}` is already used")));
                 }
+                rest.trim()
+            }
+            _ => instruction,
+        };
+        if instruction.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, params) = match instruction.split_once(char::is_whitespace) {
+            Some((mnemonic, params)) => (mnemonic, params.trim()),
+            None => (instruction, ""),
+        };
+        let params = &with_label_address(params, labels, address).map_err(error)?;
 
-                let (mnemonic, params) = {
-                    let l: Vec<&str> = instruction.split(' ').collect();
-                    if l.len() == 1 {
-                        (l[0], "")
-                    } else {
-                        (l[0], l[1])
-                    }
-                };
-
-                // Let's deduce the addressing mode
-                use AddressingMode::*;
-                let mode = match params {
-                    "" => AddressingMode::Implied,
-                    x if x.starts_with('#') => Immediate,
-                    x if x.starts_with('(') => match x {
-                        x if x.ends_with(')') => Indirect,
-                        x if x.ends_with("),x") => IndirectX,
-                        x if x.ends_with("),y") => IndirectY,
-                        _ => panic!(),
-                    },
-                    x if x.starts_with('$') => Absolute,
-                    _ => panic!(),
-                };
-
-                let mut decoded = parse_params(params);
-
-                let unknown = 0xef;
-                let code = MNEMONICS.get(&(&mnemonic, mode)).unwrap_or(&unknown);
-
-                println!("{instruction:16} -> {mnemonic} {params:8} - {mode:12?} -> {code:4x} {decoded:x?}");
-
-                instructions.push(*code);
-                if mnemonic == "BNE" {
-                    instructions.push(0x03);
-                } else {
-                    instructions.append(&mut decoded);
+        if mnemonic.eq_ignore_ascii_case(".byte") {
+            for value in params.split(',') {
+                match parse_params(value.trim()).map_err(error)?[..] {
+                    [byte] => instructions.push(byte),
+                    _ => return Err(error(format!("`{}` doesn't fit in a byte", value.trim()))),
                 }
+            }
+            continue;
+        }
+
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        let mode = addressing_mode(&mnemonic, params).map_err(error)?;
 
-                // match mode {
-                //     Absolute => todo!(),
-                //     AbsoluteX => todo!(),
-                //     AbsoluteY => todo!(),
-                //     Immediate => instructions.append(decoded.clone().as_mut()),
-                //     Implied => continue,
-                //     Indirect => todo!(),
-                //     IndirectX => todo!(),
-                //     IndirectY => todo!(),
-                //     Relative => todo!(),
-                //     ZeroPage => todo!(),
-                //     ZeroPageX => todo!(),
-                //     ZeroPageY => todo!(),
-                // }
+        let Some(code) = MNEMONICS.get(&(mnemonic.as_str(), mode)) else {
+            return Err(error(format!(
+                "{mnemonic} doesn't support {mode:?} addressing"
+            )));
+        };
+        instructions.push(*code);
+
+        use AddressingMode::*;
+        match mode {
+            Implied => {}
+            Relative => {
+                let target = bytes_to_word(&parse_params(params).map_err(error)?);
+                let pc = origin.wrapping_add(instructions.len() as u16 + 1);
+                let offset = target.wrapping_sub(pc) as i16;
+                if !(-128..=127).contains(&offset) {
+                    return Err(error(format!(
+                        "branch target ${target:04X} is out of range"
+                    )));
+                }
+                instructions.push(offset as u8);
             }
+            _ => instructions.append(&mut parse_params(params).map_err(error)?),
+        }
+    }
+
+    match start {
+        Some(start) => Ok((
+            Block {
+                start,
+                instructions,
+            },
+            found,
+        )),
+        None => Err(AsmError {
+            line: source.lines().count(),
+            message: "missing start address".into(),
+        }),
+    }
+}
+
+/// Source that doesn't assemble, and the line of it
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+// Formats one row of a memory dump, the hex values in two groups of
+// four bytes followed by the bytes as characters.
+fn memory_row(addr: u16, bytes: &[u8], decoding: Decoding) -> String {
+    let mut hex = String::from("");
+    let mut decoded = String::from("");
+
+    for (pos, byte) in bytes.iter().enumerate() {
+        hex += &format!("{byte:02X} ");
+        decoded.push(decoding.decode(*byte));
+
+        // Add inner spacing
+        if pos % 4 == 3 {
+            hex += "  ";
         }
+    }
+    format!("{addr:04X}   {hex:28}{decoded}")
+}
+
+fn format_operand(mode: AddressingMode, addr: u16, operand: &[u8]) -> String {
+    use AddressingMode::*;
+    match (mode, operand) {
+        (Immediate, [lo]) => format!("#${lo:02X}"),
+        (ZeroPage, [lo]) => format!("${lo:02X}"),
+        (ZeroPageX, [lo]) => format!("${lo:02X},X"),
+        (ZeroPageY, [lo]) => format!("${lo:02X},Y"),
+        (IndirectX, [lo]) => format!("(${lo:02X},X)"),
+        (IndirectY, [lo]) => format!("(${lo:02X}),Y"),
+        (Relative, [lo]) => format!("${:04X}", branch_target(addr, *lo)),
+        (Absolute, [lo, hi]) => format!("${hi:02X}{lo:02X}"),
+        (AbsoluteX, [lo, hi]) => format!("${hi:02X}{lo:02X},X"),
+        (AbsoluteY, [lo, hi]) => format!("${hi:02X}{lo:02X},Y"),
+        (Indirect, [lo, hi]) => format!("(${hi:02X}{lo:02X})"),
+        _ => panic!(),
+    }
+}
+
+// The offset of a branch is relative to the instruction following it
+fn branch_target(addr: u16, offset: u8) -> u16 {
+    addr.wrapping_add(2).wrapping_add(offset as i8 as u16)
+}
 
-        Block {
-            start,
-            instructions,
+// Deduce the addressing mode from the syntax of the parameters, and
+// from the width of the value when there is a zero page alternative.
+fn addressing_mode(mnemonic: &str, params: &str) -> Result<AddressingMode, String> {
+    use AddressingMode::*;
+
+    if params.is_empty() || params.eq_ignore_ascii_case("A") {
+        return Ok(Implied);
+    }
+
+    if MNEMONICS.contains_key(&(mnemonic, Relative)) {
+        return Ok(Relative);
+    }
+
+    let upper = params.to_ascii_uppercase();
+    let wide = parse_params(params)?.len() == 2;
+
+    let mode = match upper {
+        x if x.starts_with('#') => Immediate,
+        x if x.starts_with('(') => match x {
+            x if x.ends_with(",X)") => IndirectX,
+            x if x.ends_with("),Y") => IndirectY,
+            x if x.ends_with(')') => Indirect,
+            _ => return Err(format!("unknown addressing mode `{params}`")),
+        },
+        x if x.ends_with(",X") => match wide || !MNEMONICS.contains_key(&(mnemonic, ZeroPageX)) {
+            true => AbsoluteX,
+            false => ZeroPageX,
+        },
+        x if x.ends_with(",Y") => match wide || !MNEMONICS.contains_key(&(mnemonic, ZeroPageY)) {
+            true => AbsoluteY,
+            false => ZeroPageY,
+        },
+        _ => match wide || !MNEMONICS.contains_key(&(mnemonic, ZeroPage)) {
+            true => Absolute,
+            false => ZeroPage,
+        },
+    };
+
+    match mode {
+        Immediate | IndirectX | IndirectY if wide => {
+            Err(format!("`{params}` doesn't fit in a byte"))
         }
+        _ => Ok(mode),
     }
 }
 
-fn parse_params(params: &str) -> Vec<u8> {
-    let without_prefix = params
+// Parses the value of the parameters into little endian bytes, where the
+// amount of bytes follows the amount of digits used, e.g. $12 vs $0012.
+fn parse_params(params: &str) -> Result<Vec<u8>, String> {
+    let upper = params.to_ascii_uppercase();
+    let without_prefix = upper
         .trim_start_matches('(')
         .trim_start_matches('#')
-        .trim_start_matches('$')
+        .trim_end_matches(",Y")
+        .trim_end_matches(')')
+        .trim_end_matches(",X")
         .trim_end_matches(')');
 
-    match without_prefix.len() {
-        0 => vec![],
-        1 | 2 => {
-            let lo = u8::from_str_radix(without_prefix, 16).unwrap();
-            vec![lo]
-        }
-        3 => {
-            let hi_str = &without_prefix[0..1];
-            let lo_str = &without_prefix[1..3];
-            let hi = u8::from_str_radix(hi_str, 16).unwrap();
-            let lo = u8::from_str_radix(lo_str, 16).unwrap();
-
-            // println!(
-            //     "{params}, {len} - {hi_str} {lo_str}, {lo} {hi}",
-            //     len = without_prefix.len()
-            // );
-            vec![lo, hi]
-        }
-        4 => {
-            let hi_str = &without_prefix[0..2];
-            let lo_str = &without_prefix[2..4];
-            let hi = u8::from_str_radix(hi_str, 16).unwrap();
-            let lo = u8::from_str_radix(lo_str, 16).unwrap();
-
-            // println!(
-            //     "{params}, {len} - {hi_str} {lo_str}, {lo} {hi}",
-            //     len = without_prefix.len()
-            // );
-            vec![lo, hi]
-        }
-        _ => panic!(),
+    let (digits, radix, chars_per_byte) = match without_prefix {
+        x if x.starts_with('$') => (&x[1..], 16, 2),
+        x if x.starts_with('%') => (&x[1..], 2, 8),
+        x => (x, 10, 3),
+    };
+
+    let value = match u16::from_str_radix(digits, radix) {
+        Ok(value) if !digits.is_empty() => value,
+        _ => return Err(format!("invalid value `{params}`")),
+    };
+
+    let wide = match radix {
+        10 => value > 0xff,
+        _ => digits.len() > chars_per_byte,
+    };
+
+    match wide {
+        false if value <= 0xff => Ok(vec![value as u8]),
+        false => Err(format!("invalid value `{params}`")),
+        true => Ok(value.to_le_bytes().to_vec()),
+    }
+}
+
+fn bytes_to_word(bytes: &[u8]) -> u16 {
+    match bytes {
+        [lo] => *lo as u16,
+        [lo, hi] => u16::from_le_bytes([*lo, *hi]),
+        _ => 0,
+    }
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Puts the address of the label in the parameters in place of it, where the
+// address of the instruction stands in for it until the labels are known
+fn with_label_address(
+    params: &str,
+    labels: Option<&HashMap<String, u16>>,
+    address: u16,
+) -> Result<String, String> {
+    let (prefix, rest) = match params.strip_prefix('(') {
+        Some(rest) => ("(", rest),
+        None => ("", params),
+    };
+    let length = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+    let (label, suffix) = rest.split_at(length);
+
+    if !is_label(label) || params.eq_ignore_ascii_case("A") {
+        return Ok(params.to_string());
     }
+    let value = match labels {
+        Some(labels) => *labels
+            .get(label)
+            .ok_or_else(|| format!("unknown label `{label}`"))?,
+        None => address,
+    };
+    Ok(format!("{prefix}${value:04X}{suffix}"))
 }
 
 impl Display for Block {
@@ -385,33 +731,128 @@ impl Display for Block {
     }
 }
 
-pub(crate) struct C64 {
-    memory: Memory,
+/// The whole machine, the CPU connected to the bus with everything else
+pub struct C64 {
     cpu: Cpu,
-    bus: Bus,
 }
 
-type Memory = [u8; 0xffff];
+type Memory = [u8; 0x10000];
 
 impl C64 {
-    // FIXME: This is a shitty implementation for now,
-    // lets get back to this and fix it...
+    /// A PAL machine, without any ROMs
     pub fn new() -> Self {
+        Self::with_model(Model::default())
+    }
+
+    pub fn with_model(model: Model) -> Self {
         let mut cpu = Cpu::new();
-        let memory: Memory = [0; 0xffff];
-        let bus = Bus::new(cpu.clone(), memory);
+        cpu.connect_bus(Bus::with_model([0; 0x10000], model));
 
-        cpu.connect_bus(bus.clone());
+        C64 { cpu }
+    }
 
-        C64 { cpu, memory, bus }
+    pub fn model(&self) -> Model {
+        self.cpu.bus().model()
     }
 
+    /// Resets the CPU, which starts at the reset vector with the next step
     pub fn reset(&mut self) {
         self.cpu.reset();
     }
 
-    pub fn run(&self, _block: Block) {
-        todo!()
+    pub fn set_roms(&mut self, roms: Roms) {
+        self.cpu.bus_mut().set_roms(roms);
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    /// The CPU, for changing the registers, and the bus through it
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Executes the next instruction, returning the amount of cycles it took
+    pub fn step(&mut self) -> u32 {
+        self.cpu.step()
+    }
+
+    /// Executes instructions until the VIC-II has completed the frame
+    pub fn run_frame(&mut self) {
+        let frame = self.vic().frames();
+        while self.vic().frames() == frame {
+            self.step();
+        }
+    }
+
+    pub fn vic(&self) -> &VicII {
+        self.cpu.bus().vic()
+    }
+
+    /// Changes the palette the framebuffer of the VIC-II is drawn with
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.bus_mut().vic_mut().set_palette(palette);
+    }
+
+    pub fn sid(&self) -> &Sid {
+        self.cpu.bus().sid()
+    }
+
+    /// Starts producing audio samples at the rate, in Hz, or stops when None
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.cpu.bus_mut().sid_mut().set_sample_rate(sample_rate);
+    }
+
+    /// The audio samples produced since the last time they were taken
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.cpu.bus_mut().sid_mut().take_samples()
+    }
+
+    pub fn keyboard(&self) -> &Keyboard {
+        self.cpu.bus().keyboard()
+    }
+
+    /// Holds the key down, until released
+    pub fn press_key(&mut self, key: Key) {
+        self.cpu.bus_mut().keyboard_mut().press(key);
+    }
+
+    pub fn release_key(&mut self, key: Key) {
+        self.cpu.bus_mut().keyboard_mut().release(key);
+    }
+
+    pub fn release_all_keys(&mut self) {
+        self.cpu.bus_mut().keyboard_mut().release_all();
+    }
+
+    /// Holds down exactly the keys, releasing the rest. Frontends hold down
+    /// everything for the host keys still held, so SHIFT stays down when it
+    /// is held while a key that adds SHIFT is released.
+    pub fn hold_keys(&mut self, keys: impl IntoIterator<Item = Key>) {
+        let keyboard = self.cpu.bus_mut().keyboard_mut();
+        keyboard.release_all();
+        keys.into_iter().for_each(|key| keyboard.press(key));
+    }
+
+    /// Closes the switch of the joystick in the port, until released
+    pub fn press_joystick(&mut self, port: Port, switch: Switch) {
+        self.cpu.bus_mut().joystick_mut(port).press(switch);
+    }
+
+    pub fn release_joystick(&mut self, port: Port, switch: Switch) {
+        self.cpu.bus_mut().joystick_mut(port).release(switch);
+    }
+
+    /// Reads what the CPU would, without any of the side effects of reading
+    /// the chip registers
+    pub fn peek(&self, address: u16) -> u8 {
+        self.cpu.read(address as usize)
+    }
+
+    /// Writes what the CPU would, without spending any cycles
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.cpu.write(address as usize, value);
     }
 }
 
@@ -421,9 +862,19 @@ impl Default for C64 {
     }
 }
 
+// A directory in the temp directory for a test to write its files to, named
+// after the process as well, so that test runs at the same time keep apart
+#[cfg(test)]
+pub(crate) fn test_directory(name: &str) -> std::path::PathBuf {
+    let directory = std::env::temp_dir().join(format!("c64-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn should_be_able_to_init_the_machine() {
@@ -432,6 +883,102 @@ mod tests {
         assert_eq!(c64.cpu.PC, 0xfffc);
     }
 
+    // Finds the low byte of the last line the same way as many demos do, by
+    // waiting for $d012 to go down instead of up
+    #[rustfmt::skip]
+    const DETECT_MODEL: [u8; 17] = [
+        0xad, 0x12, 0xd0,   // LDA $D012
+        0xcd, 0x12, 0xd0,   // CMP $D012
+        0xf0, 0xfb,         // BEQ $1003
+        0x30, 0xf6,         // BMI $1000
+        0x85, 0x02,         // STA $02
+        0x4c, 0x0c, 0x10,   // JMP $100C
+        0x00, 0x00,
+    ];
+
+    #[test]
+    fn should_detect_model_by_its_last_line() {
+        for (model, last_line) in [
+            (Model::Pal, 0x37),
+            (Model::Ntsc, 0x06),
+            (Model::NtscOld, 0x05),
+            (Model::PalN, 0x37),
+        ] {
+            let mut c64 = C64::with_model(model);
+            c64.load(&Block {
+                start: 0x1000,
+                instructions: DETECT_MODEL.to_vec(),
+            });
+            c64.cpu.jump(0x1000);
+
+            c64.run_frame();
+            c64.run_frame();
+            assert_eq!(c64.peek(0x0002), last_line, "{model}");
+        }
+    }
+
+    #[test]
+    fn should_run_at_the_speed_of_the_model() {
+        for model in Model::ALL {
+            let mut c64 = C64::with_model(model);
+            c64.poke(0x1000, 0x4c);
+            c64.poke(0x1001, 0x00);
+            c64.poke(0x1002, 0x10);
+            c64.cpu.jump(0x1000);
+
+            // Set up for 60Hz, the time of day runs slow on 50Hz mains
+            while c64.cpu.cycles() < model.clock() as u64 / 5 {
+                c64.step();
+            }
+            let tenths = match model.mains_frequency() {
+                50 => 0x01,
+                _ => 0x02,
+            };
+            assert_eq!(c64.peek(0xdc08), tenths, "{model}");
+
+            let timing = model.timing();
+            assert_eq!(c64.vic().width(), timing.cycles_per_line as usize * 8);
+            assert_eq!(c64.cpu.bus().sid().clock(), model.clock());
+        }
+    }
+
+    #[test]
+    fn should_scan_keyboard_through_cia1() {
+        let mut c64 = C64::new();
+        c64.poke(0xdc02, 0xff);
+        c64.poke(0xdc03, 0x00);
+
+        // Space is in column 7, row 4
+        c64.press_key(Key::Space);
+        c64.poke(0xdc00, !0x80);
+        assert_eq!(c64.peek(0xdc01), !0x10);
+        c64.poke(0xdc00, !0x01);
+        assert_eq!(c64.peek(0xdc01), 0xff);
+
+        c64.release_key(Key::Space);
+        c64.poke(0xdc00, 0x00);
+        assert_eq!(c64.peek(0xdc01), 0xff);
+    }
+
+    #[test]
+    fn should_read_joysticks_through_cia1() {
+        let mut c64 = C64::new();
+        c64.poke(0xdc02, 0xff);
+        c64.poke(0xdc03, 0x00);
+        c64.poke(0xdc00, 0xff);
+
+        c64.press_joystick(Port::Two, Switch::Fire);
+        c64.press_joystick(Port::One, Switch::Left);
+        assert_eq!(c64.peek(0xdc00), !0x10);
+        assert_eq!(c64.peek(0xdc01), !0x04);
+
+        // Fire in port 2 pulls column 4 low, like scanning it, so the row of
+        // a key held down in that column reads as pressed
+        c64.release_joystick(Port::One, Switch::Left);
+        c64.press_key(Key::M);
+        assert_eq!(c64.peek(0xdc01), !0x10);
+    }
+
     // rust-fmt disable
     #[rustfmt::skip]
     #[test]
@@ -483,6 +1030,30 @@ mod tests {
         // cspell: enable
     }
 
+    #[test]
+    fn should_decode_memory() {
+        use super::petscii::Charset;
+
+        // "HELLO" in screen codes, followed by a heart
+        let block = Block {
+            start: 0x0400,
+            instructions: vec![0x08, 0x05, 0x0c, 0x0c, 0x0f, 0x20, 0x53, 0x00],
+        };
+
+        assert_eq!(
+            block.memory(),
+            vec!["0400   08 05 0C 0C   0F 20 53 00   ..... S."]
+        );
+        assert_eq!(
+            block.memory_as(Decoding::ScreenCodes(Charset::Uppercase)),
+            vec!["0400   08 05 0C 0C   0F 20 53 00   HELLO ♥@"]
+        );
+        assert_eq!(
+            block.memory_as(Decoding::ScreenCodes(Charset::Lowercase)),
+            vec!["0400   08 05 0C 0C   0F 20 53 00   hello S@"]
+        );
+    }
+
     #[test]
     fn should_assemble_block() {
         let block = Block::assemble(
@@ -522,13 +1093,134 @@ mod tests {
 
         assert_eq!(block.start, expected.start);
         assert_eq!(block.instructions.len(), expected.instructions.len());
+        assert_eq!(block.instructions, expected.instructions);
+    }
+
+    #[test]
+    fn should_assemble_labels() {
+        let block = Block::assemble(
+            r"
+            *= $c000
+            start:  LDX #$00
+            loop:
+                    LDA text,X
+                    BEQ done
+                    JSR $FFD2
+                    INX
+                    BNE loop
+            done:   JMP (vector)
+            text:   .byte $48, $49, $00
+            vector: .byte $00, $c0
+            ",
+        );
+
+        assert_eq!(block.disassemble()[1], "C002   BD 10 C0   LDA $C010,X");
+        assert_eq!(block.disassemble()[2], "C005   F0 06      BEQ $C00D");
+        assert_eq!(block.disassemble()[5], "C00B   D0 F5      BNE $C002");
+        assert_eq!(block.disassemble()[6], "C00D   6C 13 C0   JMP ($C013)");
+
+        let result = Block::try_assemble("*= $c000\nJMP nowhere");
+        assert_eq!(result.unwrap_err().message, "unknown label `nowhere`");
+        let result = Block::try_assemble("*= $c000\na: NOP\na: NOP");
+        assert_eq!(result.unwrap_err().line, 3);
     }
 
     #[test]
     fn should_parse_params() {
         let raw = "($a000)";
-        let result = parse_params(raw);
+        let result = parse_params(raw).unwrap();
 
         assert_eq!(vec![0x00, 0xa0], result);
     }
+
+    // Turns the listing from `Block::disassemble` back into source, by
+    // dropping the address and bytes columns in front of each instruction.
+    fn listing_to_source(block: &Block) -> String {
+        let mut source = format!("*= ${:04X}\n", block.start);
+        for line in block.disassemble() {
+            source += &line[18..];
+            source += "\n";
+        }
+        source
+    }
+
+    fn known_opcodes() -> Vec<u8> {
+        let mut opcodes: Vec<u8> = INSTRUCTIONS
+            .values()
+            .filter(|inst| inst.name != "???")
+            .map(|inst| inst.code)
+            .collect();
+        opcodes.sort();
+        opcodes
+    }
+
+    #[test]
+    fn should_round_trip_every_opcode() {
+        for code in known_opcodes() {
+            let length = decode(&code).length as usize;
+            let mut instructions = vec![code, 0x12, 0x34];
+            instructions.truncate(length);
+
+            let block = Block {
+                start: 0xc000,
+                instructions,
+            };
+            assert_eq!(Block::assemble(&listing_to_source(&block)), block);
+        }
+    }
+
+    #[test]
+    fn should_cover_every_addressing_mode() {
+        use AddressingMode::*;
+
+        let modes: Vec<AddressingMode> = known_opcodes()
+            .iter()
+            .map(|code| decode(code).mode)
+            .collect();
+        for mode in [
+            Absolute, AbsoluteX, AbsoluteY, Immediate, Implied, Indirect, IndirectX, IndirectY,
+            Relative, ZeroPage, ZeroPageX, ZeroPageY,
+        ] {
+            assert!(modes.contains(&mode), "{mode:?} is missing");
+        }
+    }
+
+    // A random, valid instruction with random operand bytes
+    fn instruction() -> impl Strategy<Value = Vec<u8>> {
+        prop::sample::select(known_opcodes()).prop_flat_map(|code| {
+            let length = decode(&code).length as usize;
+            prop::collection::vec(any::<u8>(), length - 1).prop_map(move |operand| {
+                let mut bytes = vec![code];
+                bytes.extend(operand);
+                bytes
+            })
+        })
+    }
+
+    fn instruction_stream() -> impl Strategy<Value = Block> {
+        (any::<u16>(), prop::collection::vec(instruction(), 1..64)).prop_map(|(start, stream)| {
+            Block {
+                start,
+                instructions: stream.concat(),
+            }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn should_reassemble_disassembled_streams(block in instruction_stream()) {
+            prop_assert_eq!(Block::assemble(&listing_to_source(&block)), block);
+        }
+
+        #[test]
+        fn should_reassemble_flow_disassembly(block in instruction_stream(), data in prop::collection::vec(any::<u8>(), 0..32)) {
+            let mut block = block;
+            block.instructions.extend(data);
+
+            let source = block.disassemble_flow(&[]).join("\n");
+            let labelled = block.disassemble_labelled(&[]).join("\n");
+            prop_assert_eq!(&Block::assemble(&source), &block);
+            prop_assert_eq!(Block::assemble(&labelled), block);
+        }
+    }
 }
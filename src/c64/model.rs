@@ -0,0 +1,118 @@
+//! The C64 was built with different versions of the VIC-II, depending on the TV
+//! standard of the country it was sold in. As the clock of the CPU is derived
+//! from the dot clock of the VIC-II, the version decides the speed of the whole
+//! machine, including the pitch of the SID:
+//!
+//! - PAL       - 6569,      63 cycles per line, 312 lines, 0.985 MHz
+//! - NTSC      - 6567R8,    65 cycles per line, 263 lines, 1.023 MHz
+//! - Old NTSC  - 6567R56A,  64 cycles per line, 262 lines, 1.023 MHz
+//! - PAL-N     - 6572,      65 cycles per line, 312 lines, 1.023 MHz
+//!
+//! The time of day clocks of the CIAs count the cycles of the mains instead,
+//! which is 50Hz in the PAL countries and 60Hz in the NTSC ones.
+//!
+//! Programs tell the models apart by the number of lines, i.e. the last value
+//! of $d012, and by the number of cycles per line.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::vicII::Timing;
+
+/// The version of the machine, by its VIC-II
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Model {
+    /// The 6569
+    #[default]
+    Pal,
+    /// The 6567R8
+    Ntsc,
+    /// The 6567R56A, found in the earliest NTSC machines
+    NtscOld,
+    /// The 6572, as in the Drean C64 sold in Argentina
+    PalN,
+}
+
+impl Model {
+    pub const ALL: [Model; 4] = [Model::Pal, Model::Ntsc, Model::NtscOld, Model::PalN];
+
+    pub fn timing(&self) -> Timing {
+        match self {
+            Model::Pal => Timing::PAL,
+            Model::Ntsc => Timing::NTSC,
+            Model::NtscOld => Timing::NTSC_OLD,
+            Model::PalN => Timing::PAL_N,
+        }
+    }
+
+    /// The clock of the CPU and the SID, in Hz
+    pub fn clock(&self) -> u32 {
+        match self {
+            Model::Pal => 985_248,
+            Model::Ntsc | Model::NtscOld => 1_022_727,
+            Model::PalN => 1_023_440,
+        }
+    }
+
+    /// The frequency of the mains, which drives the time of day clocks
+    pub fn mains_frequency(&self) -> u32 {
+        match self {
+            Model::Pal | Model::PalN => 50,
+            Model::Ntsc | Model::NtscOld => 60,
+        }
+    }
+
+    /// The number of frames per second
+    pub fn frame_rate(&self) -> f64 {
+        let timing = self.timing();
+        self.clock() as f64 / (timing.cycles_per_line as f64 * timing.lines_per_frame as f64)
+    }
+}
+
+impl Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Model::Pal => write!(f, "PAL (6569)"),
+            Model::Ntsc => write!(f, "NTSC (6567R8)"),
+            Model::NtscOld => write!(f, "old NTSC (6567R56A)"),
+            Model::PalN => write!(f, "PAL-N (6572)"),
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pal" | "6569" => Ok(Model::Pal),
+            "ntsc" | "6567r8" => Ok(Model::Ntsc),
+            "ntsc-old" | "6567r56a" => Ok(Model::NtscOld),
+            "pal-n" | "drean" | "6572" => Ok(Model::PalN),
+            _ => Err(format!(
+                "unknown model '{s}', expected pal, ntsc, ntsc-old or pal-n"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_model() {
+        assert_eq!("PAL".parse(), Ok(Model::Pal));
+        assert_eq!("ntsc-old".parse(), Ok(Model::NtscOld));
+        assert_eq!("drean".parse(), Ok(Model::PalN));
+        assert!("secam".parse::<Model>().is_err());
+    }
+
+    #[test]
+    fn should_have_frame_rate_of_video_standard() {
+        assert_eq!(Model::Pal.frame_rate().round(), 50.0);
+        assert_eq!(Model::Ntsc.frame_rate().round(), 60.0);
+        assert_eq!(Model::NtscOld.frame_rate().round(), 61.0);
+        assert_eq!(Model::PalN.frame_rate().round(), 50.0);
+    }
+}
@@ -0,0 +1,231 @@
+//! A machine language monitor, in the spirit of the one in VICE, for looking
+//! at a stopped machine from any frontend: it takes one line of input at a
+//! time and answers with the lines to show. Addresses and bytes are in hex,
+//! with or without a '$' in front.
+//!
+//! ```text
+//! r                 - the registers, and where the VIC-II is in the frame
+//! m [start [end]]   - memory, 64 bytes unless an end is given
+//! d [start [end]]   - disassembly, 32 bytes unless an end is given
+//! > address bytes   - writes the bytes to memory
+//! z [count]         - steps one instruction, or the count of them
+//! g [address]       - continues, at the address if given
+//! x                 - continues
+//! ```
+//!
+//! Without a start, m and d carry on from where the last one of them stopped,
+//! starting at the PC.
+
+use super::cpu::StatusFlags;
+use super::{Block, C64};
+
+const MEMORY_BYTES: u16 = 64;
+const DISASSEMBLY_BYTES: u16 = 32;
+
+/// What the frontend should do after a command
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    /// Show the lines, and wait for the next command
+    Lines(Vec<String>),
+    /// Leave the monitor and let the machine run
+    Resume,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Monitor {
+    // Where m and d continue from, if they have been used
+    next_address: Option<u16>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Executes a line of input, where errors are shown like any other lines
+    pub fn execute(&mut self, c64: &mut C64, line: &str) -> Reply {
+        match self.command(c64, line) {
+            Ok(reply) => reply,
+            Err(err) => Reply::Lines(vec![format!("error: {err}")]),
+        }
+    }
+
+    fn command(&mut self, c64: &mut C64, line: &str) -> Result<Reply, String> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(Reply::Lines(vec![]));
+        };
+        let args = words.map(parse_hex).collect::<Result<Vec<u16>, _>>()?;
+
+        let lines = match (command, &args[..]) {
+            ("r", []) => vec![registers(c64)],
+            ("m", args) => {
+                let (start, end) = self.range(c64, args, MEMORY_BYTES)?;
+                block(c64, start, end).memory()
+            }
+            ("d", args) => {
+                let (start, end) = self.range(c64, args, DISASSEMBLY_BYTES)?;
+                block(c64, start, end).disassemble()
+            }
+            (">", [address, bytes @ ..]) if !bytes.is_empty() => {
+                for (offset, byte) in bytes.iter().enumerate() {
+                    let byte = u8::try_from(*byte).map_err(|_| format!("{byte:x} isn't a byte"))?;
+                    c64.poke(address.wrapping_add(offset as u16), byte);
+                }
+                vec![]
+            }
+            ("z", [] | [_]) => {
+                let count = args.first().copied().unwrap_or(1);
+                for _ in 0..count {
+                    c64.step();
+                }
+                vec![registers(c64)]
+            }
+            ("g", [] | [_]) => {
+                if let Some(address) = args.first() {
+                    c64.cpu.jump(*address);
+                }
+                return Ok(Reply::Resume);
+            }
+            ("x", []) => return Ok(Reply::Resume),
+            _ => return Err(format!("unknown command '{}'", line.trim())),
+        };
+        Ok(Reply::Lines(lines))
+    }
+
+    // The start and end to show, where a missing start continues from the
+    // last command, and a missing end shows the default amount of bytes
+    fn range(&mut self, c64: &C64, args: &[u16], bytes: u16) -> Result<(u16, u16), String> {
+        let start = match args.first() {
+            Some(start) => *start,
+            None => self.next_address.unwrap_or(c64.cpu.PC),
+        };
+        let end = match args.get(1) {
+            Some(end) if *end < start => return Err(format!("{end:04x} is before {start:04x}")),
+            Some(end) => *end,
+            None => start.saturating_add(bytes - 1),
+        };
+        if args.len() > 2 {
+            return Err("expected a start and an end at most".to_string());
+        }
+
+        self.next_address = Some(end.wrapping_add(1));
+        Ok((start, end))
+    }
+}
+
+// The memory from start to end, inclusive, as the CPU sees it
+fn block(c64: &C64, start: u16, end: u16) -> Block {
+    Block {
+        start,
+        instructions: (start..=end).map(|address| c64.peek(address)).collect(),
+    }
+}
+
+fn registers(c64: &C64) -> String {
+    let cpu = &c64.cpu;
+    let flags: String = [
+        (StatusFlags::N, 'N'),
+        (StatusFlags::V, 'V'),
+        (StatusFlags::U, '-'),
+        (StatusFlags::B, 'B'),
+        (StatusFlags::D, 'D'),
+        (StatusFlags::I, 'I'),
+        (StatusFlags::Z, 'Z'),
+        (StatusFlags::C, 'C'),
+    ]
+    .iter()
+    .map(|(flag, name)| match cpu.get_flag(*flag) {
+        true => *name,
+        false => '.',
+    })
+    .collect();
+
+    let vic = c64.vic();
+    format!(
+        "PC={:04X} A={:02X} X={:02X} Y={:02X} SP={:02X} {flags} LINE={:03} CYCLE={:02} CYCLES={}",
+        cpu.PC,
+        cpu.A,
+        cpu.X,
+        cpu.Y,
+        cpu.SP,
+        vic.raster_line(),
+        vic.cycle(),
+        cpu.cycles()
+    )
+}
+
+fn parse_hex(word: &str) -> Result<u16, String> {
+    u16::from_str_radix(word.trim_start_matches('$'), 16)
+        .map_err(|_| format!("'{word}' isn't a hex number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(reply: Reply) -> Vec<String> {
+        match reply {
+            Reply::Lines(lines) => lines,
+            Reply::Resume => panic!("expected lines"),
+        }
+    }
+
+    #[test]
+    fn should_show_and_change_memory() {
+        let mut c64 = C64::new();
+        let mut monitor = Monitor::new();
+
+        assert!(lines(monitor.execute(&mut c64, "> $c000 a9 01 8d 20 d0")).is_empty());
+        assert_eq!(
+            lines(monitor.execute(&mut c64, "m c000 c007")),
+            ["C000   A9 01 8D 20   D0 00 00 00   ... ...."]
+        );
+        assert_eq!(
+            lines(monitor.execute(&mut c64, "d c000 c004")),
+            ["C000   A9 01      LDA #$01", "C002   8D 20 D0   STA $D020"]
+        );
+
+        // Carrying on from the last one
+        let next = lines(monitor.execute(&mut c64, "m"));
+        assert!(next[0].starts_with("C005"));
+        assert_eq!(next.len(), 8);
+    }
+
+    #[test]
+    fn should_step_and_continue() {
+        let mut c64 = C64::new();
+        let mut monitor = Monitor::new();
+        monitor.execute(&mut c64, "> c000 a9 01 a2 02");
+        c64.cpu.jump(0xc000);
+
+        let registers = lines(monitor.execute(&mut c64, "z 2"));
+        assert!(
+            registers[0].starts_with("PC=C004 A=01 X=02"),
+            "{registers:?}"
+        );
+
+        assert_eq!(monitor.execute(&mut c64, "g 1000"), Reply::Resume);
+        assert_eq!(c64.cpu.PC, 0x1000);
+        assert_eq!(monitor.execute(&mut c64, "x"), Reply::Resume);
+    }
+
+    #[test]
+    fn should_report_errors() {
+        let mut c64 = C64::new();
+        let mut monitor = Monitor::new();
+
+        assert_eq!(
+            lines(monitor.execute(&mut c64, "q")),
+            ["error: unknown command 'q'"]
+        );
+        assert_eq!(
+            lines(monitor.execute(&mut c64, "m zz")),
+            ["error: 'zz' isn't a hex number"]
+        );
+        assert_eq!(
+            lines(monitor.execute(&mut c64, "> c000 100")),
+            ["error: 100 isn't a byte"]
+        );
+    }
+}
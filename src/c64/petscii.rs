@@ -0,0 +1,157 @@
+//! The C64 uses two different encodings for text. PETSCII is what the KERNAL
+//! prints and what ends up in strings, while screen codes are what is stored in
+//! the video matrix, i.e. the index of the glyph in the character ROM.
+//!
+//! The character ROM holds two sets of 128 glyphs, upper case and graphics
+//! (the one used after power on) and lower and upper case. The other 128 screen
+//! codes are the same glyphs, reversed.
+//!
+//! Most of the graphics characters have a Unicode equivalent, either among the
+//! box drawing characters or in the "Symbols for Legacy Computing" block, which
+//! was added in Unicode 13 for exactly this purpose. The reversed glyphs don't
+//! have any, so they are decoded as their normal counterparts.
+
+/// The two character sets of the character ROM
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Charset {
+    /// Upper case and graphics, the one used after power on
+    #[default]
+    Uppercase,
+    /// Lower and upper case
+    Lowercase,
+}
+
+/// How to decode the bytes in a memory dump
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Decoding {
+    /// Plain ASCII, everything else is shown as a '.'
+    #[default]
+    Ascii,
+    Petscii(Charset),
+    ScreenCodes(Charset),
+}
+
+// Screen codes $40-$7f in the upper case and graphics character set
+#[rustfmt::skip]
+const GRAPHICS: [char; 64] = [
+    '─', '♠', '🭲', '🭸', '🭷', '🭶', '🭺', '🭱', '🭴', '╮', '╰', '╯', '🭼', '╲', '╱', '🭽',
+    '🭾', '●', '🭻', '♥', '🭰', '╭', '╳', '○', '♣', '🭵', '♦', '┼', '🮌', '│', 'π', '◥',
+    '\u{a0}', '▌', '▄', '▔', '▁', '▏', '▒', '▕', '🮏', '◤', '🮇', '├', '▗', '└', '┐', '▂',
+    '┌', '┴', '┬', '┤', '▎', '▍', '🮈', '🮂', '🮃', '▃', '🭿', '▖', '▝', '┘', '▘', '▚',
+];
+
+// The glyphs that differ in the lower and upper case character set, apart
+// from the letters
+const LOWERCASE_GRAPHICS: [(u8, char); 4] = [(0x5e, '🮕'), (0x5f, '🮘'), (0x69, '🮙'), (0x7a, '✓')];
+
+impl Decoding {
+    /// Decodes a byte into something that can be shown in a single column
+    pub fn decode(&self, byte: u8) -> char {
+        let decoded = match self {
+            Decoding::Ascii => Some(byte as char).filter(|c| c.is_ascii() && !c.is_control()),
+            Decoding::Petscii(charset) => petscii_to_char(byte, *charset),
+            Decoding::ScreenCodes(charset) => Some(screen_code_to_char(byte, *charset)),
+        };
+        decoded.unwrap_or('.')
+    }
+}
+
+/// The screen code of the glyph printed for a PETSCII character, if any
+pub fn petscii_to_screen_code(byte: u8) -> Option<u8> {
+    match byte {
+        0x20..=0x3f => Some(byte),
+        0x40..=0x5f => Some(byte - 0x40),
+        0x60..=0x7f => Some(byte - 0x20),
+        0xa0..=0xbf => Some(byte - 0x40),
+        0xc0..=0xfe => Some(byte - 0x80),
+        0xff => Some(0x5e),
+        // Control codes
+        _ => None,
+    }
+}
+
+/// The Unicode character for a PETSCII character, if it can be printed
+pub fn petscii_to_char(byte: u8, charset: Charset) -> Option<char> {
+    petscii_to_screen_code(byte).map(|code| screen_code_to_char(code, charset))
+}
+
+/// The Unicode character for a screen code, where reversed glyphs are decoded
+/// as their normal counterparts.
+pub fn screen_code_to_char(code: u8, charset: Charset) -> char {
+    let code = code & 0x7f;
+
+    if charset == Charset::Lowercase {
+        match code {
+            0x01..=0x1a => return (b'a' + code - 0x01) as char,
+            0x41..=0x5a => return (b'A' + code - 0x41) as char,
+            _ => {}
+        }
+        if let Some((_, c)) = LOWERCASE_GRAPHICS.iter().find(|(glyph, _)| *glyph == code) {
+            return *c;
+        }
+    }
+
+    match code {
+        0x00 => '@',
+        0x01..=0x1a => (b'A' + code - 0x01) as char,
+        0x1b => '[',
+        0x1c => '£',
+        0x1d => ']',
+        0x1e => '↑',
+        0x1f => '←',
+        0x20..=0x3f => code as char,
+        _ => GRAPHICS[(code - 0x40) as usize],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_decode_petscii() {
+        let upper: String = b"HELLO \x5c\xd3\xff"
+            .iter()
+            .map(|byte| Decoding::Petscii(Charset::Uppercase).decode(*byte))
+            .collect();
+        assert_eq!(upper, "HELLO £♥π");
+
+        let lower: String = b"HELLO \xc8\xc9"
+            .iter()
+            .map(|byte| Decoding::Petscii(Charset::Lowercase).decode(*byte))
+            .collect();
+        assert_eq!(lower, "hello HI");
+
+        // Control codes, like {clr} and {red}
+        assert_eq!(petscii_to_char(0x93, Charset::Uppercase), None);
+        assert_eq!(Decoding::Petscii(Charset::Uppercase).decode(0x1c), '.');
+    }
+
+    #[test]
+    fn should_decode_screen_codes() {
+        let decoding = Decoding::ScreenCodes(Charset::Uppercase);
+
+        assert_eq!(decoding.decode(0x00), '@');
+        assert_eq!(decoding.decode(0x08), 'H');
+        assert_eq!(decoding.decode(0x20), ' ');
+        assert_eq!(decoding.decode(0x51), '●');
+        assert_eq!(decoding.decode(0x66), '▒');
+
+        // Reversed
+        assert_eq!(decoding.decode(0x88), 'H');
+        assert_eq!(decoding.decode(0xa0), ' ');
+
+        let decoding = Decoding::ScreenCodes(Charset::Lowercase);
+        assert_eq!(decoding.decode(0x08), 'h');
+        assert_eq!(decoding.decode(0x48), 'H');
+        assert_eq!(decoding.decode(0x7a), '✓');
+    }
+
+    #[test]
+    fn should_only_decode_printable_ascii() {
+        assert_eq!(Decoding::Ascii.decode(b'A'), 'A');
+        assert_eq!(Decoding::Ascii.decode(0x0a), '.');
+        assert_eq!(Decoding::Ascii.decode(0x7f), '.');
+        assert_eq!(Decoding::Ascii.decode(0xc1), '.');
+    }
+}
@@ -0,0 +1,237 @@
+//! A .prg file is what the KERNAL SAVE routine writes to disk or tape, the load
+//! address (least significant byte first) followed by the data to put there.
+//!
+//! When BASIC loads a program, the KERNAL LOAD routine leaves the end address in
+//! $ae/$af and BASIC then points the start of variables ($2d/$2e), the start of
+//! arrays ($2f/$30) and the end of arrays ($31/$32) at the end of the program.
+//! Without this, RUN would start overwriting the program with its variables.
+
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use super::{Block, C64};
+
+// The VIC-II, SID, color RAM, CIAs and the I/O expansion areas
+const IO_AREA: RangeInclusive<u32> = 0xd000..=0xdfff;
+
+// The end address left behind by the KERNAL LOAD routine
+const LOAD_END: u16 = 0x00ae;
+
+// VARTAB, ARYTAB and STREND, the pointers BASIC updates after a LOAD
+const BASIC_POINTERS: [u16; 3] = [0x002d, 0x002f, 0x0031];
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    /// The file doesn't even contain a load address
+    TooShort,
+    /// The data continues past $ffff
+    WrapsAround {
+        start: u16,
+        length: usize,
+    },
+    /// The data would be written into the I/O area at $d000-$dfff
+    OverwritesIo {
+        start: u16,
+        end: u16,
+    },
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Io(err) => write!(f, "unable to read file: {err}"),
+            LoadError::TooShort => write!(f, "file is too short to contain a load address"),
+            LoadError::WrapsAround { start, length } => {
+                write!(
+                    f,
+                    "{length} bytes loaded at ${start:04X} would wrap past $FFFF"
+                )
+            }
+            LoadError::OverwritesIo { start, end } => {
+                write!(
+                    f,
+                    "data at ${start:04X}-${end:04X} would overwrite the I/O area at $D000-$DFFF"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl Block {
+    pub fn from_prg(bytes: &[u8]) -> Result<Self, LoadError> {
+        let [lo, hi, data @ ..] = bytes else {
+            return Err(LoadError::TooShort);
+        };
+
+        let start = u16::from_le_bytes([*lo, *hi]);
+        let end = start as u32 + data.len() as u32;
+
+        if end > 0x10000 {
+            return Err(LoadError::WrapsAround {
+                start,
+                length: data.len(),
+            });
+        }
+
+        Ok(Block {
+            start,
+            instructions: data.to_vec(),
+        })
+    }
+
+    pub fn load_prg(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        Self::from_prg(&std::fs::read(path)?)
+    }
+
+    pub fn to_prg(&self) -> Vec<u8> {
+        let mut bytes = self.start.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&self.instructions);
+        bytes
+    }
+
+    // Fails when loading the block would write into the I/O area, which only
+    // matters for the machine, not for reading the file
+    pub(super) fn check_outside_io(&self) -> Result<(), LoadError> {
+        // The last address written to, as long as there is any data at all
+        let start = self.start as u32;
+        let last = start + (self.instructions.len() as u32).saturating_sub(1);
+        if !self.instructions.is_empty() && start <= *IO_AREA.end() && last >= *IO_AREA.start() {
+            return Err(LoadError::OverwritesIo {
+                start: self.start,
+                end: last as u16,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl C64 {
+    /// Writes the block straight into memory, at its start address
+    pub fn load(&mut self, block: &Block) {
+        for (offset, byte) in block.instructions.iter().enumerate() {
+            self.poke(block.start.wrapping_add(offset as u16), *byte);
+        }
+    }
+
+    /// Loads a .prg file into memory the way `LOAD "",8,1` does, optionally
+    /// updating the BASIC pointers afterwards so the program can be `RUN`.
+    pub fn load_prg(&mut self, bytes: &[u8], fix_basic_pointers: bool) -> Result<Block, LoadError> {
+        let block = Block::from_prg(bytes)?;
+        block.check_outside_io()?;
+        self.load(&block);
+
+        let end = block.start.wrapping_add(block.instructions.len() as u16);
+        self.poke_word(LOAD_END, end);
+
+        if fix_basic_pointers {
+            for pointer in BASIC_POINTERS {
+                self.poke_word(pointer, end);
+            }
+        }
+        Ok(block)
+    }
+
+    fn poke_word(&mut self, address: u16, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.poke(address, lo);
+        self.poke(address + 1, hi);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_load_address() {
+        let block = Block::from_prg(&[0x01, 0x08, 0xaa, 0xbb]).unwrap();
+
+        assert_eq!(block.start, 0x0801);
+        assert_eq!(block.instructions, vec![0xaa, 0xbb]);
+        assert_eq!(block.to_prg(), vec![0x01, 0x08, 0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn should_fail_without_load_address() {
+        assert!(matches!(Block::from_prg(&[0x01]), Err(LoadError::TooShort)));
+    }
+
+    #[test]
+    fn should_fail_when_wrapping() {
+        let result = Block::from_prg(&[0xfe, 0xff, 0x01, 0x02, 0x03]);
+        assert!(matches!(
+            result,
+            Err(LoadError::WrapsAround {
+                start: 0xfffe,
+                length: 3
+            })
+        ));
+
+        // Ending exactly at $ffff is fine
+        assert!(Block::from_prg(&[0xfe, 0xff, 0x01, 0x02]).is_ok());
+    }
+
+    #[test]
+    fn should_fail_when_overwriting_io() {
+        let mut c64 = C64::new();
+        let result = c64.load_prg(&[0xff, 0xcf, 0x01, 0x02], false);
+        assert!(matches!(
+            result,
+            Err(LoadError::OverwritesIo {
+                start: 0xcfff,
+                end: 0xd000
+            })
+        ));
+        assert_eq!(c64.peek(0xcfff), 0x00);
+
+        assert!(c64.load_prg(&[0x00, 0xce, 0x01, 0x02], false).is_ok());
+        assert!(c64.load_prg(&[0x00, 0xe0, 0x01, 0x02], false).is_ok());
+    }
+
+    #[test]
+    fn should_read_prg_across_io() {
+        let mut prg = vec![0xf0, 0xcf];
+        prg.extend([0xea; 0x20]);
+        let block = Block::from_prg(&prg).unwrap();
+
+        let lines = block.disassemble();
+        assert_eq!(lines.len(), 0x20);
+        assert!(lines[0x10].starts_with("D000   EA"), "{lines:?}");
+    }
+
+    #[test]
+    fn should_load_into_memory_and_fix_basic_pointers() {
+        let mut c64 = C64::new();
+        let block = c64
+            .load_prg(&[0x01, 0x08, 0x0b, 0x08, 0x0a, 0x00], true)
+            .unwrap();
+
+        assert_eq!(block.start, 0x0801);
+        assert_eq!(c64.peek(0x0801), 0x0b);
+        assert_eq!(c64.peek(0x0804), 0x00);
+
+        for pointer in [LOAD_END, 0x2d, 0x2f, 0x31] {
+            assert_eq!(c64.peek(pointer), 0x05);
+            assert_eq!(c64.peek(pointer + 1), 0x08);
+        }
+    }
+
+    #[test]
+    fn should_leave_basic_pointers_alone() {
+        let mut c64 = C64::new();
+        c64.load_prg(&[0x00, 0xc0, 0xea], false).unwrap();
+
+        assert_eq!(c64.peek(0xc000), 0xea);
+        assert_eq!(c64.peek(0x2d), 0x00);
+    }
+}
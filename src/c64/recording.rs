@@ -0,0 +1,344 @@
+//! Recording dumps every frame the VIC-II draws, for a number of frames, along
+//! with the audio of the SID. It doesn't need a window, so it works just as
+//! well on a build server, and leaves the encoding to external tools:
+//!
+//! - png - a numbered PNG for every frame, next to each other
+//! - y4m - a YUV4MPEG2 stream, 4:4:4 in BT.601, which ffmpeg and most other
+//!   encoders read as it is
+//! - rgb - the raw RGB of every frame, one after the other, which needs the
+//!   size and frame rate to be given to the encoder
+//!
+//! The frames are captured like screenshots, so the region, pixel aspect and
+//! palette are the same as for those. The audio is written as 16 bit mono WAV
+//! next to the video, with the extension changed.
+//!
+//! The frame rate isn't a round number, as it is the clock of the model divided
+//! by the cycles in a frame, so it is kept as that fraction, e.g. 985248/19656
+//! or about 50.12 for PAL.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::screenshot::{Image, ImageError, Screenshot};
+use super::C64;
+
+/// The sample rate of the audio, unless set to something else
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// How the frames are written
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VideoFormat {
+    /// A numbered PNG for every frame
+    #[default]
+    Png,
+    /// A YUV4MPEG2 stream
+    Y4m,
+    /// Raw RGB, three bytes per pixel, frame after frame
+    Rgb,
+}
+
+/// What to record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Recording {
+    pub frames: u64,
+    pub video: VideoFormat,
+    pub screenshot: Screenshot,
+    /// The sample rate of the audio, or None to not record any
+    pub sample_rate: Option<u32>,
+}
+
+/// What ended up being recorded, for telling an encoder about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recorded {
+    pub width: usize,
+    pub height: usize,
+    pub frames: u64,
+    /// Frames per second, as a numerator and denominator
+    pub frame_rate: (u32, u32),
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(std::io::Error),
+    Image(ImageError),
+    Audio(hound::Error),
+}
+
+impl Display for RecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordingError::Io(err) => write!(f, "unable to write recording: {err}"),
+            RecordingError::Image(err) => write!(f, "{err}"),
+            RecordingError::Audio(err) => write!(f, "unable to write audio: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<std::io::Error> for RecordingError {
+    fn from(err: std::io::Error) -> Self {
+        RecordingError::Io(err)
+    }
+}
+
+impl From<ImageError> for RecordingError {
+    fn from(err: ImageError) -> Self {
+        RecordingError::Image(err)
+    }
+}
+
+impl From<hound::Error> for RecordingError {
+    fn from(err: hound::Error) -> Self {
+        RecordingError::Audio(err)
+    }
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Recording {
+            frames: 50,
+            video: VideoFormat::default(),
+            screenshot: Screenshot::default(),
+            sample_rate: Some(SAMPLE_RATE),
+        }
+    }
+}
+
+impl Recording {
+    /// Runs the machine for the frames, writing them to the path. For a PNG
+    /// sequence, the number of each frame is added to the name of the file.
+    pub fn record(
+        &self,
+        c64: &mut C64,
+        path: impl AsRef<Path>,
+    ) -> Result<Recorded, RecordingError> {
+        let path = path.as_ref();
+
+        let timing = c64.model().timing();
+        let frame_rate = (
+            c64.model().clock(),
+            timing.cycles_per_line as u32 * timing.lines_per_frame as u32,
+        );
+
+        let mut stream = match self.video {
+            VideoFormat::Png => None,
+            VideoFormat::Y4m | VideoFormat::Rgb => Some(BufWriter::new(File::create(path)?)),
+        };
+
+        let previous_sample_rate = c64.sid().sample_rate();
+        let mut audio = match self.sample_rate {
+            Some(sample_rate) => {
+                c64.set_sample_rate(Some(sample_rate));
+                let spec = hound::WavSpec {
+                    channels: 1,
+                    sample_rate,
+                    bits_per_sample: 16,
+                    sample_format: hound::SampleFormat::Int,
+                };
+                Some(hound::WavWriter::create(path.with_extension("wav"), spec)?)
+            }
+            None => None,
+        };
+
+        let mut files = vec![];
+        let (mut width, mut height) = (0, 0);
+
+        for frame in 0..self.frames {
+            c64.run_frame();
+            let image = c64.screenshot(self.screenshot);
+            (width, height) = (image.width, image.height);
+
+            match (&mut stream, self.video) {
+                (Some(stream), VideoFormat::Y4m) => {
+                    if frame == 0 {
+                        let (numerator, denominator) = frame_rate;
+                        writeln!(
+                            stream,
+                            "YUV4MPEG2 W{width} H{height} F{numerator}:{denominator} Ip A1:1 C444"
+                        )?;
+                    }
+                    stream.write_all(b"FRAME\n")?;
+                    stream.write_all(&to_yuv444(&image))?;
+                }
+                (Some(stream), _) => stream.write_all(&image.rgb)?,
+                (None, _) => {
+                    let file = numbered(path, frame);
+                    image.save_png(&file)?;
+                    files.push(file);
+                }
+            }
+
+            if let Some(audio) = &mut audio {
+                for sample in c64.take_samples() {
+                    audio.write_sample(sample)?;
+                }
+            }
+        }
+
+        if let Some(mut stream) = stream {
+            stream.flush()?;
+            files.push(path.to_path_buf());
+        }
+        if let Some(audio) = audio {
+            audio.finalize()?;
+            files.push(path.with_extension("wav"));
+            c64.set_sample_rate(previous_sample_rate);
+        }
+
+        Ok(Recorded {
+            width,
+            height,
+            frames: self.frames,
+            frame_rate,
+            files,
+        })
+    }
+}
+
+// The path with the number of the frame added to the name, e.g. demo.png
+// becomes demo_00042.png
+fn numbered(path: &Path, frame: u64) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{stem}_{frame:05}.png"))
+}
+
+// The Y, U and V planes of the image, in the studio range of BT.601
+fn to_yuv444(image: &Image) -> Vec<u8> {
+    let pixels = image.width * image.height;
+    let mut planes = vec![0; pixels * 3];
+
+    for (index, rgb) in image.rgb.chunks_exact(3).enumerate() {
+        let [r, g, b] = [rgb[0] as f64, rgb[1] as f64, rgb[2] as f64];
+        let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+        let u = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+        let v = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+
+        planes[index] = y.round() as u8;
+        planes[pixels + index] = u.round() as u8;
+        planes[pixels * 2 + index] = v.round() as u8;
+    }
+    planes
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "png" => Ok(VideoFormat::Png),
+            "y4m" => Ok(VideoFormat::Y4m),
+            "rgb" | "raw" => Ok(VideoFormat::Rgb),
+            _ => Err(format!(
+                "unknown video format '{s}', expected png, y4m or rgb"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c64::screenshot::Region;
+    use crate::c64::test_directory;
+
+    // A loop that plays a note, with a blue screen
+    fn c64() -> C64 {
+        let mut c64 = C64::new();
+        let program = [
+            0xa9, 0x0f, 0x8d, 0x18, 0xd4, // lda #$0f, sta $d418
+            0xa9, 0x1d, 0x8d, 0x01, 0xd4, // lda #$1d, sta $d401
+            0xa9, 0xf0, 0x8d, 0x06, 0xd4, // lda #$f0, sta $d406
+            0xa9, 0x21, 0x8d, 0x04, 0xd4, // lda #$21, sta $d404
+            0x4c, 0x14, 0x10, //             jmp *
+        ];
+        for (offset, value) in program.iter().enumerate() {
+            c64.poke(0x1000 + offset as u16, *value);
+        }
+        c64.cpu.jump(0x1000);
+        c64.poke(0xd020, 0x06);
+        c64
+    }
+
+    #[test]
+    fn should_record_png_sequence_with_audio() {
+        let directory = test_directory("recording-png");
+        let recorded = Recording {
+            frames: 3,
+            ..Recording::default()
+        }
+        .record(&mut c64(), directory.join("demo.png"))
+        .unwrap();
+
+        assert_eq!((recorded.width, recorded.height), (403, 284));
+        assert_eq!(recorded.frame_rate, (985248, 19656));
+        assert!(directory.join("demo_00000.png").exists());
+        assert!(directory.join("demo_00002.png").exists());
+        assert!(!directory.join("demo_00003.png").exists());
+
+        // 3 frames of audio, with the note playing
+        let mut wav = hound::WavReader::open(directory.join("demo.wav")).unwrap();
+        assert_eq!(wav.spec().sample_rate, SAMPLE_RATE);
+        let samples: Vec<i16> = wav.samples().map(Result::unwrap).collect();
+        let expected = (3.0 * SAMPLE_RATE as f64 / 50.1247) as usize;
+        assert!(samples.len().abs_diff(expected) <= 1);
+        assert!(samples.iter().any(|sample| *sample != 0));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn should_record_y4m_and_raw_rgb() {
+        let directory = test_directory("recording-stream");
+        let screenshot = Screenshot {
+            region: Region::Inner,
+            ..Screenshot::default()
+        };
+
+        Recording {
+            frames: 2,
+            video: VideoFormat::Y4m,
+            screenshot,
+            sample_rate: None,
+        }
+        .record(&mut c64(), directory.join("demo.y4m"))
+        .unwrap();
+
+        let y4m = std::fs::read(directory.join("demo.y4m")).unwrap();
+        let header = b"YUV4MPEG2 W320 H200 F985248:19656 Ip A1:1 C444\n";
+        assert!(y4m.starts_with(header));
+        assert_eq!(y4m.len(), header.len() + 2 * (6 + 320 * 200 * 3));
+        assert!(!directory.join("demo.wav").exists());
+
+        Recording {
+            frames: 2,
+            video: VideoFormat::Rgb,
+            screenshot,
+            sample_rate: None,
+        }
+        .record(&mut c64(), directory.join("demo.rgb"))
+        .unwrap();
+
+        let rgb = std::fs::read(directory.join("demo.rgb")).unwrap();
+        assert_eq!(rgb.len(), 2 * 320 * 200 * 3);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn should_convert_to_studio_range() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            rgb: vec![0x00, 0x00, 0x00, 0xff, 0xff, 0xff],
+        };
+        assert_eq!(to_yuv444(&image), [16, 235, 128, 128, 128, 128]);
+    }
+}
@@ -0,0 +1,111 @@
+//! The ROMs are copyrighted and can't be part of this repo, so they are loaded
+//! from a directory at runtime instead, e.g. the data/C64 directory of VICE.
+//!
+//! Any ROM that is missing leaves the RAM below it visible instead, which is
+//! enough for running code that doesn't depend on the KERNAL or BASIC.
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default)]
+pub struct Roms {
+    /// BASIC V2 at $a000-$bfff
+    pub basic: Option<Vec<u8>>,
+    /// The KERNAL at $e000-$ffff
+    pub kernal: Option<Vec<u8>>,
+    /// The character generator at $d000-$dfff
+    pub chargen: Option<Vec<u8>>,
+}
+
+// The names used by VICE, both the current and the older ones
+const BASIC_NAMES: [&str; 2] = ["basic-901226-01.bin", "basic"];
+const KERNAL_NAMES: [&str; 2] = ["kernal-901227-03.bin", "kernal"];
+const CHARGEN_NAMES: [&str; 2] = ["chargen-901225-01.bin", "chargen"];
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(PathBuf, std::io::Error),
+    WrongSize {
+        path: PathBuf,
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl Display for RomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RomError::Io(path, err) => write!(f, "unable to read {}: {err}", path.display()),
+            RomError::WrongSize {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} should be {expected} bytes, but is {found} bytes",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl Roms {
+    /// Loads whichever of the ROMs that can be found in the directory
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self, RomError> {
+        let dir = dir.as_ref();
+
+        Ok(Roms {
+            basic: load_rom(dir, &BASIC_NAMES, 0x2000)?,
+            kernal: load_rom(dir, &KERNAL_NAMES, 0x2000)?,
+            chargen: load_rom(dir, &CHARGEN_NAMES, 0x1000)?,
+        })
+    }
+}
+
+fn load_rom(dir: &Path, names: &[&str], size: usize) -> Result<Option<Vec<u8>>, RomError> {
+    let Some(path) = names
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+    else {
+        return Ok(None);
+    };
+
+    let rom = std::fs::read(&path).map_err(|err| RomError::Io(path.clone(), err))?;
+    if rom.len() != size {
+        return Err(RomError::WrongSize {
+            path,
+            expected: size,
+            found: rom.len(),
+        });
+    }
+    Ok(Some(rom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c64::test_directory;
+
+    #[test]
+    fn should_load_roms_from_dir() {
+        let dir = test_directory("roms");
+        std::fs::write(dir.join("kernal"), vec![0xea; 0x2000]).unwrap();
+        std::fs::write(dir.join("chargen"), vec![0xcc; 0x800]).unwrap();
+
+        let result = Roms::from_dir(&dir);
+        assert!(matches!(
+            result,
+            Err(RomError::WrongSize { found: 0x800, .. })
+        ));
+
+        std::fs::remove_file(dir.join("chargen")).unwrap();
+        let roms = Roms::from_dir(&dir).unwrap();
+        assert!(roms.basic.is_none());
+        assert_eq!(roms.kernal.unwrap()[0], 0xea);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,413 @@
+//! Screenshots are taken from the RGB framebuffer of the VIC-II, so the colours
+//! come from whichever palette it's set to use. There are three parts of the
+//! frame to choose from:
+//!
+//! - Full    - every pixel drawn, including the parts of the border that are
+//!   outside of the picture on a TV
+//! - Visible - what a TV shows, which depends on the model
+//! - Inner   - the 320x200 pixels of the 25 rows / 40 columns display window
+//!
+//! The pixels of a PAL C64 aren't square, they are a bit narrower than they
+//! are high, so the screenshot can be scaled horizontally to look the way it
+//! does on a TV, instead of showing every pixel 1:1. For the rest of what a TV
+//! does to the picture, the PAL filter can be run over it before the scaling.
+
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::vicII::pal_filter::PalFilter;
+use super::vicII::{Area, VicII};
+use super::C64;
+
+/// The width of a PAL pixel, relative to its height
+pub const PAL_PIXEL_ASPECT: f64 = 0.9365;
+
+/// The part of the frame to capture
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Region {
+    Full,
+    #[default]
+    Visible,
+    Inner,
+}
+
+/// The shape of the pixels in the screenshot
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Aspect {
+    /// Every pixel of the frame as one pixel of the image
+    #[default]
+    Square,
+    /// Scaled horizontally to the pixel aspect ratio of PAL
+    Pal,
+}
+
+/// How to take a screenshot
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Screenshot {
+    pub region: Region,
+    pub aspect: Aspect,
+    /// The filter to show the picture as a PAL TV would, if any
+    pub pal_filter: Option<PalFilter>,
+}
+
+/// An RGB image, three bytes per pixel
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(std::io::Error),
+    Encoding(png::EncodingError),
+    Decoding(png::DecodingError),
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageError::Io(err) => write!(f, "unable to access image: {err}"),
+            ImageError::Encoding(err) => write!(f, "unable to encode PNG: {err}"),
+            ImageError::Decoding(err) => write!(f, "unable to decode PNG: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<std::io::Error> for ImageError {
+    fn from(err: std::io::Error) -> Self {
+        ImageError::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for ImageError {
+    fn from(err: png::EncodingError) -> Self {
+        ImageError::Encoding(err)
+    }
+}
+
+impl From<png::DecodingError> for ImageError {
+    fn from(err: png::DecodingError) -> Self {
+        ImageError::Decoding(err)
+    }
+}
+
+impl Screenshot {
+    /// Captures the current frame of the VIC-II
+    pub fn take(&self, vic: &VicII) -> Image {
+        let frame = Image {
+            width: vic.width(),
+            height: vic.height(),
+            rgb: vic.framebuffer(),
+        };
+
+        let image = match self.region {
+            Region::Full => frame,
+            Region::Visible => frame.crop(vic.timing().visible),
+            Region::Inner => frame.crop(vic.display_window()),
+        };
+
+        let image = match self.pal_filter {
+            Some(filter) => Image {
+                rgb: filter.apply(&image.rgb, image.width),
+                height: match filter.scanlines > 0.0 {
+                    true => image.height * 2,
+                    false => image.height,
+                },
+                ..image
+            },
+            None => image,
+        };
+
+        match self.aspect {
+            Aspect::Square => image,
+            Aspect::Pal => {
+                let width = (image.width as f64 * PAL_PIXEL_ASPECT).round() as usize;
+                image.scale_width(width)
+            }
+        }
+    }
+}
+
+impl Image {
+    /// The part of the image within the area, where lines past the bottom
+    /// wrap around to the top
+    pub fn crop(&self, area: Area) -> Image {
+        let rgb = (area.y..area.y + area.height)
+            .flat_map(|line| {
+                let start = ((line % self.height) * self.width + area.x) * 3;
+                &self.rgb[start..start + area.width * 3]
+            })
+            .copied()
+            .collect();
+
+        Image {
+            width: area.width,
+            height: area.height,
+            rgb,
+        }
+    }
+
+    /// Resamples every line to the width, blending neighbouring pixels
+    pub fn scale_width(&self, width: usize) -> Image {
+        let scale = self.width as f64 / width as f64;
+
+        let rgb = self
+            .rgb
+            .chunks_exact(self.width * 3)
+            .flat_map(|line| {
+                (0..width).flat_map(move |x| {
+                    // The position of the centre of the pixel in the source
+                    let position = ((x as f64 + 0.5) * scale - 0.5).max(0.0);
+                    let left = (position as usize).min(self.width - 1);
+                    let right = (left + 1).min(self.width - 1);
+                    let weight = position - left as f64;
+
+                    (0..3).map(move |channel| {
+                        let a = line[left * 3 + channel] as f64;
+                        let b = line[right * 3 + channel] as f64;
+                        (a + (b - a) * weight).round() as u8
+                    })
+                })
+            })
+            .collect();
+
+        Image {
+            width,
+            height: self.height,
+            rgb,
+        }
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, ImageError> {
+        let mut bytes = vec![];
+
+        let mut encoder = png::Encoder::new(&mut bytes, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.rgb)?;
+        writer.finish()?;
+
+        Ok(bytes)
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        std::fs::write(path, self.to_png()?)?;
+        Ok(())
+    }
+
+    /// Reads a PNG of any colour type, e.g. a screenshot from VICE, which
+    /// is often indexed, converting it to RGB
+    pub fn from_png(bytes: &[u8]) -> Result<Image, ImageError> {
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info()?;
+        let mut buffer = vec![0; reader.output_buffer_size().unwrap_or_default()];
+        let info = reader.next_frame(&mut buffer)?;
+        buffer.truncate(info.buffer_size());
+
+        let rgb = match info.color_type {
+            png::ColorType::Rgb => buffer,
+            png::ColorType::Rgba => buffer
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                .collect(),
+            png::ColorType::Grayscale => buffer.iter().flat_map(|grey| [*grey; 3]).collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .flat_map(|pixel| [pixel[0]; 3])
+                .collect(),
+            png::ColorType::Indexed => unreachable!("expanded to RGB by the decoder"),
+        };
+
+        Ok(Image {
+            width: info.width as usize,
+            height: info.height as usize,
+            rgb,
+        })
+    }
+
+    pub fn load_png(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+        Self::from_png(&std::fs::read(path)?)
+    }
+}
+
+impl C64 {
+    /// Captures the current frame, see `Screenshot`
+    pub fn screenshot(&self, screenshot: Screenshot) -> Image {
+        screenshot.take(self.vic())
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Region::Full),
+            "visible" => Ok(Region::Visible),
+            "inner" => Ok(Region::Inner),
+            _ => Err(format!(
+                "unknown region '{s}', expected full, visible or inner"
+            )),
+        }
+    }
+}
+
+impl FromStr for Aspect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1:1" | "square" => Ok(Aspect::Square),
+            "pal" => Ok(Aspect::Pal),
+            _ => Err(format!("unknown aspect '{s}', expected 1:1 or pal")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c64::vicII::palette::{Colors, Palette, COLODORE, PEPTO};
+
+    // A frame with a light blue border around a blue display window
+    fn c64() -> C64 {
+        let mut c64 = C64::new();
+        c64.poke(0x1000, 0x4c);
+        c64.poke(0x1001, 0x00);
+        c64.poke(0x1002, 0x10);
+        c64.cpu.jump(0x1000);
+
+        c64.poke(0xd011, 0x1b);
+        c64.poke(0xd016, 0x08);
+        c64.poke(0xd020, 0x0e);
+        c64.poke(0xd021, 0x06);
+        c64.run_frame();
+        c64.run_frame();
+        c64
+    }
+
+    fn pixel(image: &Image, x: usize, y: usize) -> [u8; 3] {
+        let offset = (y * image.width + x) * 3;
+        [
+            image.rgb[offset],
+            image.rgb[offset + 1],
+            image.rgb[offset + 2],
+        ]
+    }
+
+    fn rgb(palette: &Palette, color: Colors) -> [u8; 3] {
+        let color = palette[color];
+        [color.r, color.g, color.b]
+    }
+
+    #[test]
+    fn should_capture_regions() {
+        let c64 = c64();
+
+        let full = c64.screenshot(Screenshot {
+            region: Region::Full,
+            ..Screenshot::default()
+        });
+        assert_eq!((full.width, full.height), (504, 312));
+
+        let visible = c64.screenshot(Screenshot::default());
+        assert_eq!((visible.width, visible.height), (403, 284));
+        assert_eq!(pixel(&visible, 0, 0), rgb(&PEPTO, Colors::light_blue));
+
+        let inner = c64.screenshot(Screenshot {
+            region: Region::Inner,
+            ..Screenshot::default()
+        });
+        assert_eq!((inner.width, inner.height), (320, 200));
+        assert_eq!(pixel(&inner, 0, 0), rgb(&PEPTO, Colors::blue));
+        assert_eq!(pixel(&inner, 319, 199), rgb(&PEPTO, Colors::blue));
+    }
+
+    #[test]
+    fn should_use_selected_palette() {
+        let mut c64 = c64();
+        c64.set_palette(COLODORE.clone());
+
+        let inner = c64.screenshot(Screenshot {
+            region: Region::Inner,
+            ..Screenshot::default()
+        });
+        assert_eq!(pixel(&inner, 0, 0), rgb(&COLODORE, Colors::blue));
+    }
+
+    #[test]
+    fn should_scale_to_pal_aspect() {
+        let image = c64().screenshot(Screenshot {
+            region: Region::Inner,
+            aspect: Aspect::Pal,
+            ..Screenshot::default()
+        });
+        assert_eq!((image.width, image.height), (300, 200));
+        assert_eq!(pixel(&image, 150, 100), rgb(&PEPTO, Colors::blue));
+    }
+
+    #[test]
+    fn should_apply_pal_filter() {
+        let image = c64().screenshot(Screenshot {
+            region: Region::Inner,
+            pal_filter: Some(PalFilter {
+                scanlines: 0.5,
+                ..PalFilter::default()
+            }),
+            ..Screenshot::default()
+        });
+        assert_eq!((image.width, image.height), (320, 400));
+        assert_eq!(image.rgb.len(), 320 * 400 * 3);
+        assert!(pixel(&image, 0, 1)[2] < pixel(&image, 0, 0)[2]);
+    }
+
+    #[test]
+    fn should_decode_png() {
+        let image = c64().screenshot(Screenshot::default());
+        assert_eq!(Image::from_png(&image.to_png().unwrap()).unwrap(), image);
+
+        // A 1x1 indexed PNG with a single red colour in its palette
+        let mut bytes = vec![];
+        let mut encoder = png::Encoder::new(&mut bytes, 1, 1);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_palette(vec![0xff, 0x00, 0x00]);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[0])
+            .unwrap();
+
+        let image = Image::from_png(&bytes).unwrap();
+        assert_eq!(image.rgb, [0xff, 0x00, 0x00]);
+        assert!(matches!(
+            Image::from_png(b"GIF89a"),
+            Err(ImageError::Decoding(_))
+        ));
+    }
+
+    #[test]
+    fn should_encode_png() {
+        let image = Image {
+            width: 2,
+            height: 1,
+            rgb: vec![0xff, 0x00, 0x00, 0x00, 0x00, 0xff],
+        };
+        let bytes = image.to_png().unwrap();
+        assert_eq!(bytes[..8], [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+
+        let decoder = png::Decoder::new(std::io::Cursor::new(&bytes));
+        let mut reader = decoder.read_info().unwrap();
+        let mut rgb = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut rgb).unwrap();
+        assert_eq!(rgb, image.rgb);
+    }
+}
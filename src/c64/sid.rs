@@ -0,0 +1,300 @@
+//! The SID is the sound chip of the C64, with 29 registers at $d400-$d41c that
+//! are repeated every 32 bytes all the way up to $d7ff.
+//!
+//! Most of the registers can only be written, and reading them returns what
+//! was last on the data bus, which is close enough to the last value written.
+//! The paddles at $d419/$d41a read as $ff when nothing is connected, while
+//! $d41b and $d41c return the top 8 bits of the waveform and the envelope
+//! level of the third voice, which is used for random numbers.
+//!
+//! The SID runs from the same clock as the CPU, so the pitch of every note
+//! depends on the model of the machine. The frequency registers of the voices
+//! count in steps of clock / 2^24 Hz, which is why music written for PAL plays
+//! a bit too high on NTSC, unless it has a table for each.
+//!
+//! The three voices are mixed and scaled by the master volume, but the filter
+//! isn't emulated, so the filter registers have no effect. To get sound out of
+//! it, a sample rate has to be set, after which the output of every cycle is
+//! averaged into samples at that rate, for a frontend to take.
+
+mod voice;
+
+use self::voice::Voice;
+
+// The number of registers, before they repeat
+const REGISTERS: usize = 0x20;
+
+const FILTER_MODE_VOLUME: usize = 0x18;
+const PADDLE_X: usize = 0x19;
+const PADDLE_Y: usize = 0x1a;
+const OSCILLATOR_3: usize = 0x1b;
+const ENVELOPE_3: usize = 0x1c;
+
+// Bit 7 of $d418 disconnects the third voice from the output
+const VOICE_3_OFF: u8 = 1 << 7;
+
+// Scales the sum of the three voices, each up to 2048 * 255 away from the
+// middle, to 16 bit samples with a bit of headroom
+const OUTPUT_SCALE: i64 = 80;
+
+#[derive(Clone, Debug)]
+pub struct Sid {
+    registers: [u8; REGISTERS],
+    voices: [Voice; 3],
+    clock: u32,
+
+    // The last value written to any register
+    bus: u8,
+
+    // How far into the current sample, in steps of the sample rate, and the
+    // sum of the output of the cycles in it so far
+    sample_rate: Option<u32>,
+    sample_phase: u32,
+    sample_sum: i64,
+    sample_cycles: i64,
+    samples: Vec<i16>,
+}
+
+impl Sid {
+    /// Creates a SID running at the clock, in Hz
+    pub fn new(clock: u32) -> Self {
+        Sid {
+            registers: [0; REGISTERS],
+            voices: [Voice::new(), Voice::new(), Voice::new()],
+            clock,
+            bus: 0,
+            sample_rate: None,
+            sample_phase: 0,
+            sample_sum: 0,
+            sample_cycles: 0,
+            samples: vec![],
+        }
+    }
+
+    pub fn clock(&self) -> u32 {
+        self.clock
+    }
+
+    /// The frequency of one of the three voices, in Hz
+    pub fn frequency(&self, voice: usize) -> f64 {
+        self.voices[voice].frequency as f64 * self.clock as f64 / (1 << 24) as f64
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address % REGISTERS {
+            PADDLE_X | PADDLE_Y => 0xff,
+            OSCILLATOR_3 => (self.voices[2].waveform(&self.voices[1]) >> 4) as u8,
+            ENVELOPE_3 => self.voices[2].level(),
+            _ => self.bus,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        let register = address % REGISTERS;
+        self.registers[register] = value;
+        self.bus = value;
+
+        if register < 3 * 7 {
+            let voice = &mut self.voices[register / 7];
+            match register % 7 {
+                0 => voice.frequency = (voice.frequency & 0xff00) | value as u16,
+                1 => voice.frequency = (voice.frequency & 0x00ff) | (value as u16) << 8,
+                2 => voice.pulse_width = (voice.pulse_width & 0x0f00) | value as u16,
+                3 => voice.pulse_width = (voice.pulse_width & 0x00ff) | (value as u16 & 0x0f) << 8,
+                4 => voice.set_control(value),
+                5 => voice.attack_decay = value,
+                _ => voice.sustain_release = value,
+            }
+        }
+    }
+
+    /// Starts producing samples at the rate, in Hz, or stops when None
+    pub fn set_sample_rate(&mut self, sample_rate: Option<u32>) {
+        self.sample_rate = sample_rate;
+        self.sample_phase = 0;
+        self.sample_sum = 0;
+        self.sample_cycles = 0;
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    /// The samples produced since the last time they were taken
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Advances the voices by one cycle
+    pub fn tick(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.clock();
+        }
+        let rising = self.voices.each_ref().map(|voice| voice.msb_rising());
+        for (index, voice) in self.voices.iter_mut().enumerate() {
+            voice.sync(rising[(index + 2) % 3]);
+        }
+
+        let Some(sample_rate) = self.sample_rate else {
+            return;
+        };
+
+        self.sample_sum += self.output();
+        self.sample_cycles += 1;
+
+        self.sample_phase += sample_rate;
+        if self.sample_phase >= self.clock {
+            self.sample_phase -= self.clock;
+
+            let sample = self.sample_sum / self.sample_cycles / OUTPUT_SCALE;
+            self.samples
+                .push(sample.clamp(i16::MIN as i64, i16::MAX as i64) as i16);
+            self.sample_sum = 0;
+            self.sample_cycles = 0;
+        }
+    }
+
+    // The mix of the voices, scaled by the volume
+    fn output(&self) -> i64 {
+        let mode_volume = self.registers[FILTER_MODE_VOLUME];
+
+        let mix: i64 = (0..3)
+            .filter(|index| *index != 2 || mode_volume & VOICE_3_OFF == 0)
+            .map(|index| {
+                let voice = &self.voices[index];
+                if !voice.audible() {
+                    return 0;
+                }
+                let waveform = voice.waveform(&self.voices[(index + 2) % 3]) as i64 - 0x800;
+                waveform * voice.level() as i64
+            })
+            .sum();
+
+        mix * (mode_volume & 0x0f) as i64 / 15
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c64::model::Model;
+
+    // A note of voice 1 at full volume, with the attack done at once
+    fn play(sid: &mut Sid, waveform: u8) {
+        sid.write(0xd400, 0x45);
+        sid.write(0xd401, 0x1d);
+        sid.write(0xd402, 0x00);
+        sid.write(0xd403, 0x08);
+        sid.write(0xd405, 0x00);
+        sid.write(0xd406, 0xf0);
+        sid.write(0xd418, 0x0f);
+        sid.write(0xd404, waveform | 0x01);
+    }
+
+    #[test]
+    fn should_tune_voices_by_clock() {
+        // A4 in a PAL frequency table
+        let mut sid = Sid::new(Model::Pal.clock());
+        sid.write(0xd400, 0x45);
+        sid.write(0xd401, 0x1d);
+        assert_eq!(sid.frequency(0).round(), 440.0);
+
+        let mut sid = Sid::new(Model::Ntsc.clock());
+        sid.write(0xd400, 0x45);
+        sid.write(0xd401, 0x1d);
+        assert_eq!(sid.frequency(0).round(), 457.0);
+
+        // Repeated every 32 bytes
+        sid.write(0xd7ee, 0x12);
+        assert_eq!(sid.registers[0x0e], 0x12);
+    }
+
+    #[test]
+    fn should_read_last_value_written() {
+        let mut sid = Sid::new(Model::Pal.clock());
+        sid.write(0xd418, 0x0f);
+        assert_eq!(sid.read(0xd400), 0x0f);
+        assert_eq!(sid.read(0xd419), 0xff);
+    }
+
+    #[test]
+    fn should_run_envelope_through_adsr() {
+        let mut sid = Sid::new(Model::Pal.clock());
+
+        // Attack 2ms, decay 6ms to a sustain level of 8, release 6ms
+        sid.write(0xd413, 0x00);
+        sid.write(0xd414, 0x80);
+        sid.write(0xd412, 0x11);
+        for _ in 0..255 * 9 {
+            sid.tick();
+        }
+        assert_eq!(sid.read(0xd41c), 0xff);
+
+        for _ in 0..20_000 {
+            sid.tick();
+        }
+        assert_eq!(sid.read(0xd41c), 0x88);
+
+        sid.write(0xd412, 0x10);
+        for _ in 0..100_000 {
+            sid.tick();
+        }
+        assert_eq!(sid.read(0xd41c), 0x00);
+    }
+
+    #[test]
+    fn should_read_oscillator_3() {
+        let mut sid = Sid::new(Model::Pal.clock());
+        sid.write(0xd40e, 0xff);
+        sid.write(0xd40f, 0xff);
+        sid.write(0xd412, 0x20);
+
+        // The sawtooth counts up 16 bits per cycle in the top byte
+        let values: Vec<u8> = (0..4)
+            .map(|_| {
+                (0..16).for_each(|_| sid.tick());
+                sid.read(0xd41b)
+            })
+            .collect();
+        assert!(values.windows(2).all(|pair| pair[1] > pair[0]));
+    }
+
+    #[test]
+    fn should_produce_samples_at_the_sample_rate() {
+        let mut sid = Sid::new(Model::Pal.clock());
+        for _ in 0..1000 {
+            sid.tick();
+        }
+        assert!(sid.take_samples().is_empty());
+
+        sid.set_sample_rate(Some(44100));
+        play(&mut sid, 0x40);
+        for _ in 0..Model::Pal.clock() / 10 {
+            sid.tick();
+        }
+
+        // 0.1s, give or take the part of a sample at the end
+        let samples = sid.take_samples();
+        assert!((4409..=4410).contains(&samples.len()));
+        assert!(sid.take_samples().is_empty());
+
+        // A square wave at 440Hz changes sign 88 times in 0.1s
+        let edges = samples
+            .windows(2)
+            .filter(|pair| (pair[0] < 0) != (pair[1] < 0))
+            .count();
+        assert!((86..=90).contains(&edges), "{edges} edges");
+    }
+
+    #[test]
+    fn should_be_silent_at_volume_0() {
+        let mut sid = Sid::new(Model::Pal.clock());
+        sid.set_sample_rate(Some(44100));
+        play(&mut sid, 0x20);
+        sid.write(0xd418, 0x00);
+        for _ in 0..10_000 {
+            sid.tick();
+        }
+        assert!(sid.take_samples().iter().all(|sample| *sample == 0));
+    }
+}
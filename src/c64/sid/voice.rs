@@ -0,0 +1,241 @@
+//! Each of the three voices of the SID has an oscillator and an envelope
+//! generator, and the output of the voice is the waveform of the oscillator
+//! multiplied by the level of the envelope.
+//!
+//! The oscillator is a 24 bit accumulator that the frequency is added to every
+//! cycle, and the waveforms are all made from its top 12 bits:
+//!
+//! - Triangle - the accumulator folded at its MSB, which ring modulation
+//!   replaces with the MSB of the voice before
+//! - Sawtooth - the top 12 bits as they are
+//! - Pulse    - high while the top 12 bits are at least the pulse width
+//! - Noise    - 8 bits of a 23 bit shift register, clocked by bit 19
+//!
+//! Selecting more than one waveform gives the AND of them, which is roughly
+//! what the chip does.
+//!
+//! The envelope counts the level up from 0 to 255 when the gate is set (attack),
+//! then down to the sustain level (decay), and down to 0 when the gate is
+//! cleared again (release). Each of the rates is a period in cycles between the
+//! steps, and decay and release slow down further towards the bottom, to sound
+//! exponential.
+
+// The cycles between the steps of the envelope, for each value of the
+// attack, decay and release nibbles
+#[rustfmt::skip]
+const RATE_PERIODS: [u16; 16] = [
+    9, 32, 63, 95, 149, 220, 267, 313,
+    392, 977, 1954, 3126, 3907, 11720, 19532, 31251,
+];
+
+// The value the noise shift register starts with
+const NOISE_SEED: u32 = 0x7ffff8;
+
+const GATE: u8 = 1 << 0;
+const SYNC: u8 = 1 << 1;
+const RING: u8 = 1 << 2;
+const TEST: u8 = 1 << 3;
+const TRIANGLE: u8 = 1 << 4;
+const SAWTOOTH: u8 = 1 << 5;
+const PULSE: u8 = 1 << 6;
+const NOISE: u8 = 1 << 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EnvelopeState {
+    Attack,
+    DecaySustain,
+    Release,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Voice {
+    pub frequency: u16,
+    pub pulse_width: u16,
+    pub control: u8,
+    pub attack_decay: u8,
+    pub sustain_release: u8,
+
+    accumulator: u32,
+    // Whether the MSB of the accumulator went from low to high in the last
+    // cycle, which resets the voice synced to this one
+    msb_rising: bool,
+    noise: u32,
+
+    state: EnvelopeState,
+    level: u8,
+    rate_counter: u16,
+    exponential_counter: u8,
+}
+
+impl Voice {
+    pub fn new() -> Self {
+        Voice {
+            frequency: 0,
+            pulse_width: 0,
+            control: 0,
+            attack_decay: 0,
+            sustain_release: 0,
+            accumulator: 0,
+            msb_rising: false,
+            noise: NOISE_SEED,
+            state: EnvelopeState::Release,
+            level: 0,
+            rate_counter: 0,
+            exponential_counter: 0,
+        }
+    }
+
+    pub fn set_control(&mut self, value: u8) {
+        let gate = value & GATE != 0;
+        if gate && self.control & GATE == 0 {
+            self.state = EnvelopeState::Attack;
+        } else if !gate && self.control & GATE != 0 {
+            self.state = EnvelopeState::Release;
+        }
+        if value & TEST != 0 {
+            self.accumulator = 0;
+        }
+        self.control = value;
+    }
+
+    pub fn clock(&mut self) {
+        self.clock_oscillator();
+        self.clock_envelope();
+    }
+
+    fn clock_oscillator(&mut self) {
+        if self.control & TEST != 0 {
+            self.msb_rising = false;
+            return;
+        }
+
+        let previous = self.accumulator;
+        self.accumulator = (self.accumulator + self.frequency as u32) & 0xffffff;
+        self.msb_rising = previous & 0x800000 == 0 && self.accumulator & 0x800000 != 0;
+
+        if previous & 0x080000 == 0 && self.accumulator & 0x080000 != 0 {
+            let bit = ((self.noise >> 22) ^ (self.noise >> 17)) & 1;
+            self.noise = ((self.noise << 1) & 0x7fffff) | bit;
+        }
+    }
+
+    fn clock_envelope(&mut self) {
+        let rate = match self.state {
+            EnvelopeState::Attack => self.attack_decay >> 4,
+            EnvelopeState::DecaySustain => self.attack_decay & 0x0f,
+            EnvelopeState::Release => self.sustain_release & 0x0f,
+        };
+
+        self.rate_counter += 1;
+        if self.rate_counter < RATE_PERIODS[rate as usize] {
+            return;
+        }
+        self.rate_counter = 0;
+
+        if self.state == EnvelopeState::Attack {
+            self.exponential_counter = 0;
+            self.level = self.level.saturating_add(1);
+            if self.level == 0xff {
+                self.state = EnvelopeState::DecaySustain;
+            }
+            return;
+        }
+
+        self.exponential_counter += 1;
+        if self.exponential_counter < exponential_period(self.level) {
+            return;
+        }
+        self.exponential_counter = 0;
+
+        let sustain = (self.sustain_release >> 4) * 0x11;
+        match self.state {
+            EnvelopeState::DecaySustain if self.level > sustain => self.level -= 1,
+            EnvelopeState::Release => self.level = self.level.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    /// Whether the MSB of the accumulator went high in the last cycle
+    pub fn msb_rising(&self) -> bool {
+        self.msb_rising
+    }
+
+    /// Resets the accumulator when synced to the voice before and its MSB
+    /// went high, which is done after all of the oscillators have been clocked
+    pub fn sync(&mut self, source_msb_rising: bool) {
+        if self.control & SYNC != 0 && source_msb_rising {
+            self.accumulator = 0;
+        }
+    }
+
+    /// The 12 bit output of the waveform, where the voice before is the
+    /// source of the ring modulation
+    pub fn waveform(&self, source: &Voice) -> u16 {
+        let selected = self.control & (TRIANGLE | SAWTOOTH | PULSE | NOISE);
+        if selected == 0 {
+            return 0;
+        }
+
+        let top = (self.accumulator >> 12) as u16;
+        let mut output = 0xfff;
+
+        if selected & TRIANGLE != 0 {
+            let mut msb = self.accumulator & 0x800000;
+            if self.control & RING != 0 {
+                msb ^= source.accumulator & 0x800000;
+            }
+            let folded = if msb != 0 {
+                !self.accumulator
+            } else {
+                self.accumulator
+            };
+            output &= ((folded >> 11) & 0xfff) as u16;
+        }
+        if selected & SAWTOOTH != 0 {
+            output &= top;
+        }
+        if selected & PULSE != 0 && self.control & TEST == 0 && top < self.pulse_width {
+            output = 0;
+        }
+        if selected & NOISE != 0 {
+            output &= self.noise_output();
+        }
+        output
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Whether any waveform is selected, as the voice is silent otherwise
+    pub fn audible(&self) -> bool {
+        self.control & (TRIANGLE | SAWTOOTH | PULSE | NOISE) != 0
+    }
+
+    // Bits 22, 20, 16, 13, 11, 7, 4 and 2 of the shift register, as the top
+    // 8 bits of the output
+    fn noise_output(&self) -> u16 {
+        let r = self.noise;
+        (((r >> 11) & 0x800)
+            | ((r >> 10) & 0x400)
+            | ((r >> 7) & 0x200)
+            | ((r >> 5) & 0x100)
+            | ((r >> 4) & 0x080)
+            | ((r >> 1) & 0x040)
+            | ((r << 1) & 0x020)
+            | ((r << 2) & 0x010)) as u16
+    }
+}
+
+// How many steps of the rate counter there are between each step of the
+// level, during decay and release
+fn exponential_period(level: u8) -> u8 {
+    match level {
+        0x5e..=0xff => 1,
+        0x37..=0x5d => 2,
+        0x1b..=0x36 => 4,
+        0x0f..=0x1a => 8,
+        0x07..=0x0e => 16,
+        _ => 30,
+    }
+}
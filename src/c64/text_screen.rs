@@ -0,0 +1,168 @@
+//! The text screen is the 40x25 characters of the video matrix, read the way
+//! the VIC-II would draw them, for showing the screen somewhere that can only
+//! show text, like a terminal.
+//!
+//! The screen codes are decoded to Unicode in the character set selected by
+//! $d018, with the colour of each character from the colour RAM, and the
+//! background from $d021. Reversed characters swap the two, and with extended
+//! colour mode, the top two bits of the screen code pick one of the four
+//! background colours instead. Bitmaps and multicolour are shown as text all the
+//! same, as there's no better way.
+
+use super::petscii::{screen_code_to_char, Charset};
+use super::vicII::palette::Color;
+use super::C64;
+
+pub const COLUMNS: usize = 40;
+pub const ROWS: usize = 25;
+
+// The registers of the VIC-II involved
+const CONTROL_1: usize = 0xd011;
+const MEMORY_POINTERS: usize = 0xd018;
+const BORDER_COLOR: usize = 0xd020;
+const BACKGROUND_COLOR: usize = 0xd021;
+
+const DEN: u8 = 1 << 4;
+const ECM: u8 = 1 << 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub character: char,
+    pub foreground: Color,
+    pub background: Color,
+}
+
+/// The characters on the screen, row by row
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextScreen {
+    pub cells: Vec<Cell>,
+    pub border: Color,
+}
+
+impl TextScreen {
+    pub fn cell(&self, column: usize, row: usize) -> Cell {
+        self.cells[row * COLUMNS + column]
+    }
+
+    /// The characters of a row, without any colours
+    pub fn row_text(&self, row: usize) -> String {
+        self.cells[row * COLUMNS..(row + 1) * COLUMNS]
+            .iter()
+            .map(|cell| cell.character)
+            .collect()
+    }
+}
+
+impl C64 {
+    /// The text on the screen, with the colours from the palette
+    pub fn text_screen(&self) -> TextScreen {
+        let vic = self.vic();
+        let palette = vic.palette();
+        let memory = self.cpu.bus().vic_memory();
+
+        let control = vic.peek(CONTROL_1);
+        let pointers = vic.peek(MEMORY_POINTERS);
+        let border = palette.color(vic.peek(BORDER_COLOR));
+
+        let screen = (pointers >> 4) as u16 * 0x400;
+        let charset = match pointers & 0x02 {
+            0 => Charset::Uppercase,
+            _ => Charset::Lowercase,
+        };
+
+        let cells = (0..(COLUMNS * ROWS) as u16)
+            .map(|offset| {
+                if control & DEN == 0 {
+                    return Cell {
+                        character: ' ',
+                        foreground: border,
+                        background: border,
+                    };
+                }
+
+                let mut code = memory.read(screen + offset);
+                let mut background = vic.peek(BACKGROUND_COLOR);
+                if control & ECM != 0 {
+                    background = vic.peek(BACKGROUND_COLOR + (code >> 6) as usize);
+                    code &= 0x3f;
+                }
+
+                let foreground = palette.color(memory.color(offset));
+                let background = palette.color(background);
+                let character = screen_code_to_char(code, charset);
+
+                match code & 0x80 {
+                    0 => Cell {
+                        character,
+                        foreground,
+                        background,
+                    },
+                    _ => Cell {
+                        character,
+                        foreground: background,
+                        background: foreground,
+                    },
+                }
+            })
+            .collect();
+
+        TextScreen { cells, border }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::c64::vicII::palette::{Colors, PEPTO};
+
+    fn c64() -> C64 {
+        let mut c64 = C64::new();
+        c64.poke(0xd011, 0x1b);
+        c64.poke(0xd018, 0x14);
+        c64.poke(0xd020, Colors::light_blue as u8);
+        c64.poke(0xd021, Colors::blue as u8);
+        for offset in 0..1000 {
+            c64.poke(0x0400 + offset, 0x20);
+            c64.poke(0xd800 + offset, Colors::light_blue as u8);
+        }
+        c64
+    }
+
+    #[test]
+    fn should_read_text_and_colours() {
+        let mut c64 = c64();
+        for (offset, code) in [0x08, 0x09, 0x80 | 0x21].iter().enumerate() {
+            c64.poke(0x0400 + 40 + offset as u16, *code);
+        }
+        c64.poke(0xd800 + 40, Colors::white as u8);
+
+        let screen = c64.text_screen();
+        assert_eq!(screen.border, PEPTO[Colors::light_blue]);
+        assert_eq!(screen.row_text(1).trim_end(), "HI!");
+
+        let cell = screen.cell(0, 1);
+        assert_eq!(cell.foreground, PEPTO[Colors::white]);
+        assert_eq!(cell.background, PEPTO[Colors::blue]);
+
+        // Reversed
+        let cell = screen.cell(2, 1);
+        assert_eq!(cell.foreground, PEPTO[Colors::blue]);
+        assert_eq!(cell.background, PEPTO[Colors::light_blue]);
+    }
+
+    #[test]
+    fn should_follow_charset_and_screen_of_vic() {
+        let mut c64 = c64();
+        c64.poke(0xd018, 0x26);
+        c64.poke(0x0800, 0x08);
+
+        let screen = c64.text_screen();
+        assert_eq!(screen.cell(0, 0).character, 'h');
+
+        // Blank, when the screen is turned off
+        c64.poke(0xd011, 0x0b);
+        let screen = c64.text_screen();
+        assert_eq!(screen.cell(0, 0).character, ' ');
+        assert_eq!(screen.cell(0, 0).background, screen.border);
+    }
+}
@@ -1,128 +1,569 @@
-use std::str::FromStr;
-
-enum Colors {
-    black = 0x0,
-    white = 0x1,
-    red = 0x2,
-    cyan = 0x3,
-    purple = 0x4,
-    green = 0x5,
-    blue = 0x6,
-    yellow = 0x7,
-    orange = 0x8,
-    brown = 0x9,
-    light_red = 0xa,
-    dark_grey = 0xb,
-    grey = 0xc,
-    light_green = 0xd,
-    light_blue = 0xe,
-    light_grey = 0xf,
+//! The VIC-II is the video chip of the C64. It has 47 registers at $d000-$d02e,
+//! which are repeated every 64 bytes all the way up to $d3ff, as only the lowest
+//! six bits of the address are decoded.
+//!
+//! The chip draws the picture line by line, with a fixed amount of cycles per
+//! line and lines per frame depending on the video standard:
+//!
+//! - PAL  - 63 cycles per line, 312 lines per frame
+//! - NTSC - 65 cycles per line, 263 lines per frame
+//!
+//! The current raster line can be read from $d012, with its 9th bit in bit 7 of
+//! $d011. Writing to the same bits sets the line to compare against instead, and
+//! when the raster reaches that line, the raster interrupt is triggered. Every
+//! interrupt source is latched in $d019 until acknowledged by writing a 1 to its
+//! bit, and the IRQ line is pulled for those sources that are enabled in $d01a.
+//!
+//! Every cycle the chip draws 8 pixels into the frame, which covers every cycle
+//! of every line, including the border and the parts that are never visible on
+//! a TV. The frame holds colour numbers, which are turned into RGB values using
+//! a palette when the framebuffer is read.
+//!
+//! The VIC-II can only see 16K of memory at a time, in the bank selected by CIA 2,
+//! where the character ROM replaces the RAM at $1000-$1fff in bank 0 and 2. It
+//! also has a 4 bit wide data bus of its own, to the colour RAM.
+//!
+//! The VIC-II shares the bus with the CPU, normally only using the half of each
+//! cycle when the CPU doesn't. On the first line of every row of text, a "bad
+//! line", it needs 40 more cycles to read the screen codes and colours, and each
+//! sprite shown on the next line needs 2 more cycles for its data. To take the
+//! bus, the VIC-II pulls BA low 3 cycles early, which is connected to RDY of the
+//! CPU. The CPU stops at the next read while RDY is low, but finishes any writes
+//! first, which is at most 3 in a row.
+//!
+//! The border is drawn by two flip-flops, one for the top and bottom and one
+//! for the sides, which are only set and cleared when the raster hits the edges
+//! of the display window exactly. Changing RSEL or CSEL just as the raster
+//! passes an edge keeps the border from being drawn at all.
+
+mod graphics;
+pub mod pal_filter;
+pub mod palette;
+mod sprites;
+
+use std::ops::RangeInclusive;
+
+use self::graphics::Graphics;
+use self::palette::{Color, Palette};
+use self::sprites::Sprite;
+
+/// The timing of a video standard
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    pub cycles_per_line: u16,
+    pub lines_per_frame: u16,
+    /// The part of the frame that is visible on a TV
+    pub visible: Area,
 }
 
-struct Palette {
-    Black: Color,
-    White: Color,
-    Red: Color,
-    Cyan: Color,
-    Purple: Color,
-    Green: Color,
-    Blue: Color,
-    Yellow: Color,
-    Orange: Color,
-    Brown: Color,
-    Light_Red: Color,
-    Dark_Grey: Color,
-    Grey: Color,
-    Light_Green: Color,
-    Light_Blue: Color,
-    Light_Grey: Color,
+/// A rectangle of the frame in pixels, where lines past the end of the frame
+/// wrap around to the first ones
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Area {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
 }
 
+impl Timing {
+    /// The 6569
+    pub const PAL: Timing = Timing {
+        cycles_per_line: 63,
+        lines_per_frame: 312,
+        visible: Area {
+            x: 80,
+            y: 16,
+            width: 403,
+            height: 284,
+        },
+    };
+    /// The 6567R8
+    pub const NTSC: Timing = Timing {
+        cycles_per_line: 65,
+        lines_per_frame: 263,
+        visible: Area {
+            x: 81,
+            y: 41,
+            width: 418,
+            height: 235,
+        },
+    };
+    /// The 6567R56A, the first NTSC version, with one line and cycle less
+    pub const NTSC_OLD: Timing = Timing {
+        cycles_per_line: 64,
+        lines_per_frame: 262,
+        visible: Area {
+            x: 81,
+            y: 41,
+            width: 411,
+            height: 234,
+        },
+    };
+    /// The 6572, as in the Drean C64 sold in Argentina, which has the lines of
+    /// PAL and the cycles of NTSC
+    pub const PAL_N: Timing = Timing {
+        cycles_per_line: 65,
+        lines_per_frame: 312,
+        visible: Area {
+            x: 80,
+            y: 16,
+            width: 403,
+            height: 284,
+        },
+    };
+}
+
+// Where the border flip-flops are cleared and set, for the 25 rows / 40
+// columns settings of RSEL and CSEL in $d011/$d016. With them cleared, the
+// display window shrinks by 4 lines at the top and bottom, and by 7 and 9
+// pixels at the sides.
+const BORDER_TOP: u16 = 51;
+const BORDER_BOTTOM: u16 = 251;
+const BORDER_LEFT: i32 = 24;
+const BORDER_RIGHT: i32 = 344;
+
+// The lines where bad lines can happen, and the cycles where BA is pulled
+// low for them
+const FIRST_DMA_LINE: u16 = 0x30;
+const LAST_DMA_LINE: u16 = 0xf7;
+const BAD_LINE_BA: RangeInclusive<u16> = 12..=54;
+
+// The column of the frame where X coordinate 0 ends up, so that the display
+// window starts at the first pixel of cycle 17
+const X_OFFSET: i32 = 104;
+
+// Control register 1, $d011
+const RSEL: u8 = 1 << 3;
+const DEN: u8 = 1 << 4;
+const BMM: u8 = 1 << 5;
+const ECM: u8 = 1 << 6;
+
+// Control register 2, $d016
+const CSEL: u8 = 1 << 3;
+const MCM: u8 = 1 << 4;
+
+// The display modes, by their ECM/BMM/MCM bits
+const BMM_MCM: u8 = BMM | MCM;
+const ECM_MCM: u8 = ECM | MCM;
+const ECM_BMM: u8 = ECM | BMM;
+const ECM_BMM_MCM: u8 = ECM | BMM | MCM;
+
+// The registers with a special meaning, as offsets from $d000
+const CONTROL_1: usize = 0x11;
+const RASTER: usize = 0x12;
+const CONTROL_2: usize = 0x16;
+const SPRITE_Y_EXPAND: usize = 0x17;
+const MEMORY_POINTERS: usize = 0x18;
+const INTERRUPT: usize = 0x19;
+const INTERRUPT_ENABLE: usize = 0x1a;
+const SPRITE_SPRITE_COLLISION: usize = 0x1e;
+const SPRITE_BACKGROUND_COLLISION: usize = 0x1f;
+const BORDER_COLOR: usize = 0x20;
+const BACKGROUND_COLOR: usize = 0x21;
+const REGISTER_COUNT: usize = 0x2f;
+
+/// The interrupt sources, as bits in $d019 and $d01a
+pub const IRQ_RASTER: u8 = 1 << 0;
+pub const IRQ_SPRITE_BACKGROUND: u8 = 1 << 1;
+pub const IRQ_SPRITE_SPRITE: u8 = 1 << 2;
+pub const IRQ_LIGHTPEN: u8 = 1 << 3;
+
+// Bits without any function always read as 1
 #[rustfmt::skip]
-static RAW_PALETTE: Palette = Palette {
-    Black:        Color::hex(0x000000),
-    White:        Color::hex(0xffffff),
-    Red:          Color::hex(0x68372b),
-    Cyan:         Color::hex(0x70a4b2),
-    Purple:       Color::hex(0x6f3d86),
-    Green:        Color::hex(0x588d43),
-    Blue:         Color::hex(0x352879),
-    Yellow:       Color::hex(0xb8c76f),
-    Orange:       Color::hex(0x6f4f25),
-    Brown:        Color::hex(0x433900),
-    Light_Red:    Color::hex(0x9a6759),
-    Dark_Grey:    Color::hex(0x444444),
-    Grey:         Color::hex(0x6c6c6c),
-    Light_Green:  Color::hex(0x9ad284),
-    Light_Blue:   Color::hex(0x6c5eb5),
-    Light_Grey:   Color::hex(0x959595),
-};
-
-/*
-  This palette takes it's color values from this article:
-  https://www.pepto.de/projects/colorvic/2001/
-*/
-#[rustfmt::skip]
-static GAMMA_CORRECTED_PALETTE: Palette = Palette {
-    Black:        Color::hex(0x000000),
-    White:        Color::hex(0xffffff),
-    Red:          Color::hex(0x68372b),
-    Cyan:         Color::hex(0x70a4b2),
-    Purple:       Color::hex(0x6f3d86),
-    Green:        Color::hex(0x588d43),
-    Blue:         Color::hex(0x352879),
-    Yellow:       Color::hex(0xb8c76f),
-    Orange:       Color::hex(0x6f4f25),
-    Brown:        Color::hex(0x433900),
-    Light_Red:    Color::hex(0x9a6759),
-    Dark_Grey:    Color::hex(0x444444),
-    Grey:         Color::hex(0x6c6c6c),
-    Light_Green:  Color::hex(0x9ad284),
-    Light_Blue:   Color::hex(0x6c5eb5),
-    Light_Grey:   Color::hex(0x959595),
-};
-
-#[derive(Debug)]
-struct Color {
-    r: u8,
-    g: u8,
-    b: u8,
+const UNUSED_BITS: [u8; REGISTER_COUNT] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0x00,
+    0x01, 0x70, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0,
+    0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0, 0xf0,
+];
+
+#[derive(Clone, Debug)]
+pub struct VicII {
+    timing: Timing,
+    registers: [u8; REGISTER_COUNT],
+
+    // The line being drawn, and the cycle within it, counted from 1 the same
+    // way as in most of the documentation
+    raster_line: u16,
+    cycle: u16,
+
+    // The latched interrupt sources, $d019
+    interrupts: u8,
+
+    // Whether DEN was set at any cycle of the first line where bad lines can
+    // happen, which is required for any of them to happen in this frame
+    den_seen: bool,
+
+    // The state of the graphics, see graphics.rs, and the screen codes and
+    // colours read on the last bad line
+    vc_base: u16,
+    vc: u16,
+    rc: u8,
+    vmli: usize,
+    display_state: bool,
+    bad_line_ba: u16,
+    video_matrix: [(u8, u8); 40],
+
+    // The graphics read for every column of the current line, waiting to be
+    // drawn
+    graphics: Vec<Graphics>,
+
+    // The border is drawn while either of these is set
+    main_border: bool,
+    vertical_border: bool,
+
+    sprites: [Sprite; 8],
+
+    // The sprites that have collided since the registers were last read,
+    // $d01e and $d01f
+    sprite_collisions: u8,
+    background_collisions: u8,
+
+    // The colour of every pixel in the frame, and the number of frames drawn
+    frame: Vec<u8>,
+    frames: u64,
+
+    // The RGB values the colours are turned into
+    palette: Palette,
+}
+
+/// What the VIC-II sees of the memory
+pub struct VicMemory<'a> {
+    pub ram: &'a [u8],
+    pub chargen: Option<&'a [u8]>,
+    pub color_ram: &'a [u8],
+    /// The start of the 16K bank, as selected by CIA 2
+    pub bank: u16,
 }
 
-impl Color {
-    #[allow(clippy::identity_op)]
-    const fn hex(value: u32) -> Self {
-        let r = (value >> 0x10 & 0xff) as u8;
-        let g = (value >> 0x08 & 0xff) as u8;
-        let b = (value >> 0x00 & 0xff) as u8;
-        Color { r, g, b }
+impl VicMemory<'_> {
+    /// Reads an address within the current bank
+    pub fn read(&self, address: u16) -> u8 {
+        let address = address & 0x3fff;
+
+        match (self.chargen, self.bank, address) {
+            (Some(chargen), 0x0000 | 0x8000, 0x1000..=0x1fff) => chargen[address as usize - 0x1000],
+            _ => self.ram[(self.bank | address) as usize],
+        }
+    }
+
+    pub fn color(&self, offset: u16) -> u8 {
+        self.color_ram[offset as usize & 0x3ff] & 0x0f
     }
 }
 
-impl FromStr for Color {
-    type Err = ();
+impl VicII {
+    pub fn new(timing: Timing) -> Self {
+        VicII {
+            timing,
+            registers: [0; REGISTER_COUNT],
+            raster_line: 0,
+            cycle: 1,
+            interrupts: 0,
+            den_seen: false,
+            vc_base: 0,
+            vc: 0,
+            rc: 0,
+            vmli: 0,
+            display_state: false,
+            bad_line_ba: 0,
+            video_matrix: [(0, 0); 40],
+            graphics: vec![Graphics::default(); timing.cycles_per_line as usize * 8],
+            main_border: true,
+            vertical_border: true,
+            sprites: [Sprite::default(); 8],
+            sprite_collisions: 0,
+            background_collisions: 0,
+            frame: vec![0; timing.cycles_per_line as usize * 8 * timing.lines_per_frame as usize],
+            frames: 0,
+            palette: Palette::default(),
+        }
+    }
+
+    /// The width of the frame in pixels, 8 for every cycle of a line
+    pub fn width(&self) -> usize {
+        self.timing.cycles_per_line as usize * 8
+    }
+
+    /// The height of the frame in pixels, one for every line
+    pub fn height(&self) -> usize {
+        self.timing.lines_per_frame as usize
+    }
+
+    /// The colour number of every pixel in the frame
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// The number of frames completed since power on
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The frame as RGB values, three bytes per pixel, using the palette
+    pub fn framebuffer(&self) -> Vec<u8> {
+        self.frame
+            .iter()
+            .flat_map(|index| {
+                let Color { r, g, b } = self.palette[*index];
+                [r, g, b]
+            })
+            .collect()
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Changes the palette, which takes effect at once, as the frame holds
+    /// colour numbers
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// The colour number of every pixel in the visible area of the frame
+    pub fn visible_frame(&self) -> Vec<u8> {
+        let Area {
+            x,
+            y,
+            width,
+            height,
+        } = self.timing.visible;
+
+        (y..y + height)
+            .flat_map(|line| {
+                let start = (line % self.height()) * self.width() + x;
+                &self.frame[start..start + width]
+            })
+            .copied()
+            .collect()
+    }
+
+    /// The 320x200 pixels of the display window, inside the border when it
+    /// is 25 rows and 40 columns
+    pub fn display_window(&self) -> Area {
+        Area {
+            x: (BORDER_LEFT + X_OFFSET) as usize,
+            y: BORDER_TOP as usize,
+            width: 320,
+            height: 200,
+        }
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.starts_with('#') || s.starts_with("0x") {
-            let s = s.trim_start_matches('#').trim_start_matches("0x");
-            if let Ok(val) = u32::from_str_radix(s, 16) {
-                Ok(Color::hex(val))
-            } else {
-                Err(())
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn raster_line(&self) -> u16 {
+        self.raster_line
+    }
+
+    pub fn cycle(&self) -> u16 {
+        self.cycle
+    }
+
+    /// The line that triggers the raster interrupt
+    pub fn raster_compare(&self) -> u16 {
+        (self.registers[CONTROL_1] as u16 & 0x80) << 1 | self.registers[RASTER] as u16
+    }
+
+    /// The offset of the video matrix within the current bank
+    pub fn screen_address(&self) -> u16 {
+        (self.registers[MEMORY_POINTERS] as u16 >> 4) * 0x0400
+    }
+
+    /// The offset of the character set within the current bank
+    pub fn charset_address(&self) -> u16 {
+        (self.registers[MEMORY_POINTERS] as u16 >> 1 & 0x07) * 0x0800
+    }
+
+    pub fn border_color(&self) -> u8 {
+        self.registers[BORDER_COLOR] & 0x0f
+    }
+
+    pub fn background_color(&self) -> u8 {
+        self.registers[BACKGROUND_COLOR] & 0x0f
+    }
+
+    /// Whether the IRQ line is pulled, by any of the enabled sources
+    pub fn irq(&self) -> bool {
+        self.interrupts & self.registers[INTERRUPT_ENABLE] != 0
+    }
+
+    /// Whether the current line is a bad line, where the VIC-II reads the
+    /// screen codes and colours of the next row of text
+    pub fn bad_line(&self) -> bool {
+        self.den_seen
+            && (FIRST_DMA_LINE..=LAST_DMA_LINE).contains(&self.raster_line)
+            && self.raster_line & 0x07 == (self.registers[CONTROL_1] & 0x07) as u16
+    }
+
+    /// Whether BA is pulled low for the coming cycle, which halts the CPU at
+    /// its next read
+    pub fn ba_low(&self) -> bool {
+        (self.bad_line() && BAD_LINE_BA.contains(&self.cycle)) || self.sprite_ba_low()
+    }
+
+    /// Advances the chip by one cycle, drawing the pixels of it
+    pub fn tick(&mut self, memory: &VicMemory) {
+        if self.raster_line == FIRST_DMA_LINE && self.registers[CONTROL_1] & DEN != 0 {
+            self.den_seen = true;
+        }
+
+        self.sprite_cycle(memory);
+        self.graphics_cycle(memory);
+        self.draw();
+
+        if self.cycle == self.timing.cycles_per_line {
+            self.check_vertical_border();
+        }
+        self.cycle += 1;
+
+        if self.cycle > self.timing.cycles_per_line {
+            self.cycle = 1;
+            self.raster_line = (self.raster_line + 1) % self.timing.lines_per_frame;
+            if self.raster_line == 0 {
+                self.frames += 1;
+                self.den_seen = false;
+            }
+
+            if self.raster_line == self.raster_compare() {
+                self.interrupts |= IRQ_RASTER;
             }
-        } else {
-            Err(())
         }
     }
-}
 
-#[allow(clippy::identity_op)]
-impl From<u32> for Color {
-    fn from(value: u32) -> Self {
-        let r = (value >> 0x10 & 0xff) as u8;
-        let g = (value >> 0x08 & 0xff) as u8;
-        let b = (value >> 0x00 & 0xff) as u8;
-        Color { r, g, b }
+    fn draw(&mut self) {
+        let first = (self.cycle as usize - 1) * 8;
+        let start = self.raster_line as usize * self.width();
+
+        for column in first..first + 8 {
+            self.frame[start + column] = self.pixel(column);
+        }
+    }
+
+    // The colour of the pixel at the column of the current line, where the
+    // sprites are drawn on top of the graphics, unless they are set to be
+    // behind the foreground, and the border on top of everything.
+    fn pixel(&mut self, column: usize) -> u8 {
+        self.check_main_border(column as i32 - X_OFFSET);
+
+        let (color, foreground) = self.graphics_color(self.graphics[column]);
+        let (sprite, sprites) = self.sprite_pixel(column);
+
+        self.collide(sprites, foreground && !self.main_border);
+
+        match sprite {
+            _ if self.main_border => self.border_color(),
+            Some((_, true)) if foreground => color,
+            Some((color, _)) => color,
+            None => color,
+        }
+    }
+
+    // Sets or clears the border flip-flops when the X coordinate reaches the
+    // sides of the display window. The vertical one is checked here as well,
+    // so that the border doesn't cover the first line of the display window.
+    fn check_main_border(&mut self, x: i32) {
+        let (left, right) = match self.registers[CONTROL_2] & CSEL {
+            0 => (BORDER_LEFT + 7, BORDER_RIGHT - 9),
+            _ => (BORDER_LEFT, BORDER_RIGHT),
+        };
+
+        if x == right {
+            self.main_border = true;
+        }
+        if x == left {
+            self.check_vertical_border();
+            if !self.vertical_border {
+                self.main_border = false;
+            }
+        }
+    }
+
+    // Sets or clears the vertical border flip-flop when the raster reaches the
+    // bottom or the top of the display window, where the top only counts with
+    // DEN set. The raster has to hit the lines exactly, so changing RSEL at
+    // the right time keeps the border open.
+    fn check_vertical_border(&mut self) {
+        let (top, bottom) = match self.registers[CONTROL_1] & RSEL {
+            0 => (BORDER_TOP + 4, BORDER_BOTTOM - 4),
+            _ => (BORDER_TOP, BORDER_BOTTOM),
+        };
+
+        if self.raster_line == bottom {
+            self.vertical_border = true;
+        } else if self.raster_line == top && self.registers[CONTROL_1] & DEN != 0 {
+            self.vertical_border = false;
+        }
+    }
+
+    // The four background colours, $d021-$d024
+    fn background_colors(&self, index: u8) -> u8 {
+        self.registers[BACKGROUND_COLOR + index as usize] & 0x0f
+    }
+
+    /// The offset of the bitmap within the current bank
+    pub fn bitmap_address(&self) -> u16 {
+        (self.registers[MEMORY_POINTERS] as u16 & 0x08) << 10
+    }
+
+    /// Reads a register, where reading the collision registers clears them
+    pub fn read(&mut self, address: usize) -> u8 {
+        let value = self.peek(address);
+
+        match address & 0x3f {
+            SPRITE_SPRITE_COLLISION => self.sprite_collisions = 0,
+            SPRITE_BACKGROUND_COLLISION => self.background_collisions = 0,
+            _ => {}
+        }
+        value
+    }
+
+    /// Reads a register without any side effects
+    pub fn peek(&self, address: usize) -> u8 {
+        let register = address & 0x3f;
+
+        match register {
+            CONTROL_1 => self.registers[CONTROL_1] & 0x7f | ((self.raster_line >> 8) as u8) << 7,
+            RASTER => self.raster_line as u8,
+            INTERRUPT => {
+                let irq = if self.irq() { 0x80 } else { 0x00 };
+                self.interrupts | irq | UNUSED_BITS[INTERRUPT]
+            }
+            SPRITE_SPRITE_COLLISION => self.sprite_collisions,
+            SPRITE_BACKGROUND_COLLISION => self.background_collisions,
+            REGISTER_COUNT.. => 0xff,
+            _ => self.registers[register] | UNUSED_BITS[register],
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        let register = address & 0x3f;
+
+        match register {
+            // Writing a 1 acknowledges the interrupt
+            INTERRUPT => self.interrupts &= !value & 0x0f,
+            INTERRUPT_ENABLE => self.registers[register] = value & 0x0f,
+            CONTROL_1 | RASTER => {
+                let compare = self.raster_compare();
+                self.registers[register] = value;
+
+                // Moving the compare to the current line triggers it as well
+                if self.raster_compare() != compare && self.raster_compare() == self.raster_line {
+                    self.interrupts |= IRQ_RASTER;
+                }
+            }
+            SPRITE_Y_EXPAND => {
+                self.registers[register] = value;
+                self.sprite_y_expand_written(value);
+            }
+            SPRITE_SPRITE_COLLISION | SPRITE_BACKGROUND_COLLISION | REGISTER_COUNT.. => {}
+            _ => self.registers[register] = value,
+        }
     }
 }
 
@@ -130,25 +571,400 @@ impl From<u32> for Color {
 mod tests {
     use super::*;
 
+    static RAM: [u8; 0x10000] = [0; 0x10000];
+    static COLOR_RAM: [u8; 0x400] = [0; 0x400];
+
+    fn no_memory() -> VicMemory<'static> {
+        VicMemory {
+            ram: &RAM,
+            chargen: None,
+            color_ram: &COLOR_RAM,
+            bank: 0x0000,
+        }
+    }
+
+    fn run_lines(vic: &mut VicII, lines: u16) {
+        for _ in 0..lines * vic.timing().cycles_per_line {
+            vic.tick(&no_memory());
+        }
+    }
+
+    #[test]
+    fn should_count_raster_lines() {
+        let mut vic = VicII::new(Timing::PAL);
+
+        run_lines(&mut vic, 10);
+        assert_eq!(vic.read(0xd012), 10);
+        assert_eq!(vic.read(0xd011) & 0x80, 0x00);
+
+        run_lines(&mut vic, 300);
+        assert_eq!(vic.read(0xd012), (310 - 256) as u8);
+        assert_eq!(vic.read(0xd011) & 0x80, 0x80);
+
+        // The frame ends after line 311
+        run_lines(&mut vic, 2);
+        assert_eq!(vic.raster_line(), 0);
+        assert_eq!(vic.cycle(), 1);
+    }
+
+    #[test]
+    fn should_use_ntsc_timing() {
+        let mut vic = VicII::new(Timing::NTSC);
+
+        run_lines(&mut vic, 262);
+        assert_eq!(vic.raster_line(), 262);
+
+        for _ in 0..64 {
+            vic.tick(&no_memory());
+        }
+        assert_eq!(vic.cycle(), 65);
+        vic.tick(&no_memory());
+        assert_eq!(vic.raster_line(), 0);
+    }
+
+    #[test]
+    fn should_wrap_visible_area_past_the_last_line() {
+        let mut vic = VicII::new(Timing::NTSC);
+        vic.write(0xd020, 0x0e);
+        run_lines(&mut vic, 263);
+
+        let visible = vic.visible_frame();
+        assert_eq!(visible.len(), 418 * 235);
+        assert!(visible.iter().all(|color| *color == 0x0e));
+    }
+
+    #[test]
+    fn should_trigger_raster_interrupt() {
+        let mut vic = VicII::new(Timing::PAL);
+
+        // Line 300, with the 9th bit in $d011
+        vic.write(0xd011, 0x9b);
+        vic.write(0xd012, 0x2c);
+        vic.write(0xd01a, IRQ_RASTER);
+        assert_eq!(vic.raster_compare(), 300);
+
+        run_lines(&mut vic, 299);
+        assert!(!vic.irq());
+        run_lines(&mut vic, 1);
+        assert!(vic.irq());
+        assert_eq!(vic.read(0xd019), 0xf1);
+
+        // Acknowledge it
+        vic.write(0xd019, 0xff);
+        assert!(!vic.irq());
+        assert_eq!(vic.read(0xd019), 0x70);
+    }
+
+    #[test]
+    fn should_latch_disabled_interrupts() {
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd012, 0x01);
+
+        run_lines(&mut vic, 1);
+        assert!(!vic.irq());
+        assert_eq!(vic.read(0xd019), 0x71);
+
+        vic.write(0xd01a, IRQ_RASTER);
+        assert!(vic.irq());
+    }
+
+    #[test]
+    fn should_mirror_registers() {
+        let mut vic = VicII::new(Timing::PAL);
+
+        vic.write(0xd3e0, 0x06);
+        assert_eq!(vic.read(0xd020), 0xf6);
+        assert_eq!(vic.border_color(), 0x06);
+
+        // The unused registers
+        vic.write(0xd02f, 0x00);
+        assert_eq!(vic.read(0xd03f), 0xff);
+    }
+
+    #[test]
+    fn should_decode_memory_pointers() {
+        let mut vic = VicII::new(Timing::PAL);
+
+        vic.write(0xd018, 0x14);
+        assert_eq!(vic.screen_address(), 0x0400);
+        assert_eq!(vic.charset_address(), 0x1000);
+        assert_eq!(vic.read(0xd018), 0x15);
+    }
+
+    fn draw_frame(vic: &mut VicII, memory: &VicMemory) {
+        let frame = vic.frames();
+        while vic.frames() == frame {
+            vic.tick(memory);
+        }
+    }
+
+    // The colour of a pixel, by its X coordinate and line
+    fn pixel_at(vic: &VicII, x: i32, line: usize) -> u8 {
+        vic.frame()[line * vic.width() + (x + X_OFFSET) as usize]
+    }
+
+    #[test]
+    fn should_draw_standard_text_mode() {
+        let mut ram = vec![0x20; 0x10000];
+        let mut color_ram = vec![0x0e; 0x400];
+
+        // An "A" in the top left corner, in white, and a character set at $2000
+        ram[0x0400] = 0x01;
+        color_ram[0] = 0x01;
+        ram[0x2008..0x2010].copy_from_slice(&[0x18, 0x3c, 0x66, 0x7e, 0x66, 0x66, 0x66, 0x00]);
+        ram[0x2100..0x2108].fill(0x00);
+
+        let memory = VicMemory {
+            ram: &ram,
+            chargen: None,
+            color_ram: &color_ram,
+            bank: 0x0000,
+        };
+
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, 0x1b);
+        vic.write(0xd016, 0x08);
+        vic.write(0xd018, 0x18);
+        vic.write(0xd020, 0x0e);
+        vic.write(0xd021, 0x06);
+
+        draw_frame(&mut vic, &memory);
+
+        // The border
+        assert_eq!(pixel_at(&vic, 23, 100), 0x0e);
+        assert_eq!(pixel_at(&vic, 100, 50), 0x0e);
+
+        // The first line of the "A" is %00011000
+        assert_eq!(pixel_at(&vic, 24 + 2, 51), 0x06);
+        assert_eq!(pixel_at(&vic, 24 + 3, 51), 0x01);
+        assert_eq!(pixel_at(&vic, 24 + 4, 51), 0x01);
+        assert_eq!(pixel_at(&vic, 24 + 5, 51), 0x06);
+
+        // The last line of the "A" is empty
+        assert_eq!(pixel_at(&vic, 24 + 3, 58), 0x06);
+
+        let rgb = vic.framebuffer();
+        let offset = (51 * vic.width() + (24 + 3 + X_OFFSET) as usize) * 3;
+        assert_eq!(rgb[offset..offset + 3], [0xff, 0xff, 0xff]);
+    }
+
     #[test]
-    fn can_construct_color_from_u32() {
-        let c = Color::hex(0x345678);
-        assert_eq!(c.r, 0x34);
-        assert_eq!(c.g, 0x56);
-        assert_eq!(c.b, 0x78);
+    fn should_draw_framebuffer_with_palette() {
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd020, 0x02);
+        run_lines(&mut vic, 1);
+        assert_eq!(vic.framebuffer()[..3], [0x68, 0x37, 0x2b]);
+
+        vic.set_palette(palette::COLODORE.clone());
+        assert_eq!(vic.framebuffer()[..3], [0x81, 0x33, 0x38]);
+    }
+
+    #[test]
+    fn should_shrink_display_window() {
+        let memory = no_memory();
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, 0x13);
+        vic.write(0xd016, 0x00);
+        vic.write(0xd020, 0x0e);
+
+        draw_frame(&mut vic, &memory);
+
+        assert_eq!(pixel_at(&vic, 30, 100), 0x0e);
+        assert_eq!(pixel_at(&vic, 31, 100), 0x00);
+        assert_eq!(pixel_at(&vic, 100, 54), 0x0e);
+        assert_eq!(pixel_at(&vic, 100, 55), 0x00);
+        assert_eq!(pixel_at(&vic, 100, 246), 0x00);
+        assert_eq!(pixel_at(&vic, 100, 247), 0x0e);
+    }
+
+    // Runs until the cycle of the line is the next one to be drawn
+    fn run_until(vic: &mut VicII, line: u16, cycle: u16) {
+        while vic.raster_line() != line || vic.cycle() != cycle {
+            vic.tick(&no_memory());
+        }
+    }
+
+    #[test]
+    fn should_open_top_and_bottom_border() {
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, 0x1b);
+        vic.write(0xd016, 0x08);
+        vic.write(0xd020, 0x0e);
+
+        // Switching to 24 rows after the bottom of that window, but before
+        // the bottom of the 25 row one
+        run_until(&mut vic, 249, 1);
+        vic.write(0xd011, 0x13);
+        run_until(&mut vic, 300, 1);
+
+        assert_eq!(pixel_at(&vic, 100, 260), 0x00);
+        assert_eq!(pixel_at(&vic, 100, 299), 0x00);
+
+        // The side borders are still there
+        assert_eq!(pixel_at(&vic, 20, 260), 0x0e);
+        assert_eq!(pixel_at(&vic, 344, 260), 0x0e);
+    }
+
+    #[test]
+    fn should_open_side_border() {
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, 0x1b);
+        vic.write(0xd016, 0x08);
+        vic.write(0xd020, 0x0e);
+
+        // Switching to 38 columns in cycle 56, after the right edge of that
+        // window, but before the right edge of the 40 column one
+        run_until(&mut vic, 100, 57);
+        vic.write(0xd016, 0x00);
+        run_until(&mut vic, 101, 1);
+        vic.write(0xd016, 0x08);
+        run_until(&mut vic, 102, 1);
+
+        assert_eq!(pixel_at(&vic, 350, 99), 0x0e);
+        assert_eq!(pixel_at(&vic, 350, 100), 0x00);
+
+        // It stays open until the right edge of the next line
+        assert_eq!(pixel_at(&vic, 10, 101), 0x00);
+        assert_eq!(pixel_at(&vic, 344, 101), 0x0e);
+    }
+
+    #[test]
+    fn should_see_character_rom_in_bank_0_and_2() {
+        let mut ram = vec![0x00; 0x10000];
+        ram[0x9000] = 0x11;
+        ram[0x5000] = 0x22;
+        let chargen = vec![0xcc; 0x1000];
+
+        let mut memory = VicMemory {
+            ram: &ram,
+            chargen: Some(&chargen),
+            color_ram: &COLOR_RAM,
+            bank: 0x8000,
+        };
+        assert_eq!(memory.read(0x1000), 0xcc);
+
+        memory.bank = 0x4000;
+        assert_eq!(memory.read(0x1000), 0x22);
+    }
+
+    // Draws a frame with the display mode, where the first cell has its screen
+    // and colour RAM set, and the character set and bitmap start with data
+    fn draw_mode(control_1: u8, control_2: u8, screen: u8, color: u8, data: u8) -> VicII {
+        let mut ram = vec![0x00; 0x10000];
+        let mut color_ram = vec![0x00; 0x400];
+        ram[0x0400] = screen;
+        color_ram[0] = color;
+
+        // The character set at $2000, and the bitmap at $2000 as well
+        ram[0x2000 + screen as usize * 8] = data;
+        ram[0x2000 + (screen & 0x3f) as usize * 8] = data;
+        ram[0x2000] = data;
+
+        let memory = VicMemory {
+            ram: &ram,
+            chargen: None,
+            color_ram: &color_ram,
+            bank: 0x0000,
+        };
+
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, control_1);
+        vic.write(0xd016, control_2);
+        vic.write(0xd018, 0x18);
+        vic.write(0xd021, 0x01);
+        vic.write(0xd022, 0x02);
+        vic.write(0xd023, 0x03);
+        vic.write(0xd024, 0x04);
+        draw_frame(&mut vic, &memory);
+        vic
+    }
+
+    // The first 8 pixels drawn
+    fn first_pixels(vic: &VicII) -> Vec<u8> {
+        (0..8).map(|x| pixel_at(vic, 24 + x, 51)).collect()
+    }
+
+    #[test]
+    fn should_draw_multicolor_text_mode() {
+        let vic = draw_mode(0x1b, 0x18, 0x05, 0x0d, 0b00_01_10_11);
+        assert_eq!(first_pixels(&vic), [1, 1, 2, 2, 3, 3, 5, 5]);
+
+        // Without bit 3 of the colour, the character is drawn in hires
+        let vic = draw_mode(0x1b, 0x18, 0x05, 0x0d & 0x07, 0b00_01_10_11);
+        assert_eq!(first_pixels(&vic), [1, 1, 1, 5, 5, 1, 5, 5]);
+    }
+
+    #[test]
+    fn should_draw_bitmap_mode() {
+        let vic = draw_mode(0x3b, 0x08, 0x7e, 0x00, 0b1100_0011);
+        assert_eq!(first_pixels(&vic), [7, 7, 14, 14, 14, 14, 7, 7]);
+    }
+
+    #[test]
+    fn should_draw_multicolor_bitmap_mode() {
+        let vic = draw_mode(0x3b, 0x18, 0x7e, 0x09, 0b00_01_10_11);
+        assert_eq!(first_pixels(&vic), [1, 1, 7, 7, 14, 14, 9, 9]);
+    }
+
+    #[test]
+    fn should_draw_extended_background_color_mode() {
+        // The top two bits of the screen code select the background colour
+        let vic = draw_mode(0x5b, 0x08, 0b11_000101, 0x05, 0b1111_0000);
+        assert_eq!(first_pixels(&vic), [5, 5, 5, 5, 4, 4, 4, 4]);
     }
 
     #[test]
-    fn can_construct_color_from_string() {
-        let c = Color::from_str("#345678").unwrap();
-        assert_eq!(c.r, 0x34);
-        assert_eq!(c.g, 0x56);
-        assert_eq!(c.b, 0x78);
+    fn should_draw_invalid_modes_in_black() {
+        for (control_1, control_2) in [(0x5b, 0x18), (0x7b, 0x08), (0x7b, 0x18)] {
+            let vic = draw_mode(control_1, control_2, 0x05, 0x0d, 0b1010_0101);
+            assert_eq!(first_pixels(&vic), [0; 8]);
+        }
+    }
+
+    // The cycles of the line where BA is low
+    fn ba_low_cycles(vic: &mut VicII, line: u16) -> Vec<u16> {
+        while vic.raster_line() != line {
+            vic.tick(&no_memory());
+        }
+
+        let mut cycles = vec![];
+        while vic.raster_line() == line {
+            if vic.ba_low() {
+                cycles.push(vic.cycle());
+            }
+            vic.tick(&no_memory());
+        }
+        cycles
     }
 
     #[test]
-    fn should_return_err_if_decimal() {
-        let c = Color::from_str("345678");
-        assert!(c.is_err())
+    fn should_pull_ba_low_on_bad_lines() {
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, 0x1b);
+
+        assert_eq!(ba_low_cycles(&mut vic, 0x33), (12..=54).collect::<Vec<_>>());
+        assert_eq!(ba_low_cycles(&mut vic, 0x34), vec![]);
+        assert_eq!(ba_low_cycles(&mut vic, 0x3b), (12..=54).collect::<Vec<_>>());
+
+        // Changing YSCROLL moves the bad lines
+        vic.write(0xd011, 0x1c);
+        assert_eq!(ba_low_cycles(&mut vic, 0x43), vec![]);
+        assert_eq!(ba_low_cycles(&mut vic, 0x44), (12..=54).collect::<Vec<_>>());
+
+        // None below the last row
+        assert_eq!(ba_low_cycles(&mut vic, 0xfc), vec![]);
+    }
+
+    #[test]
+    fn should_only_have_bad_lines_when_den_was_set() {
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, 0x0b);
+        assert_eq!(ba_low_cycles(&mut vic, 0x33), vec![]);
+
+        // Setting DEN after line $30 is too late for this frame
+        vic.write(0xd011, 0x1b);
+        assert_eq!(ba_low_cycles(&mut vic, 0x3b), vec![]);
+        assert_eq!(ba_low_cycles(&mut vic, 0x10), vec![]);
+        assert_eq!(ba_low_cycles(&mut vic, 0x33), (12..=54).collect::<Vec<_>>());
     }
 }
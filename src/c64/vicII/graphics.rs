@@ -0,0 +1,341 @@
+//! The graphics are drawn by a small state machine, which is what all the raster
+//! tricks depend on, so it's modelled the same way as in the chip, following
+//! "The MOS 6567/6569 video controller (VIC-II) and its application in the
+//! Commodore 64" by Christian Bauer.
+//!
+//! - VC is the index into the video matrix of the cell being drawn, and VCBASE
+//!   is where it starts over on every line of a row of text. VCBASE is cleared
+//!   on line 0.
+//! - RC is the line within the row, and VMLI the index into the internal buffer
+//!   of 40 screen codes and colours.
+//! - In cycle 14, VC is loaded from VCBASE and VMLI is cleared. On a bad line,
+//!   RC is cleared as well.
+//! - On a bad line, the screen codes and colours of the row are read into the
+//!   buffer in cycles 15-54, the c-accesses. BA is pulled low 3 cycles before,
+//!   and if the bad line starts later than that, the bus still belongs to the
+//!   CPU for the first few of them, which reads as $ff.
+//! - The graphics data is read in cycles 16-55, the g-accesses. In display
+//!   state, it's read from the character set or bitmap, after which VC and VMLI
+//!   are incremented. In idle state, it's always read from $3fff ($39ff with
+//!   ECM), and drawn as if the screen code and colour were 0.
+//! - A bad line switches to display state, at any cycle. In cycle 58, if RC is
+//!   7, VCBASE is loaded from VC and the state switches to idle. If still in
+//!   display state after that, RC is incremented.
+//!
+//! Every g-access is drawn 8 pixels later, shifted by XSCROLL, and the colours
+//! are looked up when the pixels are drawn, so that changing them in the middle
+//! of a line takes effect at once.
+
+use super::*;
+
+// The cycles of the c-accesses on bad lines, and of the g-accesses
+const C_ACCESSES: RangeInclusive<u16> = 15..=54;
+const G_ACCESSES: RangeInclusive<u16> = 16..=55;
+
+// The address of the g-accesses in idle state
+const IDLE_ADDRESS: u16 = 0x3fff;
+
+/// A pixel of graphics, as read by a g-access, waiting to be drawn
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct Graphics {
+    mode: u8,
+    bits: u8,
+    data: u8,
+    color: u8,
+}
+
+impl VicII {
+    // The display mode, by its ECM/BMM/MCM bits
+    fn mode(&self) -> u8 {
+        (self.registers[CONTROL_1] & (ECM | BMM)) | (self.registers[CONTROL_2] & MCM)
+    }
+
+    pub(super) fn graphics_cycle(&mut self, memory: &VicMemory) {
+        let bad_line = self.bad_line();
+        if bad_line {
+            self.display_state = true;
+        }
+
+        // The number of cycles BA has been low for the bad line, where the
+        // CPU can still use the bus for the first 3
+        self.bad_line_ba = match bad_line && BAD_LINE_BA.contains(&self.cycle) {
+            true => self.bad_line_ba + 1,
+            false => 0,
+        };
+
+        match self.cycle {
+            1 => {
+                if self.raster_line == 0 {
+                    self.vc_base = 0;
+                }
+                let background = Graphics {
+                    mode: self.mode(),
+                    ..Graphics::default()
+                };
+                self.graphics.fill(background);
+            }
+            14 => {
+                self.vc = self.vc_base;
+                self.vmli = 0;
+                if bad_line {
+                    self.rc = 0;
+                }
+            }
+            58 => {
+                if self.rc == 7 {
+                    self.vc_base = self.vc;
+                    self.display_state = bad_line;
+                }
+                if self.display_state {
+                    self.rc = (self.rc + 1) & 0x07;
+                }
+            }
+            _ => {}
+        }
+
+        if G_ACCESSES.contains(&self.cycle) {
+            self.g_access(memory);
+        }
+        if bad_line && C_ACCESSES.contains(&self.cycle) {
+            self.c_access(memory);
+        }
+    }
+
+    // Reads the screen code and colour of the cell at VC into the buffer
+    fn c_access(&mut self, memory: &VicMemory) {
+        self.video_matrix[self.vmli] = match self.bad_line_ba > 3 {
+            true => (
+                memory.read(self.screen_address() + self.vc),
+                memory.color(self.vc),
+            ),
+            false => (0xff, 0x0f),
+        };
+    }
+
+    // Reads the graphics data for the next 8 pixels, and shifts them into the
+    // line by XSCROLL
+    fn g_access(&mut self, memory: &VicMemory) {
+        let mode = self.mode();
+
+        let (data, color, address) = match self.display_state {
+            true => {
+                let (data, color) = self.video_matrix[self.vmli];
+                let address = match mode & BMM {
+                    0 => self.charset_address() | (data as u16) << 3 | self.rc as u16,
+                    _ => self.bitmap_address() | self.vc << 3 | self.rc as u16,
+                };
+                self.vc = (self.vc + 1) & 0x3ff;
+                self.vmli += 1;
+                (data, color, address)
+            }
+            false => (0x00, 0x00, IDLE_ADDRESS),
+        };
+
+        // ECM holds address lines 9 and 10 low, which limits the text to the
+        // first 64 characters
+        let pixels = match mode & ECM {
+            0 => memory.read(address),
+            _ => memory.read(address & 0xf9ff),
+        };
+
+        let multicolor = multicolor(mode, color);
+        let first = self.cycle as usize * 8 + (self.registers[CONTROL_2] & 0x07) as usize;
+
+        for (i, graphics) in self.graphics[first..first + 8].iter_mut().enumerate() {
+            // Multicolor pixels are twice as wide, two bits for each
+            let bits = match multicolor {
+                true => pixels >> (6 - i / 2 * 2) & 0x03,
+                false => (pixels >> (7 - i) & 0x01) << 1,
+            };
+            *graphics = Graphics {
+                mode,
+                bits,
+                data,
+                color,
+            };
+        }
+    }
+
+    // The colour of a pixel of graphics, and whether it's in the foreground,
+    // which sprites can be put behind.
+    pub(super) fn graphics_color(&self, graphics: Graphics) -> (u8, bool) {
+        let Graphics {
+            mode,
+            bits,
+            data,
+            color,
+        } = graphics;
+
+        let color = match (mode, bits) {
+            // The invalid combinations only draw black
+            (ECM_MCM | ECM_BMM | ECM_BMM_MCM, _) => 0x00,
+
+            (BMM, 0b00) => data & 0x0f,
+            (BMM, _) => data >> 4,
+
+            (BMM_MCM, 0b00) => self.background_colors(0),
+            (BMM_MCM, 0b01) => data >> 4,
+            (BMM_MCM, 0b10) => data & 0x0f,
+            (BMM_MCM, _) => color,
+
+            (ECM, 0b00) => self.background_colors(data >> 6),
+
+            (_, 0b00) => self.background_color(),
+            (_, 0b01) => self.background_colors(1),
+            (_, 0b10) if multicolor(mode, color) => self.background_colors(2),
+            (MCM, _) => color & 0x07,
+            (_, _) => color,
+        };
+
+        (color, bits & 0b10 != 0)
+    }
+}
+
+// Whether the graphics are drawn with two bits per pixel, which in
+// multicolor text mode is up to bit 3 of the colour of each cell
+fn multicolor(mode: u8, color: u8) -> bool {
+    mode & MCM != 0 && (mode & BMM != 0 || color & 0x08 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets up a screen at $0400 and a character set at $2000, where the first
+    // line of character 1 is filled in white, on a blue background
+    fn memory(setup: impl Fn(&mut Vec<u8>)) -> (Vec<u8>, Vec<u8>) {
+        let mut ram = vec![0x00; 0x10000];
+        ram[0x2008] = 0xff;
+        setup(&mut ram);
+        (ram, vec![0x01; 0x400])
+    }
+
+    fn vic() -> VicII {
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, 0x1b);
+        vic.write(0xd016, 0x08);
+        vic.write(0xd018, 0x18);
+        vic.write(0xd021, 0x06);
+        vic
+    }
+
+    // Runs until the cycle of the line is the next one to be drawn
+    fn run_until(vic: &mut VicII, memory: &VicMemory, line: u16, cycle: u16) {
+        while vic.raster_line() != line || vic.cycle() != cycle {
+            vic.tick(memory);
+        }
+    }
+
+    fn pixel_at(vic: &VicII, x: i32, line: usize) -> u8 {
+        vic.frame()[line * vic.width() + (x + X_OFFSET) as usize]
+    }
+
+    fn memory_of<'a>(ram: &'a [u8], color_ram: &'a [u8]) -> VicMemory<'a> {
+        VicMemory {
+            ram,
+            chargen: None,
+            color_ram,
+            bank: 0x0000,
+        }
+    }
+
+    #[test]
+    fn should_draw_idle_state_from_3fff() {
+        let (ram, color_ram) = memory(|ram| ram[0x3fff] = 0x0f);
+        let memory = memory_of(&ram, &color_ram);
+
+        // Scrolled down, the lines above the first row are in idle state
+        let mut vic = vic();
+        vic.write(0xd011, 0x1f);
+        run_until(&mut vic, &memory, 0x38, 1);
+
+        assert_eq!(pixel_at(&vic, 24 + 3, 0x33), 0x06);
+        assert_eq!(pixel_at(&vic, 24 + 4, 0x33), 0x00);
+    }
+
+    #[test]
+    fn should_push_rows_down_with_fld() {
+        let (ram, color_ram) = memory(|ram| ram[0x0400] = 0x01);
+        let memory = memory_of(&ram, &color_ram);
+        let mut vic = vic();
+
+        // Keep YSCROLL one line ahead of the raster, so that no bad line
+        // happens until it's put back
+        for line in 0x30..0x3b {
+            run_until(&mut vic, &memory, line, 1);
+            vic.write(0xd011, 0x18 | ((line + 1) & 0x07) as u8);
+        }
+        run_until(&mut vic, &memory, 0x3b, 1);
+        vic.write(0xd011, 0x1b);
+        run_until(&mut vic, &memory, 0x40, 1);
+
+        assert_eq!(pixel_at(&vic, 24, 0x33), 0x06);
+        assert_eq!(pixel_at(&vic, 24, 0x3b), 0x01);
+    }
+
+    #[test]
+    fn should_read_new_screen_codes_every_line_with_fli() {
+        let (ram, color_ram) = memory(|ram| {
+            ram[0x0400] = 0x01;
+            ram[0x0800] = 0x02;
+            ram[0x2010..0x2018].fill(0x00);
+            ram[0x2008..0x2010].fill(0xff);
+        });
+        let memory = memory_of(&ram, &color_ram);
+        let mut vic = vic();
+
+        // A bad line on every line, with the screen moving between $0400
+        // and $0800
+        for line in 0x33..0x37 {
+            run_until(&mut vic, &memory, line, 1);
+            vic.write(0xd011, 0x18 | (line & 0x07) as u8);
+            vic.write(0xd018, if line % 2 == 0 { 0x28 } else { 0x18 });
+        }
+        run_until(&mut vic, &memory, 0x40, 1);
+
+        assert_eq!(pixel_at(&vic, 24, 0x33), 0x01);
+        assert_eq!(pixel_at(&vic, 24, 0x34), 0x06);
+        assert_eq!(pixel_at(&vic, 24, 0x35), 0x01);
+        assert_eq!(pixel_at(&vic, 24, 0x36), 0x06);
+    }
+
+    #[test]
+    fn should_skip_rows_with_linecrunch() {
+        let (ram, color_ram) = memory(|ram| ram[0x0400 + 80] = 0x01);
+        let memory = memory_of(&ram, &color_ram);
+        let mut vic = vic();
+
+        // Cancel the bad line of the second row before cycle 14, which
+        // leaves RC at 7, so the row ends after a single line
+        run_until(&mut vic, &memory, 0x3b, 13);
+        vic.write(0xd011, 0x1c);
+        run_until(&mut vic, &memory, 0x48, 1);
+
+        // The third row follows on the next line
+        assert_eq!(pixel_at(&vic, 24, 0x3c), 0x01);
+    }
+
+    #[test]
+    fn should_shift_screen_with_vsp() {
+        let (ram, color_ram) = memory(|ram| {
+            ram[0x0400 + 36] = 0x01;
+            ram[0x0400 + 40] = 0x01;
+        });
+        let memory = memory_of(&ram, &color_ram);
+        let mut vic = vic();
+
+        // A bad line starting in cycle 20 of line $30, in the second frame
+        // when RC is left at 7 from the last row of the first one
+        run_until(&mut vic, &memory, 0x31, 1);
+        run_until(&mut vic, &memory, 0x30, 20);
+        vic.write(0xd011, 0x18);
+        run_until(&mut vic, &memory, 0x31, 1);
+        vic.write(0xd011, 0x1b);
+        run_until(&mut vic, &memory, 0x40, 1);
+
+        // Only 36 cells were read, so the rows start 4 cells early
+        assert_eq!(pixel_at(&vic, 24, 0x33), 0x01);
+        assert_eq!(pixel_at(&vic, 24 + 8, 0x33), 0x06);
+        assert_eq!(pixel_at(&vic, 24 + 4 * 8, 0x33), 0x01);
+    }
+}
@@ -0,0 +1,231 @@
+//! A PAL TV doesn't show the picture the VIC-II draws pixel by pixel. The colour
+//! is sent as a separate chroma signal with much less bandwidth than the
+//! luminance, and PAL averages the chroma of each line with the line before it,
+//! through a delay line, to cancel out phase errors. Graphics artists rely on
+//! both:
+//!
+//! - Colours with the same luminance next to each other blend into a new one,
+//!   as the chroma is blurred horizontally while the luminance stays sharp.
+//! - Alternating lines of two colours blend vertically into a third one.
+//!
+//! The filter works on the RGB framebuffer, entirely on the CPU, by converting
+//! every pixel to YUV, blurring U and V along the line and averaging them with
+//! the line above, and converting back. On top of that, scanlines can be drawn
+//! by doubling every line and darkening the copy, like the gaps between the
+//! lines on a TV.
+
+/// A simulation of how a PAL TV shows the picture
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PalFilter {
+    /// How many pixels to each side the chroma is spread over, 0 for none
+    pub chroma_blur: usize,
+    /// Whether the chroma of every line is averaged with the line above
+    pub delay_line: bool,
+    /// How much darker the gaps between the lines are, from 0.0 for no
+    /// scanlines to 1.0 for black ones. With scanlines, every line is drawn
+    /// twice, so the picture is twice as high.
+    pub scanlines: f64,
+}
+
+impl Default for PalFilter {
+    fn default() -> Self {
+        PalFilter {
+            chroma_blur: 2,
+            delay_line: true,
+            scanlines: 0.0,
+        }
+    }
+}
+
+// A pixel in YUV, as used by PAL
+#[derive(Clone, Copy, Debug, Default)]
+struct Yuv {
+    y: f64,
+    u: f64,
+    v: f64,
+}
+
+impl Yuv {
+    fn from_rgb(rgb: &[u8]) -> Self {
+        let [r, g, b] = [rgb[0] as f64, rgb[1] as f64, rgb[2] as f64];
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        Yuv {
+            y,
+            u: 0.492 * (b - y),
+            v: 0.877 * (r - y),
+        }
+    }
+
+    fn to_rgb(self) -> [u8; 3] {
+        let Yuv { y, u, v } = self;
+        let r = y + 1.140 * v;
+        let g = y - 0.395 * u - 0.581 * v;
+        let b = y + 2.032 * u;
+        [r, g, b].map(|value| value.round().clamp(0.0, 255.0) as u8)
+    }
+}
+
+impl PalFilter {
+    /// Filters an RGB picture, three bytes per pixel, returning one that is
+    /// twice as high when drawing scanlines
+    pub fn apply(&self, rgb: &[u8], width: usize) -> Vec<u8> {
+        let mut output = Vec::with_capacity(rgb.len() * 2);
+        let mut previous: Option<Vec<Yuv>> = None;
+
+        for line in rgb.chunks_exact(width * 3) {
+            let yuv: Vec<Yuv> = line.chunks_exact(3).map(Yuv::from_rgb).collect();
+            let blurred = self.blur_chroma(&yuv);
+
+            let shown: Vec<u8> = match (&previous, self.delay_line) {
+                (Some(above), true) => blurred
+                    .iter()
+                    .zip(above)
+                    .flat_map(|(pixel, above)| {
+                        Yuv {
+                            y: pixel.y,
+                            u: (pixel.u + above.u) / 2.0,
+                            v: (pixel.v + above.v) / 2.0,
+                        }
+                        .to_rgb()
+                    })
+                    .collect(),
+                _ => blurred.iter().flat_map(|pixel| pixel.to_rgb()).collect(),
+            };
+
+            output.extend_from_slice(&shown);
+            if self.scanlines > 0.0 {
+                let brightness = 1.0 - self.scanlines.min(1.0);
+                output.extend(
+                    shown
+                        .iter()
+                        .map(|value| (*value as f64 * brightness).round() as u8),
+                );
+            }
+
+            previous = Some(blurred);
+        }
+        output
+    }
+
+    // Spreads U and V over the neighbouring pixels, leaving Y as it is, with
+    // the sums of the window kept as it moves along the line
+    fn blur_chroma(&self, line: &[Yuv]) -> Vec<Yuv> {
+        let radius = self.chroma_blur;
+        let (mut u, mut v) = (0.0, 0.0);
+        for pixel in line.iter().take(radius) {
+            u += pixel.u;
+            v += pixel.v;
+        }
+
+        let mut blurred = Vec::with_capacity(line.len());
+        for x in 0..line.len() {
+            if let Some(entering) = line.get(x + radius) {
+                u += entering.u;
+                v += entering.v;
+            }
+            if let Some(leaving) = x.checked_sub(radius + 1).map(|x| line[x]) {
+                u -= leaving.u;
+                v -= leaving.v;
+            }
+
+            let count = (x + radius).min(line.len() - 1) + 1 - x.saturating_sub(radius);
+            blurred.push(Yuv {
+                y: line[x].y,
+                u: u / count as f64,
+                v: v / count as f64,
+            });
+        }
+        blurred
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::palette::{Colors, COLODORE};
+    use super::*;
+
+    // An RGB picture from colour numbers
+    fn picture(lines: &[&[u8]]) -> Vec<u8> {
+        lines
+            .iter()
+            .flat_map(|line| line.iter())
+            .flat_map(|index| {
+                let color = COLODORE[*index];
+                [color.r, color.g, color.b]
+            })
+            .collect()
+    }
+
+    fn close(a: &[u8], b: &[u8]) -> bool {
+        a.iter().zip(b).all(|(a, b)| a.abs_diff(*b) <= 2)
+    }
+
+    #[test]
+    fn should_leave_plain_areas_alone() {
+        let red = Colors::red as u8;
+        let rgb = picture(&[&[red; 8], &[red; 8]]);
+        assert!(close(&PalFilter::default().apply(&rgb, 8), &rgb));
+    }
+
+    #[test]
+    fn should_keep_luma_sharp() {
+        let (black, white) = (Colors::black as u8, Colors::white as u8);
+        let rgb = picture(&[&[black, black, white, white, black, black]]);
+        assert!(close(&PalFilter::default().apply(&rgb, 6), &rgb));
+    }
+
+    #[test]
+    fn should_blend_colours_next_to_each_other() {
+        // Grey and light blue have the same luminance
+        let (grey, blue) = (Colors::grey as u8, Colors::light_blue as u8);
+        let rgb = picture(&[&[grey, blue, grey, blue, grey, blue]]);
+        let filtered = PalFilter {
+            delay_line: false,
+            ..PalFilter::default()
+        }
+        .apply(&rgb, 6);
+
+        // The pixels in the middle end up in between the two
+        let (g, b) = (COLODORE[Colors::grey], COLODORE[Colors::light_blue]);
+        for pixel in filtered[2 * 3..4 * 3].chunks(3) {
+            assert!(pixel[2] > g.b && pixel[2] < b.b);
+        }
+    }
+
+    #[test]
+    fn should_blend_lines_through_delay_line() {
+        let (red, green) = (Colors::red as u8, Colors::green as u8);
+        let rgb = picture(&[&[red; 4], &[green; 4], &[red; 4]]);
+        let filtered = PalFilter {
+            chroma_blur: 0,
+            ..PalFilter::default()
+        }
+        .apply(&rgb, 4);
+
+        // The first line has nothing above it, while the others get the
+        // same mix of the chroma of both, with their own luminance
+        assert!(close(&filtered[..12], &rgb[..12]));
+
+        let (second, third) = (
+            Yuv::from_rgb(&filtered[12..15]),
+            Yuv::from_rgb(&filtered[24..27]),
+        );
+        assert!((second.u - third.u).abs() < 2.0 && (second.v - third.v).abs() < 2.0);
+        assert!((second.y - Yuv::from_rgb(&rgb[12..15]).y).abs() < 2.0);
+    }
+
+    #[test]
+    fn should_draw_scanlines() {
+        let white = Colors::white as u8;
+        let rgb = picture(&[&[white; 2], &[white; 2]]);
+        let filtered = PalFilter {
+            scanlines: 0.5,
+            ..PalFilter::default()
+        }
+        .apply(&rgb, 2);
+
+        assert_eq!(filtered.len(), rgb.len() * 2);
+        assert_eq!(filtered[..6], [0xff; 6]);
+        assert_eq!(filtered[6..12], [0x80; 6]);
+    }
+}
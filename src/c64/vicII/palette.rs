@@ -0,0 +1,353 @@
+//! The VIC-II has 16 fixed colours, but as it outputs an analog luminance and
+//! chrominance signal instead of RGB, there is no single right set of values
+//! for them. What they look like depends on the revision of the chip and on the
+//! TV, so there are several palettes to choose from:
+//!
+//! - Pepto     - measured by Pepto in 2001, <https://www.pepto.de/projects/colorvic/2001/>
+//! - Colodore  - Pepto's later model from 2017, <https://www.pepto.de/projects/colorvic/>
+//! - VICE      - the palette VICE used by default for a long time
+//! - Pepto, gamma corrected - the Pepto palette converted from the gamma of
+//!   2.8 of PAL to the 2.2 of a PC display, which is a bit darker
+//!
+//! Any other look can be generated from the luminance and chroma of the chip,
+//! see colodore.rs, where the palette named "generated" uses the default knobs.
+//!
+//! VICE keeps its palettes in .vpl files, with a line for each colour holding
+//! the red, green and blue values in hex, and an optional dither value that is
+//! ignored here. Lines starting with a '#' are comments.
+
+#![allow(non_camel_case_types)]
+
+pub mod colodore;
+
+use std::fmt::Display;
+use std::ops::Index;
+use std::path::Path;
+use std::str::FromStr;
+
+use self::colodore::PaletteSettings;
+
+/// The colour numbers of the VIC-II
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colors {
+    black = 0x0,
+    white = 0x1,
+    red = 0x2,
+    cyan = 0x3,
+    purple = 0x4,
+    green = 0x5,
+    blue = 0x6,
+    yellow = 0x7,
+    orange = 0x8,
+    brown = 0x9,
+    light_red = 0xa,
+    dark_grey = 0xb,
+    grey = 0xc,
+    light_green = 0xd,
+    light_blue = 0xe,
+    light_grey = 0xf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// The RGB values of the 16 colours, indexed by colour number
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colors: [Color; 16],
+}
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(std::io::Error),
+    /// A line that isn't a comment doesn't hold three hex values
+    InvalidLine {
+        line: usize,
+    },
+    /// There should be exactly 16 colours
+    WrongCount(usize),
+    /// There is no palette by that name
+    Unknown(String),
+}
+
+impl Display for PaletteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaletteError::Io(err) => write!(f, "unable to read palette: {err}"),
+            PaletteError::InvalidLine { line } => {
+                write!(f, "line {line} should hold red, green and blue in hex")
+            }
+            PaletteError::WrongCount(count) => write!(f, "expected 16 colours, found {count}"),
+            PaletteError::Unknown(name) => write!(
+                f,
+                "unknown palette '{name}', expected pepto, colodore, vice, pepto-gamma or generated"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PaletteError {}
+
+impl From<std::io::Error> for PaletteError {
+    fn from(err: std::io::Error) -> Self {
+        PaletteError::Io(err)
+    }
+}
+
+#[rustfmt::skip]
+pub static PEPTO: Palette = Palette::hex([
+    0x000000, 0xffffff, 0x68372b, 0x70a4b2,
+    0x6f3d86, 0x588d43, 0x352879, 0xb8c76f,
+    0x6f4f25, 0x433900, 0x9a6759, 0x444444,
+    0x6c6c6c, 0x9ad284, 0x6c5eb5, 0x959595,
+]);
+
+#[rustfmt::skip]
+pub static PEPTO_GAMMA_CORRECTED: Palette = Palette::hex([
+    0x000000, 0xffffff, 0x51241a, 0x5991a1,
+    0x582970, 0x42782f, 0x231863, 0xa8ba58,
+    0x583916, 0x2f2600, 0x865043, 0x2f2f2f,
+    0x555555, 0x86c76e, 0x5548a5, 0x818181,
+]);
+
+#[rustfmt::skip]
+pub static COLODORE: Palette = Palette::hex([
+    0x000000, 0xffffff, 0x813338, 0x75cec8,
+    0x8e3c97, 0x56ac4d, 0x2e2c9b, 0xedf171,
+    0x8e5029, 0x553800, 0xc46c71, 0x4a4a4a,
+    0x7b7b7b, 0xa9ff9f, 0x706deb, 0xb2b2b2,
+]);
+
+#[rustfmt::skip]
+pub static VICE: Palette = Palette::hex([
+    0x000000, 0xfdfefc, 0xbe1a24, 0x30e6c6,
+    0xb41ae2, 0x1fd21e, 0x211bae, 0xdff60a,
+    0xb84104, 0x6a3304, 0xfe4a57, 0x424540,
+    0x70746f, 0x59fe59, 0x5f53fe, 0xa4a7a2,
+]);
+
+impl Palette {
+    const fn hex(values: [u32; 16]) -> Self {
+        let mut colors = [Color::hex(0); 16];
+        let mut i = 0;
+        while i < 16 {
+            colors[i] = Color::hex(values[i]);
+            i += 1;
+        }
+        Palette { colors }
+    }
+
+    pub fn new(colors: [Color; 16]) -> Self {
+        Palette { colors }
+    }
+
+    /// The colour of a colour number, where only the lowest 4 bits count
+    pub fn color(&self, index: u8) -> Color {
+        self.colors[(index & 0x0f) as usize]
+    }
+
+    pub fn colors(&self) -> &[Color; 16] {
+        &self.colors
+    }
+
+    /// Reads a palette in the .vpl format of VICE
+    pub fn from_vpl(text: &str) -> Result<Self, PaletteError> {
+        let mut colors = vec![];
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values: Vec<u8> = line
+                .split_whitespace()
+                .take(3)
+                .map(|value| u8::from_str_radix(value, 16))
+                .collect::<Result<_, _>>()
+                .map_err(|_| PaletteError::InvalidLine { line: index + 1 })?;
+
+            match values[..] {
+                [r, g, b] => colors.push(Color { r, g, b }),
+                _ => return Err(PaletteError::InvalidLine { line: index + 1 }),
+            }
+        }
+
+        let count = colors.len();
+        colors
+            .try_into()
+            .map(Palette::new)
+            .map_err(|_| PaletteError::WrongCount(count))
+    }
+
+    pub fn load_vpl(path: impl AsRef<Path>) -> Result<Self, PaletteError> {
+        Self::from_vpl(&std::fs::read_to_string(path)?)
+    }
+
+    /// Writes the palette in the .vpl format of VICE
+    pub fn to_vpl(&self) -> String {
+        self.colors
+            .iter()
+            .map(|Color { r, g, b }| format!("{r:02X} {g:02X} {b:02X} 0\n"))
+            .collect()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PEPTO.clone()
+    }
+}
+
+impl FromStr for Palette {
+    type Err = PaletteError;
+
+    /// Picks one of the built in palettes by name
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pepto" => Ok(PEPTO.clone()),
+            "pepto-gamma" => Ok(PEPTO_GAMMA_CORRECTED.clone()),
+            "colodore" => Ok(COLODORE.clone()),
+            "vice" => Ok(VICE.clone()),
+            "generated" => Ok(Palette::generate(&PaletteSettings::default())),
+            _ => Err(PaletteError::Unknown(s.to_string())),
+        }
+    }
+}
+
+impl Index<u8> for Palette {
+    type Output = Color;
+
+    fn index(&self, index: u8) -> &Self::Output {
+        &self.colors[(index & 0x0f) as usize]
+    }
+}
+
+impl Index<Colors> for Palette {
+    type Output = Color;
+
+    fn index(&self, color: Colors) -> &Self::Output {
+        &self[color as u8]
+    }
+}
+
+impl Color {
+    #[allow(clippy::identity_op)]
+    pub const fn hex(value: u32) -> Self {
+        let r = (value >> 0x10 & 0xff) as u8;
+        let g = (value >> 0x08 & 0xff) as u8;
+        let b = (value >> 0x00 & 0xff) as u8;
+        Color { r, g, b }
+    }
+}
+
+impl FromStr for Color {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('#') || s.starts_with("0x") {
+            let s = s.trim_start_matches('#').trim_start_matches("0x");
+            if let Ok(val) = u32::from_str_radix(s, 16) {
+                Ok(Color::hex(val))
+            } else {
+                Err(())
+            }
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl From<u32> for Color {
+    fn from(value: u32) -> Self {
+        Color::hex(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_construct_color_from_u32() {
+        let c = Color::hex(0x345678);
+        assert_eq!(c.r, 0x34);
+        assert_eq!(c.g, 0x56);
+        assert_eq!(c.b, 0x78);
+    }
+
+    #[test]
+    fn can_construct_color_from_string() {
+        let c = Color::from_str("#345678").unwrap();
+        assert_eq!(c.r, 0x34);
+        assert_eq!(c.g, 0x56);
+        assert_eq!(c.b, 0x78);
+    }
+
+    #[test]
+    fn should_return_err_if_decimal() {
+        let c = Color::from_str("345678");
+        assert!(c.is_err())
+    }
+
+    #[test]
+    fn should_look_up_colors_by_number() {
+        assert_eq!(COLODORE[Colors::red], Color::hex(0x813338));
+        assert_eq!(COLODORE[0x02], Color::hex(0x813338));
+        assert_eq!(COLODORE.color(0xf2), Color::hex(0x813338));
+        assert_eq!(PEPTO[Colors::light_grey], Color::hex(0x959595));
+    }
+
+    #[test]
+    fn should_darken_gamma_corrected_palette() {
+        assert_ne!(PEPTO, PEPTO_GAMMA_CORRECTED);
+        assert_eq!(PEPTO_GAMMA_CORRECTED[Colors::white], PEPTO[Colors::white]);
+        assert!(PEPTO_GAMMA_CORRECTED[Colors::grey].r < PEPTO[Colors::grey].r);
+    }
+
+    #[test]
+    fn should_read_vpl() {
+        let vpl = format!(
+            "# VICE Palette file\n#\n\n# Black\n00 00 00 0\n{}",
+            "FF 80 01 4\n".repeat(15)
+        );
+        let palette = Palette::from_vpl(&vpl).unwrap();
+        assert_eq!(palette[Colors::black], Color::hex(0x000000));
+        assert_eq!(palette[Colors::white], Color::hex(0xff8001));
+
+        assert_eq!(Palette::from_vpl(&VICE.to_vpl()).unwrap(), VICE);
+    }
+
+    #[test]
+    fn should_reject_invalid_vpl() {
+        assert!(matches!(
+            Palette::from_vpl("00 00 00\n00 00\n"),
+            Err(PaletteError::InvalidLine { line: 2 })
+        ));
+        assert!(matches!(
+            Palette::from_vpl("00 00 zz\n"),
+            Err(PaletteError::InvalidLine { line: 1 })
+        ));
+        assert!(matches!(
+            Palette::from_vpl("00 00 00\n"),
+            Err(PaletteError::WrongCount(1))
+        ));
+    }
+
+    #[test]
+    fn should_pick_palette_by_name() {
+        assert_eq!("Colodore".parse::<Palette>().unwrap(), COLODORE);
+        assert_eq!(
+            "generated".parse::<Palette>().unwrap(),
+            Palette::generate(&PaletteSettings::default())
+        );
+        assert!(matches!(
+            "ntsc".parse::<Palette>(),
+            Err(PaletteError::Unknown(_))
+        ));
+    }
+}
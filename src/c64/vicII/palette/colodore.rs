@@ -0,0 +1,163 @@
+//! Instead of measuring the colours off a screen, Pepto's Colodore model
+//! computes them from what the VIC-II actually outputs, a luminance level and
+//! a chroma angle for each colour:
+//!
+//! - The luminance levels are multiples of 1/32 of the full range. The first
+//!   revision of the 6569 only had 5 different levels, while the later ones
+//!   have 9.
+//! - The chroma angles are multiples of 22.5 degrees, offset by half of that,
+//!   and the greys have no chroma at all.
+//!
+//! The luminance and chroma are turned into YUV, adjusted by the brightness,
+//! contrast and saturation, as on a TV, converted to RGB and gamma corrected
+//! from the 2.8 of PAL to the gamma of the display.
+//!
+//! With the default settings, this gives exactly the Colodore palette.
+
+use super::{Color, Palette};
+
+// The luminance levels of the colours, out of 32
+#[rustfmt::skip]
+const FIRST_REVISION_LUMA: [u8; 16] = [0, 32, 8, 24, 16, 16, 8, 24, 16, 8, 16, 8, 16, 24, 16, 24];
+#[rustfmt::skip]
+const LATER_REVISION_LUMA: [u8; 16] = [0, 32, 10, 20, 12, 16, 8, 24, 12, 8, 16, 10, 15, 24, 15, 20];
+
+// The chroma angles of the colours, in sectors of 22.5 degrees, where 0
+// means no chroma
+#[rustfmt::skip]
+const CHROMA_ANGLES: [u8; 16] = [0, 0, 4, 12, 2, 10, 15, 7, 5, 6, 4, 0, 0, 10, 15, 0];
+
+// The gamma of a PAL TV
+const SOURCE_GAMMA: f64 = 2.8;
+
+// How much of the picture a TV adds on its own, on top of the contrast
+const SCREEN: f64 = 1.0 / 5.0;
+
+/// The luminance levels of the VIC-II, which changed after the first revision
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LumaRevision {
+    /// The first 6569, with only 5 levels
+    First,
+    #[default]
+    Later,
+}
+
+/// The knobs of the TV the palette is generated for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaletteSettings {
+    /// 0-100, where 50 leaves the luminance as it is
+    pub brightness: f64,
+    /// 0-100
+    pub contrast: f64,
+    /// 0-100
+    pub saturation: f64,
+    /// The gamma of the display, 2.2 for most PC displays
+    pub gamma: f64,
+    pub revision: LumaRevision,
+}
+
+impl Default for PaletteSettings {
+    fn default() -> Self {
+        PaletteSettings {
+            brightness: 50.0,
+            contrast: 100.0,
+            saturation: 50.0,
+            gamma: 2.2,
+            revision: LumaRevision::Later,
+        }
+    }
+}
+
+impl Palette {
+    /// Computes the colours from the luminance and chroma of the VIC-II
+    pub fn generate(settings: &PaletteSettings) -> Self {
+        let colors = std::array::from_fn(|index| settings.color(index));
+        Palette::new(colors)
+    }
+}
+
+impl PaletteSettings {
+    fn color(&self, index: usize) -> Color {
+        let luma = match self.revision {
+            LumaRevision::First => FIRST_REVISION_LUMA[index],
+            LumaRevision::Later => LATER_REVISION_LUMA[index],
+        };
+        let y = luma as f64 * 8.0 + self.brightness - 50.0;
+
+        let (u, v) = match CHROMA_ANGLES[index] {
+            0 => (0.0, 0.0),
+            sector => {
+                let angle = (11.25 + sector as f64 * 22.5).to_radians();
+                let saturation = self.saturation * (1.0 - SCREEN);
+                (angle.cos() * saturation, angle.sin() * saturation)
+            }
+        };
+
+        let contrast = self.contrast / 100.0 + SCREEN;
+        let (y, u, v) = (y * contrast, u * contrast, v * contrast);
+
+        Color {
+            r: self.gamma_correct(y + 1.140 * v),
+            g: self.gamma_correct(y - 0.396 * u - 0.581 * v),
+            b: self.gamma_correct(y + 2.029 * u),
+        }
+    }
+
+    fn gamma_correct(&self, value: f64) -> u8 {
+        let value = value.clamp(0.0, 255.0) / 255.0;
+        (value.powf(SOURCE_GAMMA / self.gamma) * 255.0).round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Colors, COLODORE};
+    use super::*;
+
+    #[test]
+    fn should_generate_colodore_by_default() {
+        assert_eq!(Palette::generate(&PaletteSettings::default()), COLODORE);
+    }
+
+    #[test]
+    fn should_only_have_5_luma_levels_in_first_revision() {
+        let palette = Palette::generate(&PaletteSettings {
+            saturation: 0.0,
+            revision: LumaRevision::First,
+            ..PaletteSettings::default()
+        });
+
+        let mut levels: Vec<u8> = palette.colors().iter().map(|color| color.r).collect();
+        levels.sort();
+        levels.dedup();
+        assert_eq!(levels.len(), 5);
+
+        // Dark grey and blue are the same
+        assert_eq!(palette[Colors::dark_grey], palette[Colors::blue]);
+    }
+
+    #[test]
+    fn should_adjust_like_a_tv() {
+        let default = Palette::generate(&PaletteSettings::default());
+
+        let brighter = Palette::generate(&PaletteSettings {
+            brightness: 60.0,
+            ..PaletteSettings::default()
+        });
+        assert!(brighter[Colors::grey].r > default[Colors::grey].r);
+
+        let grey = Palette::generate(&PaletteSettings {
+            saturation: 0.0,
+            ..PaletteSettings::default()
+        });
+        let Color { r, g, b } = grey[Colors::red];
+        assert!(r == g && g == b);
+
+        // A display with the gamma of PAL needs no correction
+        let uncorrected = Palette::generate(&PaletteSettings {
+            gamma: 2.8,
+            ..PaletteSettings::default()
+        });
+        assert_eq!(uncorrected[Colors::grey], Color::hex(0x909090));
+    }
+}
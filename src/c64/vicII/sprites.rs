@@ -0,0 +1,475 @@
+//! The VIC-II has 8 sprites of 24x21 pixels, or 12x21 in multicolor, that can
+//! be expanded to twice the width and/or height. Each of them has a pointer in
+//! the last 8 bytes of the video matrix, to the 64 byte block of its data.
+//!
+//! At the end of each line, the sprites to show on the next line are checked
+//! and their 3 bytes of data for it are fetched, two cycles per sprite. Which
+//! row of the data to fetch is kept in the MC counter, loaded from MCBASE at
+//! the start of the fetches, and MCBASE is only moved on to the next row at the
+//! start of the line when the Y expansion flip-flop is set. The flip-flop is
+//! toggled every line for sprites expanded in Y, which is why each row is shown
+//! twice for them.
+//!
+//! Sprites with a lower number are drawn on top of those with a higher number,
+//! and each sprite can be put behind the foreground of the graphics in $d01b,
+//! where the multicolor pixels %01 count as the background.
+//!
+//! Any overlapping pixels of two sprites set their bits in $d01e, and a sprite
+//! pixel on top of the foreground sets its bit in $d01f. The interrupts are only
+//! triggered by the first collision after the registers have been read.
+
+use super::{VicII, VicMemory, IRQ_SPRITE_BACKGROUND, IRQ_SPRITE_SPRITE, X_OFFSET};
+
+const SPRITE_X_MSB: usize = 0x10;
+const SPRITE_ENABLE: usize = 0x15;
+const SPRITE_PRIORITY: usize = 0x1b;
+const SPRITE_MULTICOLOR: usize = 0x1c;
+const SPRITE_X_EXPAND: usize = 0x1d;
+const SPRITE_MULTICOLOR_0: usize = 0x25;
+const SPRITE_MULTICOLOR_1: usize = 0x26;
+const SPRITE_COLOR: usize = 0x27;
+
+// The pointers are in the last 8 bytes of the video matrix
+const POINTERS: u16 = 0x03f8;
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Sprite {
+    // Whether the data is being fetched, and whether it's shown on the line
+    dma: bool,
+    display: bool,
+
+    // The offsets of the data to fetch, MCBASE and MC
+    mc_base: u8,
+    mc: u8,
+
+    // The Y expansion flip-flop, the data only moves on to the next row when set
+    expand: bool,
+
+    // The 24 pixels of the line
+    data: u32,
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Sprite {
+            dma: false,
+            display: false,
+            mc_base: 0,
+            mc: 0,
+            expand: true,
+            data: 0,
+        }
+    }
+}
+
+impl VicII {
+    // Runs the parts of the sprite logic that happens at the current cycle
+    pub(super) fn sprite_cycle(&mut self, memory: &VicMemory) {
+        let cycles = self.timing.cycles_per_line;
+        let cycle = self.cycle;
+        let line = self.raster_line as u8;
+        let enabled = self.registers[SPRITE_ENABLE];
+        let y_expand = self.registers[super::SPRITE_Y_EXPAND];
+
+        for n in 0..8 {
+            let bit = 1 << n;
+            let y = self.registers[n * 2 + 1];
+            let sprite = &mut self.sprites[n];
+
+            match cycle {
+                15 if sprite.dma && sprite.expand => sprite.mc_base = (sprite.mc_base + 2) & 0x3f,
+                16 if sprite.dma && sprite.expand => {
+                    sprite.mc_base = (sprite.mc_base + 1) & 0x3f;
+                    if sprite.mc_base == 63 {
+                        sprite.dma = false;
+                    }
+                }
+                _ if cycle == cycles - 8 => {
+                    if y_expand & bit != 0 {
+                        sprite.expand = !sprite.expand;
+                    }
+                    if enabled & bit != 0 && y == line && !sprite.dma {
+                        sprite.dma = true;
+                        sprite.mc_base = 0;
+                        if y_expand & bit != 0 {
+                            sprite.expand = false;
+                        }
+                    }
+                }
+                _ if cycle == cycles - 5 => {
+                    sprite.mc = sprite.mc_base;
+                    if !sprite.dma {
+                        sprite.display = false;
+                    } else if y == line {
+                        sprite.display = true;
+                    }
+                }
+                _ => {}
+            }
+
+            if cycle == self.fetch_cycle(n) {
+                self.fetch_sprite(memory, n);
+            }
+        }
+    }
+
+    // Two cycles for each sprite, from sprite 0 at 5 cycles before the end of
+    // the line, into the start of the next line
+    fn fetch_cycle(&self, n: usize) -> u16 {
+        let cycles = self.timing.cycles_per_line;
+        (cycles - 6 + n as u16 * 2) % cycles + 1
+    }
+
+    // Whether any sprite is about to fetch its data, from 3 cycles before the
+    // fetch until it's done
+    pub(super) fn sprite_ba_low(&self) -> bool {
+        let cycles = self.timing.cycles_per_line;
+        let check = self.cycle == cycles - 8;
+
+        (0..8).any(|n| {
+            let sprite = &self.sprites[n];
+
+            // The DMA is turned on during the same cycle BA is pulled low for sprite 0
+            let y = self.registers[n * 2 + 1];
+            let starting =
+                check && self.registers[SPRITE_ENABLE] & 1 << n != 0 && y == self.raster_line as u8;
+
+            let start = (self.fetch_cycle(n) + cycles - 4) % cycles;
+            let since = (self.cycle - 1 + cycles - start) % cycles;
+            (sprite.dma || starting) && since < 5
+        })
+    }
+
+    fn fetch_sprite(&mut self, memory: &VicMemory, n: usize) {
+        let pointer = memory.read(self.screen_address() + POINTERS + n as u16) as u16 * 64;
+        let sprite = &mut self.sprites[n];
+
+        sprite.data = 0;
+        if !sprite.dma {
+            return;
+        }
+
+        for _ in 0..3 {
+            sprite.data = sprite.data << 8 | memory.read(pointer + sprite.mc as u16) as u32;
+            sprite.mc = (sprite.mc + 1) & 0x3f;
+        }
+    }
+
+    // Clearing the Y expansion of a sprite sets its flip-flop
+    pub(super) fn sprite_y_expand_written(&mut self, value: u8) {
+        for (n, sprite) in self.sprites.iter_mut().enumerate() {
+            if value & 1 << n == 0 {
+                sprite.expand = true;
+            }
+        }
+    }
+
+    // The colour of the sprite on top at the column of the current line, and
+    // whether it's behind the foreground, along with the bits of every sprite
+    // that has a pixel there.
+    pub(super) fn sprite_pixel(&self, column: usize) -> (Option<(u8, bool)>, u8) {
+        let width = self.width();
+        let mut top = None;
+        let mut sprites = 0;
+
+        for (n, sprite) in self.sprites.iter().enumerate().rev() {
+            if !sprite.display {
+                continue;
+            }
+
+            let bit = 1 << n;
+            let x = self.registers[n * 2] as usize
+                | ((self.registers[SPRITE_X_MSB] & bit) as usize) << (8 - n);
+            if x >= width {
+                continue;
+            }
+
+            let start = (x + X_OFFSET as usize) % width;
+            let Some(offset) = column.checked_sub(start) else {
+                continue;
+            };
+            let pixel = match self.registers[SPRITE_X_EXPAND] & bit {
+                0 => offset,
+                _ => offset / 2,
+            };
+            if pixel >= 24 {
+                continue;
+            }
+
+            let color = match self.registers[SPRITE_MULTICOLOR] & bit {
+                0 => match sprite.data >> (23 - pixel) & 0x01 {
+                    0 => None,
+                    _ => Some(self.registers[SPRITE_COLOR + n]),
+                },
+                _ => match sprite.data >> (22 - pixel / 2 * 2) & 0x03 {
+                    0b00 => None,
+                    0b01 => Some(self.registers[SPRITE_MULTICOLOR_0]),
+                    0b10 => Some(self.registers[SPRITE_COLOR + n]),
+                    _ => Some(self.registers[SPRITE_MULTICOLOR_1]),
+                },
+            };
+
+            if let Some(color) = color {
+                let behind = self.registers[SPRITE_PRIORITY] & bit != 0;
+                top = Some((color & 0x0f, behind));
+                sprites |= bit;
+            }
+        }
+        (top, sprites)
+    }
+
+    pub(super) fn collide(&mut self, sprites: u8, foreground: bool) {
+        if sprites.count_ones() > 1 {
+            if self.sprite_collisions == 0 {
+                self.interrupts |= IRQ_SPRITE_SPRITE;
+            }
+            self.sprite_collisions |= sprites;
+        }
+
+        if sprites != 0 && foreground {
+            if self.background_collisions == 0 {
+                self.interrupts |= IRQ_SPRITE_BACKGROUND;
+            }
+            self.background_collisions |= sprites;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Timing;
+    use super::*;
+
+    // A frame with sprite 0 at the position, with its data at $0800 where the
+    // first row is filled, and every pixel of the rest has the colour %10.
+    fn draw(setup: impl Fn(&mut VicII, &mut Vec<u8>)) -> VicII {
+        let mut ram = vec![0x00; 0x10000];
+        let color_ram = vec![0x00; 0x400];
+        ram[0x07f8] = 0x20;
+        ram[0x0800..0x0803].fill(0xff);
+        ram[0x0803..0x083f].fill(0xaa);
+
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd011, 0x1b);
+        vic.write(0xd016, 0x08);
+        vic.write(0xd018, 0x14);
+        vic.write(0xd015, 0x01);
+        vic.write(0xd027, 0x07);
+        setup(&mut vic, &mut ram);
+
+        let memory = VicMemory {
+            ram: &ram,
+            chargen: None,
+            color_ram: &color_ram,
+            bank: 0x0000,
+        };
+
+        // The sprites are set up during the first frame
+        for _ in 0..2 {
+            let frame = vic.frames();
+            while vic.frames() == frame {
+                vic.tick(&memory);
+            }
+        }
+        vic
+    }
+
+    fn pixel_at(vic: &VicII, x: i32, line: usize) -> u8 {
+        vic.frame()[line * vic.width() + (x + X_OFFSET) as usize]
+    }
+
+    #[test]
+    fn should_draw_sprite_at_position() {
+        let vic = draw(|vic, _| {
+            vic.write(0xd000, 100);
+            vic.write(0xd001, 100);
+        });
+
+        // The first line of the sprite is the one after its Y coordinate
+        assert_eq!(pixel_at(&vic, 100, 100), 0x00);
+        assert_eq!(pixel_at(&vic, 99, 101), 0x00);
+        assert_eq!(pixel_at(&vic, 100, 101), 0x07);
+        assert_eq!(pixel_at(&vic, 123, 101), 0x07);
+        assert_eq!(pixel_at(&vic, 124, 101), 0x00);
+
+        // %10101010...
+        assert_eq!(pixel_at(&vic, 100, 102), 0x07);
+        assert_eq!(pixel_at(&vic, 101, 102), 0x00);
+
+        // 21 lines
+        assert_eq!(pixel_at(&vic, 100, 121), 0x07);
+        assert_eq!(pixel_at(&vic, 100, 122), 0x00);
+    }
+
+    #[test]
+    fn should_use_most_significant_bit_of_x() {
+        let vic = draw(|vic, _| {
+            vic.write(0xd000, 0x10);
+            vic.write(0xd001, 100);
+            vic.write(0xd010, 0x01);
+        });
+
+        assert_eq!(pixel_at(&vic, 0x10, 101), 0x00);
+        assert_eq!(pixel_at(&vic, 0x110, 101), 0x07);
+    }
+
+    #[test]
+    fn should_take_pointer_from_end_of_video_matrix() {
+        // Video matrix at $0c00 instead, pointing at the data at $0840
+        let vic = draw(|vic, ram| {
+            vic.write(0xd018, 0x34);
+            vic.write(0xd000, 100);
+            vic.write(0xd001, 100);
+            ram[0x0ff8] = 0x21;
+            ram[0x0840] = 0x80;
+        });
+
+        assert_eq!(pixel_at(&vic, 100, 101), 0x07);
+        assert_eq!(pixel_at(&vic, 101, 101), 0x00);
+    }
+
+    #[test]
+    fn should_expand_sprite() {
+        let vic = draw(|vic, _| {
+            vic.write(0xd000, 100);
+            vic.write(0xd001, 100);
+            vic.write(0xd017, 0x01);
+            vic.write(0xd01d, 0x01);
+        });
+
+        assert_eq!(pixel_at(&vic, 147, 101), 0x07);
+        assert_eq!(pixel_at(&vic, 148, 101), 0x00);
+
+        // The first row is shown twice, and the next row is twice as wide
+        assert_eq!(pixel_at(&vic, 101, 102), 0x07);
+        assert_eq!(pixel_at(&vic, 101, 103), 0x07);
+        assert_eq!(pixel_at(&vic, 102, 103), 0x00);
+
+        // 42 lines
+        assert_eq!(pixel_at(&vic, 100, 142), 0x07);
+        assert_eq!(pixel_at(&vic, 100, 143), 0x00);
+    }
+
+    #[test]
+    fn should_draw_multicolor_sprite() {
+        let vic = draw(|vic, ram| {
+            vic.write(0xd000, 100);
+            vic.write(0xd001, 100);
+            vic.write(0xd01c, 0x01);
+            vic.write(0xd025, 0x0a);
+            vic.write(0xd026, 0x0b);
+            ram[0x0800] = 0b00_01_10_11;
+        });
+
+        let pixels: Vec<u8> = (0..8).map(|x| pixel_at(&vic, 100 + x, 101)).collect();
+        assert_eq!(pixels, [0x00, 0x00, 0x0a, 0x0a, 0x07, 0x07, 0x0b, 0x0b]);
+    }
+
+    #[test]
+    fn should_put_sprites_behind_foreground() {
+        // The right half of every character is black, on a blue background
+        let setup = |vic: &mut VicII, ram: &mut Vec<u8>, priority: u8| {
+            vic.write(0xd000, 100);
+            vic.write(0xd001, 100);
+            vic.write(0xd01b, priority);
+            vic.write(0xd018, 0x18);
+            vic.write(0xd021, 0x06);
+            ram[0x2000..0x2008].fill(0x0f);
+        };
+
+        let vic = draw(|vic, ram| setup(vic, ram, 0x00));
+        assert_eq!(pixel_at(&vic, 100, 101), 0x07);
+        assert_eq!(pixel_at(&vic, 104, 101), 0x07);
+
+        let vic = draw(|vic, ram| setup(vic, ram, 0x01));
+        assert_eq!(pixel_at(&vic, 100, 101), 0x00);
+        assert_eq!(pixel_at(&vic, 104, 101), 0x07);
+    }
+
+    #[test]
+    fn should_draw_lower_sprites_on_top() {
+        let vic = draw(|vic, ram| {
+            vic.write(0xd015, 0x03);
+            vic.write(0xd000, 100);
+            vic.write(0xd001, 100);
+            vic.write(0xd002, 110);
+            vic.write(0xd003, 100);
+            vic.write(0xd028, 0x02);
+            ram[0x07f9] = 0x20;
+        });
+
+        assert_eq!(pixel_at(&vic, 110, 101), 0x07);
+        assert_eq!(pixel_at(&vic, 130, 101), 0x02);
+    }
+
+    #[test]
+    fn should_detect_collisions() {
+        let mut vic = draw(|vic, ram| {
+            vic.write(0xd015, 0x03);
+            vic.write(0xd000, 100);
+            vic.write(0xd001, 100);
+            vic.write(0xd002, 110);
+            vic.write(0xd003, 100);
+            vic.write(0xd01a, IRQ_SPRITE_SPRITE);
+            ram[0x07f9] = 0x20;
+        });
+
+        assert!(vic.irq());
+        assert_eq!(vic.peek(0xd01e), 0x03);
+        assert_eq!(vic.read(0xd01f), 0x00);
+
+        // Reading clears the collisions, but not the interrupt
+        assert_eq!(vic.read(0xd01e), 0x03);
+        assert_eq!(vic.read(0xd01e), 0x00);
+        assert_eq!(vic.read(0xd019) & 0x06, IRQ_SPRITE_SPRITE);
+    }
+
+    #[test]
+    fn should_detect_collisions_with_foreground() {
+        let vic = draw(|vic, ram| {
+            vic.write(0xd000, 100);
+            vic.write(0xd001, 100);
+            vic.write(0xd018, 0x18);
+            ram[0x2000..0x2008].fill(0x01);
+        });
+
+        assert_eq!(vic.peek(0xd01f), 0x01);
+        assert_eq!(vic.peek(0xd019) & 0x06, IRQ_SPRITE_BACKGROUND);
+    }
+
+    #[test]
+    fn should_pull_ba_low_before_fetching_sprites() {
+        let memory = VicMemory {
+            ram: &[0; 0x10000],
+            chargen: None,
+            color_ram: &[0; 0x400],
+            bank: 0x0000,
+        };
+
+        // Sprite 0 and 3 on line 101, from line 100
+        let mut vic = VicII::new(Timing::PAL);
+        vic.write(0xd015, 0x09);
+        vic.write(0xd001, 100);
+        vic.write(0xd007, 100);
+
+        let mut cycles = vec![];
+        while vic.raster_line() < 102 {
+            if vic.ba_low() {
+                cycles.push((vic.raster_line(), vic.cycle()));
+            }
+            vic.tick(&memory);
+        }
+
+        #[rustfmt::skip]
+        let expected = [
+            // Sprite 0, at 58-59 on the line before
+            (100, 55), (100, 56), (100, 57), (100, 58), (100, 59),
+            // Sprite 3, at 1-2 on the line
+            (100, 61), (100, 62), (100, 63), (101, 1), (101, 2),
+            // The same for the next line of the sprites
+            (101, 55), (101, 56), (101, 57), (101, 58), (101, 59),
+            (101, 61), (101, 62), (101, 63),
+        ];
+        assert_eq!(cycles, expected);
+    }
+}
@@ -0,0 +1,774 @@
+//! The commands of the command line, a function for each of them, taking the
+//! arguments after the name of the command. Whatever they print goes to
+//! stdout, so it can be piped on, while the errors go to stderr, with an exit
+//! code to go by:
+//!
+//! ```text
+//! 0 - it went fine
+//! 1 - the command failed, e.g. a file that couldn't be read
+//! 2 - the arguments were wrong, shown along with the usage
+//! ```
+//!
+//! Addresses can be given in hex as $c000 or 0xc000, or in decimal as 49152.
+//! A file that isn't a PRG, e.g. a ROM, can be read with `--at`, which is the
+//! address the data of it belongs at, as there is no load address in it.
+
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use c64::autostart::{AutostartError, Boot, Start};
+use c64::d64::{D64Error, Entry, FileType, D64};
+use c64::petscii::{Charset, Decoding};
+use c64::prg::LoadError;
+use c64::roms::{RomError, Roms};
+use c64::screenshot::{ImageError, Screenshot};
+use c64::vicII::pal_filter::PalFilter;
+use c64::vicII::palette::{Palette, PaletteError};
+use c64::{AsmError, Block, C64};
+
+#[cfg(feature = "desktop")]
+use crate::desktop::{self, DesktopError};
+#[cfg(feature = "terminal")]
+use crate::terminal;
+
+// Where the ROMs are loaded from, unless given with --roms
+const ROMS_VARIABLE: &str = "C64_ROMS";
+const ROMS_DIR: &str = "roms";
+
+// Frames to run headless unless given with --frames, about 5 seconds on PAL
+const DEFAULT_FRAMES: u64 = 250;
+
+const DISASM_USAGE: &str =
+    "c64 disasm FILE [--at ADDR] [--start ADDR] [--end ADDR] [--entry ADDR]... [--labels]";
+const ASM_USAGE: &str = "c64 asm SOURCE [-o FILE.prg] [--sys]";
+const RUN_USAGE: &str =
+    "c64 run FILE.prg [--roms DIR] [--palette NAME|FILE.vpl] [--headless] [--frames N] [--screenshot FILE.png] [--pal-filter]";
+const HEXDUMP_USAGE: &str =
+    "c64 hexdump FILE [--at ADDR] [--start ADDR] [--end ADDR] [--petscii | --screen-codes] [--lowercase]";
+const D64_USAGE: &str = "c64 d64 ls IMAGE.d64
+       c64 d64 extract IMAGE.d64 NAME [-o FILE]
+       c64 d64 add IMAGE.d64 FILE [--name NAME]
+       c64 d64 new IMAGE.d64 NAME [--id ID]";
+const BASIC_USAGE: &str = "c64 basic list FILE.prg";
+const TERMINAL_USAGE: &str = "c64 terminal [--roms DIR] [--palette NAME|FILE.vpl] [FILE.prg]";
+const DESKTOP_USAGE: &str =
+    "c64 desktop [--roms DIR] [--palette NAME|FILE.vpl] [--scale N] [FILE.prg]";
+
+#[derive(Debug)]
+pub enum CommandError {
+    /// The arguments are wrong, where the usage shows how they should be
+    Usage {
+        message: String,
+        usage: &'static str,
+    },
+    Read(PathBuf, io::Error),
+    Write(PathBuf, io::Error),
+    /// The output couldn't be written to stdout
+    Output(io::Error),
+    /// The file to create is already there
+    Exists(PathBuf),
+    /// The addresses asked for aren't part of the file
+    OutsideFile {
+        start: u16,
+        end: u16,
+    },
+    /// The program is too far from $0801, or too close, for a SYS line
+    NoRoomForSys(u16),
+    /// No file on the disk matches the name
+    NotFound(String),
+    Load(LoadError),
+    Asm(AsmError),
+    D64(D64Error),
+    Rom(RomError),
+    Autostart(AutostartError),
+    Palette(PaletteError),
+    Image(ImageError),
+    #[cfg(feature = "terminal")]
+    Terminal(io::Error),
+    #[cfg(feature = "desktop")]
+    Desktop(DesktopError),
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Usage { message, usage } => write!(f, "{message}\nusage: {usage}"),
+            CommandError::Read(path, err) => write!(f, "unable to read {}: {err}", path.display()),
+            CommandError::Write(path, err) => {
+                write!(f, "unable to write {}: {err}", path.display())
+            }
+            CommandError::Output(err) => write!(f, "unable to write output: {err}"),
+            CommandError::Exists(path) => write!(f, "{} already exists", path.display()),
+            CommandError::OutsideFile { start, end } => {
+                write!(f, "${start:04X}-${end:04X} isn't part of the file")
+            }
+            CommandError::NoRoomForSys(start) => {
+                write!(f, "a SYS line at $0801 can't start code at ${start:04X}")
+            }
+            CommandError::NotFound(name) => write!(f, "there is no file named {name}"),
+            CommandError::Load(err) => write!(f, "{err}"),
+            CommandError::Asm(err) => write!(f, "{err}"),
+            CommandError::D64(err) => write!(f, "{err}"),
+            CommandError::Rom(err) => write!(f, "{err}"),
+            CommandError::Autostart(err) => write!(f, "{err}"),
+            CommandError::Palette(err) => write!(f, "{err}"),
+            CommandError::Image(err) => write!(f, "{err}"),
+            #[cfg(feature = "terminal")]
+            CommandError::Terminal(err) => write!(f, "{err}"),
+            #[cfg(feature = "desktop")]
+            CommandError::Desktop(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<LoadError> for CommandError {
+    fn from(err: LoadError) -> Self {
+        CommandError::Load(err)
+    }
+}
+
+impl From<AsmError> for CommandError {
+    fn from(err: AsmError) -> Self {
+        CommandError::Asm(err)
+    }
+}
+
+impl From<D64Error> for CommandError {
+    fn from(err: D64Error) -> Self {
+        CommandError::D64(err)
+    }
+}
+
+impl From<RomError> for CommandError {
+    fn from(err: RomError) -> Self {
+        CommandError::Rom(err)
+    }
+}
+
+impl From<AutostartError> for CommandError {
+    fn from(err: AutostartError) -> Self {
+        CommandError::Autostart(err)
+    }
+}
+
+impl From<PaletteError> for CommandError {
+    fn from(err: PaletteError) -> Self {
+        CommandError::Palette(err)
+    }
+}
+
+impl From<ImageError> for CommandError {
+    fn from(err: ImageError) -> Self {
+        CommandError::Image(err)
+    }
+}
+
+#[cfg(feature = "desktop")]
+impl From<DesktopError> for CommandError {
+    fn from(err: DesktopError) -> Self {
+        CommandError::Desktop(err)
+    }
+}
+
+impl CommandError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CommandError::Usage { .. } => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// The usage of every command there is in this build
+pub fn usage() -> String {
+    let frontends = [
+        cfg!(feature = "terminal").then_some(TERMINAL_USAGE),
+        cfg!(feature = "desktop").then_some(DESKTOP_USAGE),
+    ];
+    let usages: Vec<&str> = [
+        DISASM_USAGE,
+        ASM_USAGE,
+        RUN_USAGE,
+        HEXDUMP_USAGE,
+        D64_USAGE,
+        BASIC_USAGE,
+    ]
+    .into_iter()
+    .chain(frontends.into_iter().flatten())
+    .collect();
+
+    format!("usage: {}", usages.join("\n       "))
+}
+
+/// Runs the command, or returns None when there is no command by that name
+pub fn run(command: &str, args: &[String]) -> Option<Result<(), CommandError>> {
+    let result = match command {
+        "disasm" => disasm(args),
+        "asm" => asm(args),
+        "run" => run_prg(args),
+        "hexdump" => hexdump(args),
+        "d64" => d64(args),
+        "basic" => basic(args),
+        #[cfg(feature = "terminal")]
+        "terminal" => terminal_command(args),
+        #[cfg(feature = "desktop")]
+        "desktop" => desktop_command(args),
+        _ => return None,
+    };
+    Some(result)
+}
+
+// c64 disasm FILE [--at ADDR] [--start ADDR] [--end ADDR] [--entry ADDR]... [--labels]
+fn disasm(args: &[String]) -> Result<(), CommandError> {
+    let options = ["--at", "--start", "--end", "--entry"];
+    let args = Args::parse(args, &options, &["--labels"], DISASM_USAGE)?;
+    let [path] = args.positional()?;
+
+    let block = args.part(&read_block(path, args.address("--at")?)?)?;
+    let entries = args
+        .values("--entry")
+        .map(|entry| args.parse_address("--entry", entry))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match args.flag("--labels") {
+        true => print_lines(block.disassemble_labelled(&entries)),
+        false => print_lines(block.disassemble_flow(&entries)),
+    }
+}
+
+// c64 asm SOURCE [-o FILE.prg] [--sys]
+fn asm(args: &[String]) -> Result<(), CommandError> {
+    let args = Args::parse(args, &["-o"], &["--sys"], ASM_USAGE)?;
+    let [path] = args.positional()?;
+
+    let source = read(path)?;
+    let source = String::from_utf8_lossy(&source);
+    let mut block = Block::try_assemble(&source)?;
+
+    if args.flag("--sys") {
+        block = block
+            .with_sys_stub()
+            .ok_or(CommandError::NoRoomForSys(block.start))?;
+    }
+
+    let output = match args.value("-o") {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(path).with_extension("prg"),
+    };
+    write(&output, &block.to_prg())
+}
+
+// c64 run FILE.prg [--roms DIR] [--palette NAME|FILE.vpl] [--headless] [--frames N] [--screenshot FILE.png] [--pal-filter]
+fn run_prg(args: &[String]) -> Result<(), CommandError> {
+    let options = ["--roms", "--palette", "--frames", "--screenshot"];
+    let flags = ["--headless", "--pal-filter"];
+    let args = Args::parse(args, &options, &flags, RUN_USAGE)?;
+    let [path] = args.positional()?;
+
+    // Without a frontend to show it in, there is only running headless
+    let headless =
+        args.flag("--headless") || cfg!(not(any(feature = "terminal", feature = "desktop")));
+    let frames = match args.value("--frames") {
+        Some(frames) => frames
+            .parse()
+            .map_err(|_| args.usage_error("--frames needs a number of frames"))?,
+        None => DEFAULT_FRAMES,
+    };
+    let headless_only = args.value("--frames").is_some()
+        || args.value("--screenshot").is_some()
+        || args.flag("--pal-filter");
+    if !headless && headless_only {
+        return Err(args.usage_error("--frames, --screenshot and --pal-filter need --headless"));
+    }
+
+    let mut c64 = start(&roms_dir(&args), Some(path))?;
+    set_palette(&mut c64, &args)?;
+    if !headless {
+        return run_frontend(&mut c64, &args);
+    }
+
+    for _ in 0..frames {
+        c64.run_frame();
+    }
+    match args.value("--screenshot") {
+        Some(path) => {
+            let screenshot = Screenshot {
+                pal_filter: args.flag("--pal-filter").then(PalFilter::default),
+                ..Screenshot::default()
+            };
+            Ok(c64.screenshot(screenshot).save_png(path)?)
+        }
+        None => Ok(()),
+    }
+}
+
+// c64 hexdump FILE [--at ADDR] [--start ADDR] [--end ADDR] [--petscii | --screen-codes] [--lowercase]
+fn hexdump(args: &[String]) -> Result<(), CommandError> {
+    let options = ["--at", "--start", "--end"];
+    let flags = ["--petscii", "--screen-codes", "--lowercase"];
+    let args = Args::parse(args, &options, &flags, HEXDUMP_USAGE)?;
+    let [path] = args.positional()?;
+
+    let charset = match args.flag("--lowercase") {
+        true => Charset::Lowercase,
+        false => Charset::Uppercase,
+    };
+    let decoding = match (args.flag("--petscii"), args.flag("--screen-codes")) {
+        (true, true) => {
+            return Err(args.usage_error("--petscii and --screen-codes can't be combined"))
+        }
+        (true, false) => Decoding::Petscii(charset),
+        (false, true) => Decoding::ScreenCodes(charset),
+        (false, false) => Decoding::Ascii,
+    };
+
+    let block = args.part(&read_block(path, args.address("--at")?)?)?;
+    print_lines(block.memory_as(decoding))
+}
+
+// c64 d64 ls|extract|add|new IMAGE.d64 ...
+fn d64(args: &[String]) -> Result<(), CommandError> {
+    let Some((command, args)) = args.split_first() else {
+        return Err(usage_error("expected ls, extract, add or new", D64_USAGE));
+    };
+
+    match command.as_str() {
+        "ls" => {
+            let args = Args::parse(args, &[], &[], D64_USAGE)?;
+            let [path] = args.positional()?;
+            print_lines(directory(&D64::load(path)?)?)
+        }
+        "extract" => {
+            let args = Args::parse(args, &["-o"], &[], D64_USAGE)?;
+            let [path, name] = args.positional()?;
+
+            let d64 = D64::load(path)?;
+            let entry = d64
+                .find(name)?
+                .ok_or_else(|| CommandError::NotFound(name.to_string()))?;
+            let output = match args.value("-o") {
+                Some(output) => PathBuf::from(output),
+                None => PathBuf::from(file_name(&entry)),
+            };
+            write(&output, &d64.read(&entry)?)?;
+            print_lines([output.display()])
+        }
+        "add" => {
+            let args = Args::parse(args, &["--name"], &[], D64_USAGE)?;
+            let [path, file] = args.positional()?;
+
+            let name = match args.value("--name") {
+                Some(name) => name.to_string(),
+                None => Path::new(file)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            };
+            let mut d64 = D64::load(path)?;
+            d64.add(&name, FileType::Prg, &read(file)?)?;
+            d64.save(path)?;
+            Ok(())
+        }
+        "new" => {
+            let args = Args::parse(args, &["--id"], &[], D64_USAGE)?;
+            let [path, name] = args.positional()?;
+
+            if Path::new(path).exists() {
+                return Err(CommandError::Exists(PathBuf::from(path)));
+            }
+            let d64 = D64::new(name, args.value("--id").unwrap_or("00"))?;
+            write(Path::new(path), d64.bytes())
+        }
+        command => Err(usage_error(
+            &format!("there is no d64 command {command}"),
+            D64_USAGE,
+        )),
+    }
+}
+
+// c64 basic list FILE.prg
+fn basic(args: &[String]) -> Result<(), CommandError> {
+    match args.split_first() {
+        Some((command, args)) if command == "list" => {
+            let args = Args::parse(args, &[], &[], BASIC_USAGE)?;
+            let [path] = args.positional()?;
+            print_lines(Block::from_prg(&read(path)?)?.list())
+        }
+        _ => Err(usage_error("expected list", BASIC_USAGE)),
+    }
+}
+
+// c64 terminal [--roms DIR] [--palette NAME|FILE.vpl] [FILE.prg]
+#[cfg(feature = "terminal")]
+fn terminal_command(args: &[String]) -> Result<(), CommandError> {
+    let args = Args::parse(args, &["--roms", "--palette"], &[], TERMINAL_USAGE)?;
+    let [path] = args.optional()?;
+
+    let mut c64 = start(&roms_dir(&args), path)?;
+    set_palette(&mut c64, &args)?;
+    terminal::run(&mut c64).map_err(CommandError::Terminal)
+}
+
+// c64 desktop [--roms DIR] [--palette NAME|FILE.vpl] [--scale N] [FILE.prg]
+#[cfg(feature = "desktop")]
+fn desktop_command(args: &[String]) -> Result<(), CommandError> {
+    let args = Args::parse(
+        args,
+        &["--roms", "--palette", "--scale"],
+        &[],
+        DESKTOP_USAGE,
+    )?;
+    let [path] = args.optional()?;
+    let scale = match args.value("--scale") {
+        Some(scale) => scale
+            .parse()
+            .map_err(|_| args.usage_error("--scale needs a number"))?,
+        None => 2,
+    };
+
+    let mut c64 = start(&roms_dir(&args), path)?;
+    set_palette(&mut c64, &args)?;
+    Ok(desktop::run(&mut c64, scale)?)
+}
+
+// The window when there is one, or else the terminal
+#[cfg(feature = "desktop")]
+fn run_frontend(c64: &mut C64, _args: &Args) -> Result<(), CommandError> {
+    Ok(desktop::run(c64, 2)?)
+}
+
+#[cfg(all(feature = "terminal", not(feature = "desktop")))]
+fn run_frontend(c64: &mut C64, _args: &Args) -> Result<(), CommandError> {
+    terminal::run(c64).map_err(CommandError::Terminal)
+}
+
+#[cfg(not(any(feature = "terminal", feature = "desktop")))]
+fn run_frontend(_c64: &mut C64, args: &Args) -> Result<(), CommandError> {
+    Err(args.usage_error("there is no frontend in this build, use --headless"))
+}
+
+fn roms_dir(args: &Args) -> PathBuf {
+    match args.value("--roms") {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var(ROMS_VARIABLE)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(ROMS_DIR)),
+    }
+}
+
+// Uses the palette of --palette, either one of the built in ones by name or a
+// .vpl file of VICE
+fn set_palette(c64: &mut C64, args: &Args) -> Result<(), CommandError> {
+    let palette = match args.value("--palette") {
+        Some(file) if file.to_ascii_lowercase().ends_with(".vpl") => Palette::load_vpl(file)?,
+        Some(name) => name.parse()?,
+        None => return Ok(()),
+    };
+    c64.set_palette(palette);
+    Ok(())
+}
+
+// A machine with the ROMs, either running the PRG or at the BASIC prompt. The
+// PRG is started with RUN, or with its SYS line when there is no KERNAL or
+// BASIC to boot with.
+fn start(roms: &Path, prg: Option<&str>) -> Result<C64, CommandError> {
+    let mut c64 = C64::new();
+    c64.set_roms(Roms::from_dir(roms)?);
+
+    let Some(path) = prg else {
+        c64.reset();
+        return Ok(c64);
+    };
+    let prg = read(path)?;
+    match c64.autostart(&prg, Boot::Kernal, Start::Run) {
+        Err(AutostartError::MissingKernal | AutostartError::MissingBasic) => {
+            c64.autostart(&prg, Boot::Fast, Start::Sys)?
+        }
+        started => started?,
+    }
+    Ok(c64)
+}
+
+// The directory, the way LOAD "$",8 and LIST show it
+fn directory(d64: &D64) -> Result<Vec<String>, CommandError> {
+    let mut lines = vec![format!("0 \"{:16}\" {}", d64.name(), d64.id())];
+
+    for entry in d64.directory()? {
+        let splat = if entry.closed { " " } else { "*" };
+        let lock = if entry.locked { "<" } else { "" };
+        let name = format!("\"{}\"", entry.name_text());
+        lines.push(format!(
+            "{:<4} {name:18}{splat}{}{lock}",
+            entry.blocks, entry.file_type
+        ));
+    }
+
+    lines.push(format!("{} BLOCKS FREE.", d64.blocks_free()));
+    Ok(lines)
+}
+
+// A name for the file extracted from a disk, with the type as the extension
+// and anything that doesn't belong in a file name replaced
+fn file_name(entry: &Entry) -> String {
+    let name: String = entry
+        .name_text()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("{name}.{}", entry.file_type.to_string().to_lowercase())
+}
+
+// A PRG, or the data of any file at the address, when given one
+fn read_block(path: &str, at: Option<u16>) -> Result<Block, CommandError> {
+    let bytes = read(path)?;
+    let Some(start) = at else {
+        return Ok(Block::from_prg(&bytes)?);
+    };
+
+    if start as usize + bytes.len() > 0x10000 {
+        return Err(CommandError::Load(LoadError::WrapsAround {
+            start,
+            length: bytes.len(),
+        }));
+    }
+    Ok(Block {
+        start,
+        instructions: bytes,
+    })
+}
+
+fn read(path: &str) -> Result<Vec<u8>, CommandError> {
+    std::fs::read(path).map_err(|err| CommandError::Read(PathBuf::from(path), err))
+}
+
+fn write(path: &Path, bytes: &[u8]) -> Result<(), CommandError> {
+    std::fs::write(path, bytes).map_err(|err| CommandError::Write(path.to_path_buf(), err))
+}
+
+// Prints the lines to stdout, where a pipe closed early, e.g. by `head`, is
+// fine, as whatever reads the output has seen enough of it
+fn print_lines(lines: impl IntoIterator<Item = impl Display>) -> Result<(), CommandError> {
+    let mut stdout = io::stdout().lock();
+    let printed = lines
+        .into_iter()
+        .try_for_each(|line| writeln!(stdout, "{line}"))
+        .and_then(|_| stdout.flush());
+
+    match printed {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => Err(CommandError::Output(err)),
+        _ => Ok(()),
+    }
+}
+
+fn usage_error(message: &str, usage: &'static str) -> CommandError {
+    CommandError::Usage {
+        message: message.to_string(),
+        usage,
+    }
+}
+
+fn arguments(count: usize) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        count => format!("{count} arguments"),
+    }
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    match text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// The arguments of a command, the options with their values, the flags and
+// the rest, in the order they were given
+struct Args {
+    options: Vec<(String, String)>,
+    flags: Vec<String>,
+    positional: Vec<String>,
+    usage: &'static str,
+}
+
+impl Args {
+    // Splits up the arguments, where the `options` take a value and the
+    // `flags` don't, and anything else starting with a dash is an error
+    fn parse(
+        args: &[String],
+        options: &[&str],
+        flags: &[&str],
+        usage: &'static str,
+    ) -> Result<Args, CommandError> {
+        let mut parsed = Args {
+            options: vec![],
+            flags: vec![],
+            positional: vec![],
+            usage,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                option if options.contains(&option) => match args.next() {
+                    Some(value) => parsed.options.push((arg.clone(), value.clone())),
+                    None => return Err(parsed.usage_error(&format!("{option} needs a value"))),
+                },
+                flag if flags.contains(&flag) => parsed.flags.push(arg.clone()),
+                unknown if unknown.starts_with('-') && unknown.len() > 1 => {
+                    return Err(parsed.usage_error(&format!("unknown option {unknown}")))
+                }
+                _ => parsed.positional.push(arg.clone()),
+            }
+        }
+        Ok(parsed)
+    }
+
+    // Exactly N arguments that aren't options
+    fn positional<const N: usize>(&self) -> Result<[&str; N], CommandError> {
+        let found: Vec<&str> = self.positional.iter().map(String::as_str).collect();
+        found.try_into().map_err(|found: Vec<&str>| {
+            let expected = arguments(N);
+            self.usage_error(&format!("expected {expected}, found {}", found.len()))
+        })
+    }
+
+    // At most N arguments that aren't options
+    #[cfg(any(feature = "terminal", feature = "desktop"))]
+    fn optional<const N: usize>(&self) -> Result<[Option<&str>; N], CommandError> {
+        if self.positional.len() > N {
+            let (expected, found) = (arguments(N), self.positional.len());
+            return Err(self.usage_error(&format!("expected at most {expected}, found {found}")));
+        }
+        Ok(std::array::from_fn(|index| {
+            self.positional.get(index).map(String::as_str)
+        }))
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    // The last value of the option, when it is given more than once
+    fn value<'a>(&'a self, name: &'a str) -> Option<&'a str> {
+        self.values(name).last()
+    }
+
+    fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.options
+            .iter()
+            .filter(move |(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn address(&self, name: &str) -> Result<Option<u16>, CommandError> {
+        self.value(name)
+            .map(|value| self.parse_address(name, value))
+            .transpose()
+    }
+
+    fn parse_address(&self, name: &str, value: &str) -> Result<u16, CommandError> {
+        parse_address(value)
+            .ok_or_else(|| self.usage_error(&format!("{name} needs an address, not {value}")))
+    }
+
+    // The part of the block from --start to --end, both included
+    fn part(&self, block: &Block) -> Result<Block, CommandError> {
+        let last = (block.start as usize + block.instructions.len()).saturating_sub(1);
+        let start = self.address("--start")?.unwrap_or(block.start);
+        let end = self.address("--end")?.unwrap_or(last as u16);
+
+        let outside = start > end || start < block.start || end as usize > last;
+        if outside || block.instructions.is_empty() {
+            return Err(CommandError::OutsideFile { start, end });
+        }
+        let offset = (start - block.start) as usize;
+        Ok(Block {
+            start,
+            instructions: block.instructions[offset..=offset + (end - start) as usize].to_vec(),
+        })
+    }
+
+    fn usage_error(&self, message: &str) -> CommandError {
+        usage_error(message, self.usage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn should_parse_arguments() {
+        let args = strings(&["a.prg", "--start", "$c000", "--labels", "--entry", "49152"]);
+        let args = Args::parse(&args, &["--start", "--entry"], &["--labels"], "").unwrap();
+
+        assert_eq!(args.positional().unwrap(), ["a.prg"]);
+        assert_eq!(args.address("--start").unwrap(), Some(0xc000));
+        assert_eq!(args.address("--entry").unwrap(), Some(0xc000));
+        assert!(args.flag("--labels"));
+        assert!(args.positional::<2>().is_err());
+
+        let args = strings(&["--bogus"]);
+        let result = Args::parse(&args, &[], &[], "");
+        assert_eq!(result.err().map(|err| err.exit_code()), Some(2));
+        assert_eq!(parse_address("0xd020"), Some(0xd020));
+        assert_eq!(parse_address("$10000"), None);
+    }
+
+    #[test]
+    fn should_set_palette() {
+        let mut c64 = C64::new();
+        let args = strings(&["--palette", "colodore"]);
+        let args = Args::parse(&args, &["--palette"], &[], "").unwrap();
+        set_palette(&mut c64, &args).unwrap();
+        assert_eq!(c64.vic().palette(), &c64::vicII::palette::COLODORE);
+
+        let args = strings(&["--palette", "missing.vpl"]);
+        let args = Args::parse(&args, &["--palette"], &[], "").unwrap();
+        assert!(matches!(
+            set_palette(&mut c64, &args),
+            Err(CommandError::Palette(PaletteError::Io(_)))
+        ));
+    }
+
+    #[test]
+    fn should_take_part_of_block() {
+        let block = Block {
+            start: 0x1000,
+            instructions: vec![0, 1, 2, 3],
+        };
+
+        let args = strings(&["--start", "$1001", "--end", "$1002"]);
+        let args = Args::parse(&args, &["--start", "--end"], &[], "").unwrap();
+        assert_eq!(args.part(&block).unwrap().instructions, vec![1, 2]);
+
+        let args = strings(&["--end", "$1004"]);
+        let args = Args::parse(&args, &["--end"], &[], "").unwrap();
+        assert!(matches!(
+            args.part(&block),
+            Err(CommandError::OutsideFile { .. })
+        ));
+    }
+
+    #[test]
+    fn should_list_directory() {
+        let mut d64 = D64::new("demo", "42").unwrap();
+        d64.add("hello", FileType::Prg, &[0x01, 0x08]).unwrap();
+
+        let lines = directory(&d64).unwrap();
+        assert_eq!(lines[0], "0 \"DEMO            \" 42");
+        assert_eq!(lines[1], "1    \"HELLO\"            PRG");
+        assert_eq!(lines[2], "663 BLOCKS FREE.");
+    }
+}
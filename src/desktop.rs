@@ -0,0 +1,405 @@
+//! A frontend for the desktop, showing the visible area of the frame in a
+//! window and playing the SID on the default audio output. It's only built
+//! with the `desktop` feature, so the emulator itself doesn't need any window
+//! system or audio libraries.
+//!
+//! The frame is scaled up on the CPU, by repeating every pixel, so it stays
+//! sharp whatever the window system would have done with it.
+//!
+//! The keys map to the C64 keyboard by where they are, rather than what is
+//! printed on them, see `Key::for_position`, and the keys above F8 are for
+//! the emulator:
+//!
+//! - F9  - reset
+//! - F10 - warp, running as fast as possible, without sound
+//! - F11 - screenshot, saved as a PNG in the current directory
+//! - F12 - monitor, reading commands from the terminal the emulator was
+//!   started from, with the window stopped until leaving it
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample};
+use minifb::{KeyRepeat, Window, WindowOptions};
+
+use c64::keyboard::Key;
+use c64::monitor::{Monitor, Reply};
+use c64::screenshot::Screenshot;
+use c64::vicII::palette::Palette;
+use c64::C64;
+
+const TITLE: &str = "C64";
+
+// The most audio to keep waiting to be played, in seconds, which is dropped
+// rather than letting the sound fall further and further behind
+const MAX_AUDIO_DELAY: f64 = 0.2;
+
+#[derive(Debug)]
+pub enum DesktopError {
+    Window(minifb::Error),
+    Io(io::Error),
+}
+
+impl Display for DesktopError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DesktopError::Window(err) => write!(f, "{err}"),
+            DesktopError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for DesktopError {}
+
+impl From<minifb::Error> for DesktopError {
+    fn from(err: minifb::Error) -> Self {
+        DesktopError::Window(err)
+    }
+}
+
+impl From<io::Error> for DesktopError {
+    fn from(err: io::Error) -> Self {
+        DesktopError::Io(err)
+    }
+}
+
+/// Runs the machine in a window, scaled up by the scale, until it is closed
+pub fn run(c64: &mut C64, scale: usize) -> Result<(), DesktopError> {
+    let scale = scale.max(1);
+    let visible = c64.vic().timing().visible;
+    let (width, height) = (visible.width * scale, visible.height * scale);
+
+    let mut window = Window::new(TITLE, width, height, WindowOptions::default())?;
+    // The frames are paced here instead, so warp can run as fast as it can
+    window.set_target_fps(0);
+
+    // Without a sound card, it still runs, just without any sound
+    let audio = match Audio::open() {
+        Ok(audio) => {
+            c64.set_sample_rate(Some(audio.sample_rate));
+            Some(audio)
+        }
+        Err(err) => {
+            eprintln!("no sound: {err}");
+            None
+        }
+    };
+
+    let frame_time = Duration::from_secs_f64(1.0 / c64.model().frame_rate());
+    let mut next_frame = Instant::now();
+    let mut buffer = vec![0; width * height];
+    let mut held: HashMap<minifb::Key, Vec<Key>> = HashMap::new();
+    let mut monitor = Monitor::new();
+    let mut warp = false;
+
+    while window.is_open() {
+        for pressed in window.get_keys_pressed(KeyRepeat::No) {
+            match pressed {
+                minifb::Key::F9 => c64.reset(),
+                minifb::Key::F10 => {
+                    warp = !warp;
+                    window.set_title(match warp {
+                        true => "C64 (warp)",
+                        false => TITLE,
+                    });
+                }
+                minifb::Key::F11 => save_screenshot(c64),
+                minifb::Key::F12 => {
+                    // The releases of the keys held down are missed while in
+                    // the monitor
+                    held.clear();
+                    c64.release_all_keys();
+                    run_monitor(c64, &mut monitor)?;
+                    next_frame = Instant::now();
+                }
+                key => {
+                    if let Some(keys) = position_of(key).and_then(Key::for_position) {
+                        held.insert(key, keys);
+                    }
+                }
+            }
+        }
+        for released in window.get_keys_released() {
+            held.remove(&released);
+        }
+        c64.hold_keys(held.values().flatten().copied());
+
+        // With warp, as many frames as there is time for, showing the last
+        match warp {
+            true => {
+                let until = Instant::now() + frame_time;
+                while Instant::now() < until {
+                    c64.run_frame();
+                }
+            }
+            false => c64.run_frame(),
+        }
+
+        let samples = c64.take_samples();
+        if let (Some(audio), false) = (&audio, warp) {
+            audio.queue(&samples);
+        }
+
+        let vic = c64.vic();
+        scale_frame(
+            &vic.visible_frame(),
+            visible.width,
+            scale,
+            vic.palette(),
+            &mut buffer,
+        );
+        window.update_with_buffer(&buffer, width, height)?;
+
+        if warp {
+            next_frame = Instant::now();
+            continue;
+        }
+        next_frame += frame_time;
+        match next_frame.checked_duration_since(Instant::now()) {
+            Some(wait) => std::thread::sleep(wait),
+            // Too slow to keep up, so don't try to catch up either
+            None => next_frame = Instant::now(),
+        }
+    }
+    Ok(())
+}
+
+// Repeats every pixel of the frame, which is colour numbers, scale times in
+// both directions, as 0RGB
+fn scale_frame(frame: &[u8], width: usize, scale: usize, palette: &Palette, buffer: &mut [u32]) {
+    let scaled_width = width * scale;
+
+    for (y, line) in frame.chunks_exact(width).enumerate() {
+        let start = y * scale * scaled_width;
+        let first = &mut buffer[start..start + scaled_width];
+        for (x, index) in line.iter().enumerate() {
+            let color = palette[*index];
+            let rgb = (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32;
+            first[x * scale..(x + 1) * scale].fill(rgb);
+        }
+
+        for repeat in 1..scale {
+            buffer.copy_within(start..start + scaled_width, start + repeat * scaled_width);
+        }
+    }
+}
+
+// The name of the position of a key of the host, the way `Key::for_position`
+// takes it
+fn position_of(key: minifb::Key) -> Option<&'static str> {
+    use minifb::Key as Host;
+
+    let name = match key {
+        Host::Key0 => "Digit0",
+        Host::Key1 => "Digit1",
+        Host::Key2 => "Digit2",
+        Host::Key3 => "Digit3",
+        Host::Key4 => "Digit4",
+        Host::Key5 => "Digit5",
+        Host::Key6 => "Digit6",
+        Host::Key7 => "Digit7",
+        Host::Key8 => "Digit8",
+        Host::Key9 => "Digit9",
+        Host::A => "KeyA",
+        Host::B => "KeyB",
+        Host::C => "KeyC",
+        Host::D => "KeyD",
+        Host::E => "KeyE",
+        Host::F => "KeyF",
+        Host::G => "KeyG",
+        Host::H => "KeyH",
+        Host::I => "KeyI",
+        Host::J => "KeyJ",
+        Host::K => "KeyK",
+        Host::L => "KeyL",
+        Host::M => "KeyM",
+        Host::N => "KeyN",
+        Host::O => "KeyO",
+        Host::P => "KeyP",
+        Host::Q => "KeyQ",
+        Host::R => "KeyR",
+        Host::S => "KeyS",
+        Host::T => "KeyT",
+        Host::U => "KeyU",
+        Host::V => "KeyV",
+        Host::W => "KeyW",
+        Host::X => "KeyX",
+        Host::Y => "KeyY",
+        Host::Z => "KeyZ",
+        Host::Minus => "Minus",
+        Host::Equal => "Equal",
+        Host::Backquote => "Backquote",
+        Host::LeftBracket => "BracketLeft",
+        Host::RightBracket => "BracketRight",
+        Host::Semicolon => "Semicolon",
+        Host::Apostrophe => "Quote",
+        Host::Backslash => "Backslash",
+        Host::Comma => "Comma",
+        Host::Period => "Period",
+        Host::Slash => "Slash",
+        Host::Space => "Space",
+        Host::Enter => "Enter",
+        Host::NumPadEnter => "NumpadEnter",
+        Host::Backspace => "Backspace",
+        Host::Delete => "Delete",
+        Host::Insert => "Insert",
+        Host::Home => "Home",
+        Host::LeftShift => "ShiftLeft",
+        Host::RightShift => "ShiftRight",
+        Host::LeftCtrl => "ControlLeft",
+        Host::Tab => "Tab",
+        Host::LeftAlt => "AltLeft",
+        Host::Escape => "Escape",
+        Host::PageUp => "PageUp",
+        Host::PageDown => "PageDown",
+        Host::Right => "ArrowRight",
+        Host::Left => "ArrowLeft",
+        Host::Down => "ArrowDown",
+        Host::Up => "ArrowUp",
+        Host::F1 => "F1",
+        Host::F2 => "F2",
+        Host::F3 => "F3",
+        Host::F4 => "F4",
+        Host::F5 => "F5",
+        Host::F6 => "F6",
+        Host::F7 => "F7",
+        Host::F8 => "F8",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn save_screenshot(c64: &C64) {
+    let path = format!("c64-{:06}.png", c64.vic().frames());
+    match c64.screenshot(Screenshot::default()).save_png(&path) {
+        Ok(()) => eprintln!("saved {path}"),
+        Err(err) => eprintln!("unable to save {path}: {err}"),
+    }
+}
+
+// Reads commands for the monitor from stdin, until one of them continues
+fn run_monitor(c64: &mut C64, monitor: &mut Monitor) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    let mut lines = io::stdin().lock().lines();
+
+    println!("monitor, x to continue");
+    let mut reply = monitor.execute(c64, "r");
+    loop {
+        match reply {
+            Reply::Lines(lines) => lines.iter().for_each(|line| println!("{line}")),
+            Reply::Resume => return Ok(()),
+        }
+
+        print!("> ");
+        stdout.flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        reply = monitor.execute(c64, &line);
+    }
+}
+
+// The default audio output, playing the samples queued for it
+struct Audio {
+    // Plays for as long as it is kept
+    _stream: cpal::Stream,
+    samples: Arc<Mutex<VecDeque<i16>>>,
+    sample_rate: u32,
+}
+
+impl Audio {
+    fn open() -> Result<Self, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("there is no audio output")?;
+        let supported = device
+            .default_output_config()
+            .map_err(|err| err.to_string())?;
+
+        let samples = Arc::new(Mutex::new(VecDeque::new()));
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => stream::<f32>(&device, &supported.config(), &samples),
+            SampleFormat::I16 => stream::<i16>(&device, &supported.config(), &samples),
+            SampleFormat::U16 => stream::<u16>(&device, &supported.config(), &samples),
+            format => return Err(format!("unsupported sample format {format}")),
+        }?;
+        stream.play().map_err(|err| err.to_string())?;
+
+        Ok(Audio {
+            _stream: stream,
+            samples,
+            sample_rate: supported.sample_rate(),
+        })
+    }
+
+    fn queue(&self, samples: &[i16]) {
+        let mut queued = self.samples.lock().unwrap();
+        queued.extend(samples);
+
+        let max = (self.sample_rate as f64 * MAX_AUDIO_DELAY) as usize;
+        let excess = queued.len().saturating_sub(max);
+        queued.drain(..excess);
+    }
+}
+
+// A stream playing the samples on all channels, and silence when there are
+// none
+fn stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    samples: &Arc<Mutex<VecDeque<i16>>>,
+) -> Result<cpal::Stream, String>
+where
+    T: SizedSample + FromSample<i16>,
+{
+    let channels = config.channels as usize;
+    let samples = Arc::clone(samples);
+
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut samples = samples.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let sample = T::from_sample(samples.pop_front().unwrap_or(0));
+                    frame.fill(sample);
+                }
+            },
+            |err| eprintln!("audio: {err}"),
+            None,
+        )
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use c64::vicII::palette::{Colors, PEPTO};
+
+    #[test]
+    fn should_scale_frame() {
+        let palette = Palette::default();
+        let frame = [Colors::black as u8, Colors::white as u8];
+        let mut buffer = vec![0; 2 * 2 * 2];
+
+        scale_frame(&frame, 2, 2, &palette, &mut buffer);
+
+        let white = PEPTO[Colors::white];
+        let white = (white.r as u32) << 16 | (white.g as u32) << 8 | white.b as u32;
+        assert_eq!(buffer, [0, 0, white, white, 0, 0, white, white]);
+    }
+
+    #[test]
+    fn should_name_host_keys_by_position() {
+        assert_eq!(position_of(minifb::Key::Key7), Some("Digit7"));
+        assert_eq!(position_of(minifb::Key::Q), Some("KeyQ"));
+        assert_eq!(position_of(minifb::Key::LeftBracket), Some("BracketLeft"));
+        assert_eq!(position_of(minifb::Key::Left), Some("ArrowLeft"));
+        assert_eq!(position_of(minifb::Key::F9), None);
+    }
+}
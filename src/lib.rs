@@ -0,0 +1,41 @@
+//! A Commodore 64 emulator, as a library for tools and frontends to build on.
+//!
+//! [`C64`] is the whole machine, a [`cpu::Cpu`] connected to a [`bus::Bus`]
+//! with the memory, the ROMs, the VIC-II, the SID, the CIAs, the keyboard and
+//! the joysticks. It runs an instruction at a time with `step`, or a frame at
+//! a time with `run_frame`, with every chip clocked on every cycle.
+//!
+//! Around it are the tools for getting code in and looking at what it does:
+//!
+//! - [`Block`] is code or data at an address, with a small assembler, a
+//!   disassembler, memory dumps and PRG files
+//! - [`disassembler`] follows the flow of the code to tell it from data
+//! - [`basic`] tokenises and lists BASIC programs
+//! - [`d64`] reads and adds the files of disk images
+//! - [`autostart`] boots the machine and starts a program
+//! - [`screenshot`], [`recording`] and [`golden`] capture what it shows
+//! - [`monitor`] is a machine language monitor for any frontend
+//!
+//! The library doesn't need a terminal, a window or a sound card, so it
+//! builds for wasm32-unknown-unknown as well, where the `web` feature adds
+//! the API for JavaScript in `web`.
+//!
+//! ```
+//! use c64::{Block, C64};
+//!
+//! let program = Block::assemble("*= $c000\nLDA #$02\nSTA $D020\nJMP $C005");
+//! assert_eq!(program.disassemble()[1], "C002   8D 20 D0   STA $D020");
+//!
+//! let mut c64 = C64::new();
+//! c64.load(&program);
+//! c64.cpu_mut().jump(0xc000);
+//! c64.step();
+//! c64.step();
+//! assert_eq!(c64.peek(0xd020) & 0x0f, 0x02);
+//! ```
+
+mod c64;
+#[cfg(feature = "web")]
+pub mod web;
+
+pub use crate::c64::*;
@@ -1,37 +1,37 @@
-#![allow(dead_code, unused_variables)]
-mod c64;
-use crate::c64::Block;
+//! The command line, on top of the library, with the frontends that need more
+//! than the library does: a terminal, or a window and a sound card. Every
+//! command is in `commands`, this only picks the one to run and exits with
+//! the code of how it went.
+
+mod commands;
+#[cfg(feature = "desktop")]
+mod desktop;
+#[cfg(feature = "terminal")]
+mod terminal;
+
+use commands::CommandError;
 
-#[rustfmt::skip]
 fn main() {
-    let block = Block {
-        start: 0x0801,
-        instructions: vec![
-            0x78, 0x77, 0x78, 0x20, 0x02, 0x34
-        ],
-    };
-    show(block);
+    let args: Vec<String> = std::env::args().skip(1).collect();
 
-    let block = Block {
-        start: 0xFCE2,
-        instructions: vec![
-            0xa2, 0xff, 0x78, 0x9a, 0xd8, 0x20, 0x02, 0xfd,
-            0xd0, 0x03, 0x6c, 0x00, 0x80, 0x8e, 0x16, 0xd0,
-            0x20, 0xa3, 0xfd, 0x20, 0x50, 0xfd, 0x20, 0x15,
-            0xfd, 0x20, 0x5b, 0xff, 0x58, 0x6c, 0x00, 0xa0,
-        ],
+    let Some((command, args)) = args.split_first() else {
+        eprintln!("{}", commands::usage());
+        std::process::exit(2);
     };
-    show(block);
-}
-
-fn show(block: Block) {
-    show_vec(&block.memory());
-    show_vec(&block.disassemble());
-}
+    if matches!(command.as_str(), "help" | "--help" | "-h") {
+        println!("{}", commands::usage());
+        return;
+    }
 
-fn show_vec(vec: &[String]) {
-    for line in vec.iter() {
-        println!("{}", line);
+    let Some(result) = commands::run(command, args) else {
+        eprintln!("there is no command {command}\n{}", commands::usage());
+        std::process::exit(2);
+    };
+    if let Err(err) = result {
+        match err {
+            CommandError::Usage { .. } => eprintln!("{err}"),
+            _ => eprintln!("error: {err}"),
+        }
+        std::process::exit(err.exit_code());
     }
-    println!();
 }
@@ -0,0 +1,276 @@
+//! A frontend for the terminal, showing the 40x25 text screen with a bit of
+//! border around it, in the colours of the palette as 24 bit ANSI colours. It
+//! needs nothing but a terminal that can show those and the Unicode block of
+//! the PETSCII graphics, so it works over SSH as well.
+//!
+//! Terminals only tell about keys being pressed, not released, so every key is
+//! typed by holding it down for a couple of frames, which is long enough for
+//! the KERNAL to see it, and releasing it for a frame before the next one. The
+//! keys the C64 doesn't have map to the closest ones:
+//!
+//! - cursor up and left   - SHIFT with cursor down and right
+//! - Home                 - CLR/HOME
+//! - Backspace and Delete - INST/DEL, Insert is SHIFT with INST/DEL
+//! - Escape               - RUN/STOP
+//! - Page Up              - RESTORE
+//! - F1-F8                - F1-F7, the even ones with SHIFT
+//! - Ctrl+C               - quits
+//!
+//! Only the characters that changed since the last frame are drawn, to keep
+//! the amount of output down over slow connections.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Print, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, queue, style, terminal};
+
+use c64::keyboard::Key;
+use c64::text_screen::{TextScreen, COLUMNS, ROWS};
+use c64::vicII::palette::Color;
+use c64::C64;
+
+// The border around the text screen, in characters
+const BORDER_COLUMNS: u16 = 2;
+const BORDER_ROWS: u16 = 1;
+
+// How many frames a key is held down, and released before the next one
+const HOLD_FRAMES: u32 = 2;
+const RELEASE_FRAMES: u32 = 1;
+
+// A key being typed, with the keys held down and the frames left
+struct Typing {
+    keys: Vec<Key>,
+    frames: u32,
+    released: bool,
+}
+
+/// Runs the machine in the terminal, until Ctrl+C is pressed
+pub fn run(c64: &mut C64) -> io::Result<()> {
+    let mut stdout = io::stdout();
+
+    terminal::enable_raw_mode()?;
+    crossterm::execute!(
+        stdout,
+        terminal::EnterAlternateScreen,
+        cursor::Hide,
+        terminal::Clear(terminal::ClearType::All)
+    )?;
+
+    let result = run_frames(c64, &mut stdout);
+
+    crossterm::execute!(
+        stdout,
+        style::ResetColor,
+        cursor::Show,
+        terminal::LeaveAlternateScreen
+    )?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn run_frames(c64: &mut C64, out: &mut impl Write) -> io::Result<()> {
+    let frame_time = Duration::from_secs_f64(1.0 / c64.model().frame_rate());
+    let mut next_frame = Instant::now();
+
+    let mut queue: VecDeque<Vec<Key>> = VecDeque::new();
+    let mut typing: Option<Typing> = None;
+    let mut shown: Option<TextScreen> = None;
+
+    loop {
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                }) => return Ok(()),
+                Event::Key(event) if event.kind != KeyEventKind::Release => {
+                    queue.extend(keys_for(event.code));
+                }
+                Event::Resize(..) => {
+                    queue!(out, terminal::Clear(terminal::ClearType::All))?;
+                    shown = None;
+                }
+                _ => {}
+            }
+        }
+
+        typing = type_keys(c64, typing, &mut queue);
+        c64.run_frame();
+
+        let screen = c64.text_screen();
+        draw(out, &screen, shown.as_ref())?;
+        shown = Some(screen);
+
+        next_frame += frame_time;
+        match next_frame.checked_duration_since(Instant::now()) {
+            Some(wait) => std::thread::sleep(wait),
+            // Too slow to keep up, so don't try to catch up either
+            None => next_frame = Instant::now(),
+        }
+    }
+}
+
+// Holds the keys of the current key down, releases them, and moves on to the
+// next one in the queue
+fn type_keys(
+    c64: &mut C64,
+    typing: Option<Typing>,
+    queue: &mut VecDeque<Vec<Key>>,
+) -> Option<Typing> {
+    // This frame is one of the frames the key is held or released for
+    let typing = typing.map(|typing| Typing {
+        frames: typing.frames.saturating_sub(1),
+        ..typing
+    });
+
+    match typing {
+        Some(Typing {
+            frames: 0,
+            released: false,
+            keys,
+        }) => {
+            keys.iter().for_each(|key| c64.release_key(*key));
+            Some(Typing {
+                keys,
+                frames: RELEASE_FRAMES,
+                released: true,
+            })
+        }
+        Some(typing) if typing.frames > 0 => Some(typing),
+        _ => queue.pop_front().map(|keys| {
+            keys.iter().for_each(|key| c64.press_key(*key));
+            Typing {
+                keys,
+                frames: HOLD_FRAMES,
+                released: false,
+            }
+        }),
+    }
+}
+
+// The keys of the C64 to hold down for a key of the terminal
+fn keys_for(code: KeyCode) -> Option<Vec<Key>> {
+    let shifted = |key| vec![Key::LeftShift, key];
+
+    let keys = match code {
+        KeyCode::Char(c) => match Key::for_char(c)? {
+            (key, true) => shifted(key),
+            (key, false) => vec![key],
+        },
+        KeyCode::Enter => vec![Key::Return],
+        KeyCode::Backspace | KeyCode::Delete => vec![Key::InstDel],
+        KeyCode::Insert => shifted(Key::InstDel),
+        KeyCode::Right => vec![Key::CursorRight],
+        KeyCode::Left => shifted(Key::CursorRight),
+        KeyCode::Down => vec![Key::CursorDown],
+        KeyCode::Up => shifted(Key::CursorDown),
+        KeyCode::Home => vec![Key::ClrHome],
+        KeyCode::Esc => vec![Key::RunStop],
+        KeyCode::PageUp => vec![Key::Restore],
+        KeyCode::F(number @ 1..=8) => {
+            let key = [Key::F1, Key::F3, Key::F5, Key::F7][(number as usize - 1) / 2];
+            match number % 2 {
+                0 => shifted(key),
+                _ => vec![key],
+            }
+        }
+        _ => return None,
+    };
+    Some(keys)
+}
+
+// Draws the characters that differ from what is shown, or everything when
+// nothing is shown yet
+fn draw(out: &mut impl Write, screen: &TextScreen, shown: Option<&TextScreen>) -> io::Result<()> {
+    let border_changed = shown.is_none_or(|shown| shown.border != screen.border);
+
+    if border_changed {
+        let width = COLUMNS as u16 + BORDER_COLUMNS * 2;
+        let height = ROWS as u16 + BORDER_ROWS * 2;
+        queue!(out, SetBackgroundColor(rgb(screen.border)))?;
+        for y in 0..height {
+            for x in 0..width {
+                let inside = (BORDER_COLUMNS..width - BORDER_COLUMNS).contains(&x)
+                    && (BORDER_ROWS..height - BORDER_ROWS).contains(&y);
+                if !inside {
+                    queue!(out, cursor::MoveTo(x, y), Print(' '))?;
+                }
+            }
+        }
+    }
+
+    // Where the cursor is and which colours are set, to leave out what
+    // doesn't change from one character to the next
+    let mut position = None;
+    let mut colors = None;
+
+    for (index, cell) in screen.cells.iter().enumerate() {
+        if shown.is_some_and(|shown| shown.cells[index] == *cell) {
+            continue;
+        }
+
+        let x = (index % COLUMNS) as u16 + BORDER_COLUMNS;
+        let y = (index / COLUMNS) as u16 + BORDER_ROWS;
+        if position != Some((x, y)) {
+            queue!(out, cursor::MoveTo(x, y))?;
+        }
+        if colors != Some((cell.foreground, cell.background)) {
+            queue!(
+                out,
+                SetForegroundColor(rgb(cell.foreground)),
+                SetBackgroundColor(rgb(cell.background))
+            )?;
+            colors = Some((cell.foreground, cell.background));
+        }
+        queue!(out, Print(cell.character))?;
+        position = Some((x + 1, y));
+    }
+    out.flush()
+}
+
+fn rgb(Color { r, g, b }: Color) -> style::Color {
+    style::Color::Rgb { r, g, b }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_map_terminal_keys() {
+        assert_eq!(keys_for(KeyCode::Char('a')), Some(vec![Key::A]));
+        assert_eq!(
+            keys_for(KeyCode::Char('"')),
+            Some(vec![Key::LeftShift, Key::Num2])
+        );
+        assert_eq!(
+            keys_for(KeyCode::Up),
+            Some(vec![Key::LeftShift, Key::CursorDown])
+        );
+        assert_eq!(keys_for(KeyCode::F(4)), Some(vec![Key::LeftShift, Key::F3]));
+        assert_eq!(keys_for(KeyCode::F(7)), Some(vec![Key::F7]));
+        assert_eq!(keys_for(KeyCode::Tab), None);
+    }
+
+    #[test]
+    fn should_hold_and_release_typed_keys() {
+        let mut c64 = C64::new();
+        let mut queue = VecDeque::from([vec![Key::A], vec![Key::B]]);
+
+        let mut held = vec![];
+        let mut typing = None;
+        for _ in 0..8 {
+            typing = type_keys(&mut c64, typing, &mut queue);
+            let keyboard = c64.keyboard();
+            held.push((keyboard.is_pressed(Key::A), keyboard.is_pressed(Key::B)));
+        }
+
+        let (a, b) = ((true, false), (false, true));
+        let none = (false, false);
+        assert_eq!(held, [a, a, none, b, b, none, none, none]);
+    }
+}
@@ -0,0 +1,225 @@
+//! The API for running the emulator in a browser, through wasm-bindgen. The
+//! page does the rest: it calls `run_frame` at the frame rate, draws the
+//! framebuffer on a canvas, plays the samples and passes on the keys, see
+//! web/index.html.
+//!
+//! Building it needs the wasm32-unknown-unknown target and wasm-bindgen-cli:
+//!
+//! ```text
+//! cargo build --lib --release --target wasm32-unknown-unknown \
+//!     --no-default-features --features web
+//! wasm-bindgen --target web --out-dir web/pkg \
+//!     target/wasm32-unknown-unknown/release/c64.wasm
+//! ```
+//!
+//! The keys map to the C64 keyboard by where they are, using the `code` of
+//! the keyboard events, see `Key::for_position`.
+
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::c64::autostart::{AutostartError, Boot, Start};
+use crate::c64::d64::{FileType, D64};
+use crate::c64::joystick::{Port, Switch};
+use crate::c64::keyboard::Key;
+use crate::c64::roms::Roms;
+use crate::c64::C64;
+
+#[wasm_bindgen]
+pub struct Emulator {
+    c64: C64,
+    // The keys of the C64 held down for every key of the browser held down
+    held: HashMap<String, Vec<Key>>,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Emulator {
+        Emulator {
+            c64: C64::new(),
+            held: HashMap::new(),
+        }
+    }
+
+    /// Sets the ROMs, as read by the page, where any of them can be left out
+    pub fn set_roms(
+        &mut self,
+        basic: Option<Vec<u8>>,
+        kernal: Option<Vec<u8>>,
+        chargen: Option<Vec<u8>>,
+    ) -> Result<(), JsError> {
+        for (name, rom, size) in [
+            ("BASIC", &basic, 0x2000),
+            ("KERNAL", &kernal, 0x2000),
+            ("character", &chargen, 0x1000),
+        ] {
+            if let Some(rom) = rom.as_ref().filter(|rom| rom.len() != size) {
+                let found = rom.len();
+                let message =
+                    format!("the {name} ROM should be {size} bytes, but is {found} bytes");
+                return Err(JsError::new(&message));
+            }
+        }
+
+        self.c64.set_roms(Roms {
+            basic,
+            kernal,
+            chargen,
+        });
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.c64.reset();
+    }
+
+    /// Loads and starts the PRG, with RUN when there are a KERNAL and BASIC to
+    /// boot, or else the SYS line at the start of it
+    pub fn load_prg(&mut self, bytes: &[u8]) -> Result<(), JsError> {
+        match self.c64.autostart(bytes, Boot::Kernal, Start::Run) {
+            Err(AutostartError::MissingKernal | AutostartError::MissingBasic) => {
+                self.c64.autostart(bytes, Boot::Fast, Start::Sys)?
+            }
+            started => started?,
+        }
+        Ok(())
+    }
+
+    /// Loads and starts the first PRG on the disk, like `LOAD "*",8,1`
+    pub fn load_d64(&mut self, bytes: Vec<u8>) -> Result<(), JsError> {
+        let d64 = D64::from_bytes(bytes)?;
+        let entry = d64
+            .directory()?
+            .into_iter()
+            .find(|entry| entry.file_type == FileType::Prg)
+            .ok_or_else(|| JsError::new("there is no PRG on the disk"))?;
+
+        self.load_prg(&d64.read(&entry)?)
+    }
+
+    /// Runs until the VIC-II has completed the frame
+    pub fn run_frame(&mut self) {
+        self.c64.run_frame();
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        self.c64.model().frame_rate()
+    }
+
+    /// The width of the visible area of the frame
+    pub fn width(&self) -> usize {
+        self.c64.vic().timing().visible.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.c64.vic().timing().visible.height
+    }
+
+    /// The visible area of the frame, as RGBA for an ImageData
+    pub fn framebuffer(&self) -> Vec<u8> {
+        let vic = self.c64.vic();
+        let palette = vic.palette();
+
+        vic.visible_frame()
+            .iter()
+            .flat_map(|index| {
+                let color = palette[*index];
+                [color.r, color.g, color.b, 0xff]
+            })
+            .collect()
+    }
+
+    /// Starts producing audio at the sample rate of the AudioContext
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.c64.set_sample_rate(Some(sample_rate));
+    }
+
+    /// The samples produced since the last time they were taken, from -1 to
+    /// 1 as the Web Audio API has them
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.c64
+            .take_samples()
+            .iter()
+            .map(|sample| *sample as f32 / 32768.0)
+            .collect()
+    }
+
+    /// Holds down the keys for the `code` of a keydown event, returning
+    /// whether it is a key of the C64 at all
+    pub fn key_down(&mut self, code: &str) -> bool {
+        let Some(keys) = Key::for_position(code) else {
+            return false;
+        };
+        self.held.insert(code.to_string(), keys);
+        self.press_held();
+        true
+    }
+
+    pub fn key_up(&mut self, code: &str) -> bool {
+        let released = self.held.remove(code).is_some();
+        self.press_held();
+        released
+    }
+
+    /// Closes or opens a switch of the joystick in port 1 or 2, where the
+    /// switch is one of up, down, left, right and fire
+    pub fn joystick(&mut self, port: u8, switch: &str, pressed: bool) -> Result<(), JsError> {
+        let port = match port {
+            1 => Port::One,
+            2 => Port::Two,
+            _ => return Err(JsError::new(&format!("there is no port {port}"))),
+        };
+        let switch = match switch {
+            "up" => Switch::Up,
+            "down" => Switch::Down,
+            "left" => Switch::Left,
+            "right" => Switch::Right,
+            "fire" => Switch::Fire,
+            _ => return Err(JsError::new(&format!("there is no switch {switch}"))),
+        };
+
+        match pressed {
+            true => self.c64.press_joystick(port, switch),
+            false => self.c64.release_joystick(port, switch),
+        }
+        Ok(())
+    }
+
+    fn press_held(&mut self) {
+        self.c64.hold_keys(self.held.values().flatten().copied());
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_shift_held_down() {
+        let mut emulator = Emulator::new();
+        assert!(emulator.key_down("ShiftLeft"));
+        assert!(emulator.key_down("ArrowLeft"));
+        assert!(emulator.key_up("ArrowLeft"));
+
+        let keyboard = emulator.c64.keyboard();
+        assert!(keyboard.is_pressed(Key::LeftShift));
+        assert!(!keyboard.is_pressed(Key::CursorRight));
+        assert!(!emulator.key_down("F12"));
+    }
+
+    #[test]
+    fn should_give_rgba_of_visible_area() {
+        let emulator = Emulator::new();
+        let framebuffer = emulator.framebuffer();
+        assert_eq!(framebuffer.len(), emulator.width() * emulator.height() * 4);
+        assert!(framebuffer.chunks_exact(4).all(|pixel| pixel[3] == 0xff));
+    }
+}